@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use mparse::Location;
+
+use crate::metasyn::{
+    line_col, Alternative, Builtin, Item, OutArg, Program, Rule, Sequence, Span, Test,
+};
+use crate::MInstr;
+
+/// Rules called and branches taken by `M::execute`, summed over runs.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Coverage {
+    /// Calls of each rule, keyed by the ic of the rule entry.
+    pub calls: HashMap<usize, u64>,
+    /// Times each `BT` and `BF` fell through and branched.
+    pub branches: HashMap<usize, [u64; 2]>,
+}
+
+impl Coverage {
+    pub fn call(&mut self, entry: usize) {
+        *self.calls.entry(entry).or_default() += 1;
+    }
+
+    pub fn branch(&mut self, ic: usize, branched: bool) {
+        self.branches.entry(ic).or_default()[branched as usize] += 1;
+    }
+
+    /// Adds the counts of another run.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (entry, calls) in other.calls.iter() {
+            *self.calls.entry(*entry).or_default() += calls;
+        }
+        for (ic, counts) in other.branches.iter() {
+            let sum = self.branches.entry(*ic).or_default();
+            sum[0] += counts[0];
+            sum[1] += counts[1];
+        }
+    }
+}
+
+/// The code of a program does not have the shape the grammar compiles to.
+#[derive(Debug, PartialEq)]
+pub struct CoverageError {
+    pub at: Location,
+}
+
+impl fmt::Display for CoverageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "program differs from the grammar at {}", self.at)
+    }
+}
+
+impl Error for CoverageError {}
+
+/// An alternative or a `$` repeat, and how often it was taken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub span: Span,
+    /// Alternatives starting with output are only known to be taken when
+    /// the one before them was not.
+    pub taken: Option<u64>,
+    pub repeat: bool,
+    text: String,
+}
+
+impl Branch {
+    fn what(&self) -> &'static str {
+        match self.repeat {
+            true => "never repeated",
+            false => "never taken",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RuleCoverage<'g> {
+    rule: &'g Rule,
+    calls: u64,
+    branches: Vec<Branch>,
+}
+
+/// `Coverage` of a program mapped back onto the grammar it was compiled
+/// from by `meta.mm`.
+#[derive(Debug)]
+pub struct GrammarCoverage<'g> {
+    rules: Vec<RuleCoverage<'g>>,
+}
+
+impl<'g> GrammarCoverage<'g> {
+    pub fn new(
+        grammar: &'g Program,
+        pgm: &mparse::MProgram<MInstr>,
+        coverage: &Coverage,
+    ) -> Result<Self, CoverageError> {
+        let mut walk = Walk {
+            pgm,
+            coverage,
+            ic: 0,
+            branches: Vec::new(),
+        };
+        walk.expect(|instr| matches!(instr, MInstr::ADR(..)))?;
+        let mut rules = Vec::new();
+        for rule in grammar.rules.iter() {
+            let entry = walk.ic;
+            let labelled = pgm
+                .labels
+                .get(&rule.name.text)
+                .and_then(|addr| pgm.ic.get(addr));
+            if labelled != Some(&entry) {
+                return Err(walk.error());
+            }
+            for lit in rule.recover.iter() {
+                walk.expect(|instr| matches!(instr, MInstr::SYN(s) if *s == lit.text))?;
+            }
+            walk.alternative(&rule.body, coverage.calls.get(&entry).copied())?;
+            walk.expect(|instr| matches!(instr, MInstr::R))?;
+            rules.push(RuleCoverage {
+                rule,
+                calls: coverage.calls.get(&entry).copied().unwrap_or(0),
+                branches: std::mem::take(&mut walk.branches),
+            });
+        }
+        if walk.ic != pgm.instrs.len() {
+            return Err(walk.error());
+        }
+        Ok(GrammarCoverage { rules })
+    }
+
+    /// Branches never taken, leaving out those inside rules never called
+    /// and inside other branches never taken.
+    pub fn uncovered(&self) -> Vec<(&str, &Branch)> {
+        let mut uncovered: Vec<(&str, &Branch)> = Vec::new();
+        for rc in self.rules.iter().filter(|rc| rc.calls > 0) {
+            for branch in rc.branches.iter().filter(|b| b.taken == Some(0)) {
+                let inside = |(_, b): &(&str, &Branch)| {
+                    b.span.start <= branch.span.start && branch.span.end <= b.span.end
+                };
+                if !uncovered.iter().any(inside) {
+                    uncovered.push((&rc.rule.name.text, branch));
+                }
+            }
+        }
+        uncovered
+    }
+
+    /// Writes calls and branches taken for each rule, then the branches
+    /// never taken.
+    pub fn report(&self, src: &str, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{:<16} {:>8} {:>10}", "rule", "calls", "branches")?;
+        let (mut taken, mut known) = (0, 0);
+        for rc in self.rules.iter() {
+            let counted: Vec<u64> = rc.branches.iter().filter_map(|b| b.taken).collect();
+            let covered = counted.iter().filter(|n| **n > 0).count();
+            taken += covered;
+            known += counted.len();
+            writeln!(
+                out,
+                "{:<16} {:>8} {:>10}",
+                rc.rule.name.text,
+                rc.calls,
+                format!("{covered}/{}", counted.len())
+            )?;
+        }
+        writeln!(
+            out,
+            "{:<16} {:>8} {:>10}",
+            "total",
+            "",
+            format!("{taken}/{known}")
+        )?;
+        for rc in self.rules.iter().filter(|rc| rc.calls == 0) {
+            let (line, _) = line_col(src, rc.rule.name.span.start);
+            writeln!(out, "line {line}: {} never called", rc.rule.name.text)?;
+        }
+        for (rule, branch) in self.uncovered() {
+            let (line, _) = line_col(src, branch.span.start);
+            writeln!(
+                out,
+                "line {line}: {rule}: {} {}",
+                branch.text,
+                branch.what()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Lists the grammar source with the rules never called and the
+    /// branches never taken underlined.
+    pub fn annotate(&self, src: &str) -> String {
+        let mut marks: Vec<(Span, &str)> = self
+            .rules
+            .iter()
+            .filter(|rc| rc.calls == 0)
+            .map(|rc| (rc.rule.name.span.clone(), "never called"))
+            .collect();
+        for (_, branch) in self.uncovered() {
+            marks.push((branch.span.clone(), branch.what()));
+        }
+        marks.sort_by_key(|(span, _)| span.start);
+        let mut out = String::new();
+        let mut start = 0;
+        for (n, line) in src.split_inclusive('\n').enumerate() {
+            let end = start + line.len();
+            out.push_str(&format!("{:>5} | {}", n + 1, line));
+            if !line.ends_with('\n') {
+                out.push('\n');
+            }
+            let mut underline: Vec<char> = Vec::new();
+            let mut notes = Vec::new();
+            for (span, what) in marks.iter() {
+                let (from, to) = (span.start.max(start), span.end.min(end));
+                if from >= to || line[from - start..to - start].trim().is_empty() {
+                    continue;
+                }
+                let from = to - line[from - start..to - start].trim_start().len();
+                let col = line[..from - start].chars().count();
+                let len = line[from - start..to - start].trim_end().chars().count();
+                if underline.len() < col + len {
+                    underline.resize(col + len, ' ');
+                }
+                underline[col..col + len].fill('^');
+                if span.start >= start {
+                    notes.push(*what);
+                }
+            }
+            if !underline.is_empty() {
+                out.push_str("      | ");
+                out.extend(underline);
+                if !notes.is_empty() {
+                    out.push(' ');
+                    out.push_str(&notes.join(", "));
+                }
+                out.push('\n');
+            }
+            start = end;
+        }
+        out
+    }
+}
+
+// goes through the code `meta.mm` compiles the grammar to
+struct Walk<'p> {
+    pgm: &'p mparse::MProgram<MInstr>,
+    coverage: &'p Coverage,
+    ic: usize,
+    branches: Vec<Branch>,
+}
+
+impl Walk<'_> {
+    fn error(&self) -> CoverageError {
+        CoverageError {
+            at: self.pgm.location(self.ic),
+        }
+    }
+
+    fn expect(&mut self, is: impl Fn(&MInstr) -> bool) -> Result<(), CoverageError> {
+        match self.pgm.instrs.get(self.ic) {
+            Some(instr) if is(instr) => {
+                self.ic += 1;
+                Ok(())
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    // fell through and branched counts of the branch at ic
+    fn counts(&self, ic: usize) -> [u64; 2] {
+        self.coverage.branches.get(&ic).copied().unwrap_or_default()
+    }
+
+    fn alternative(
+        &mut self,
+        alt: &Alternative,
+        reached: Option<u64>,
+    ) -> Result<(), CoverageError> {
+        for (i, seq) in alt.seqs.iter().enumerate() {
+            let mut reached = reached;
+            if i > 0 {
+                reached = Some(self.counts(self.ic)[0]);
+                self.expect(|instr| matches!(instr, MInstr::BT(..)))?;
+            }
+            self.sequence(seq, reached)?;
+        }
+        Ok(())
+    }
+
+    fn sequence(&mut self, seq: &Sequence, reached: Option<u64>) -> Result<(), CoverageError> {
+        // before the branches inside it
+        let at = self.branches.len();
+        self.branches.push(Branch {
+            span: seq.span.clone(),
+            taken: reached,
+            repeat: false,
+            text: seq.to_string(),
+        });
+        for (i, item) in seq.items.iter().enumerate() {
+            match item {
+                Item::Test(test) => {
+                    self.test(test)?;
+                    if i == 0 {
+                        self.branches[at].taken = Some(self.counts(self.ic)[0]);
+                        self.expect(|instr| matches!(instr, MInstr::BF(..)))?;
+                    } else {
+                        self.expect(|instr| matches!(instr, MInstr::BE))?;
+                    }
+                }
+                Item::Output(output) => {
+                    for arg in output.args.iter() {
+                        self.out_arg(arg)?;
+                    }
+                    self.expect(|instr| matches!(instr, MInstr::OUT))?;
+                }
+                Item::Label(label) => {
+                    self.expect(|instr| matches!(instr, MInstr::LB))?;
+                    self.out_arg(&label.arg)?;
+                    self.expect(|instr| matches!(instr, MInstr::OUT))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn test(&mut self, test: &Test) -> Result<(), CoverageError> {
+        match test {
+            Test::Call(name) => {
+                self.expect(|instr| matches!(instr, MInstr::CLL(n, _) if *n == name.text))
+            }
+            Test::Literal(lit) => {
+                self.expect(|instr| matches!(instr, MInstr::TST(s) if *s == lit.text))
+            }
+            Test::Builtin(Builtin::Id, _) => self.expect(|instr| matches!(instr, MInstr::ID)),
+            Test::Builtin(Builtin::Number, _) => self.expect(|instr| matches!(instr, MInstr::NUM)),
+            Test::Builtin(Builtin::String, _) => self.expect(|instr| matches!(instr, MInstr::SR)),
+            Test::Builtin(Builtin::Empty, _) => self.expect(|instr| matches!(instr, MInstr::SET)),
+            Test::Group(group) => self.alternative(&group.body, None),
+            Test::Repeat(repeat) => {
+                let at = self.branches.len();
+                self.branches.push(Branch {
+                    span: repeat.span.clone(),
+                    taken: None,
+                    repeat: true,
+                    text: test.to_string(),
+                });
+                self.test(&repeat.body)?;
+                self.branches[at].taken = Some(self.counts(self.ic)[1]);
+                self.expect(|instr| matches!(instr, MInstr::BT(..)))?;
+                self.expect(|instr| matches!(instr, MInstr::SET))
+            }
+        }
+    }
+
+    fn out_arg(&mut self, arg: &OutArg) -> Result<(), CoverageError> {
+        match arg {
+            OutArg::Gn1(_) => self.expect(|instr| matches!(instr, MInstr::GN1)),
+            OutArg::Gn2(_) => self.expect(|instr| matches!(instr, MInstr::GN2)),
+            OutArg::Input(_) => self.expect(|instr| matches!(instr, MInstr::CI)),
+            OutArg::Literal(lit) => {
+                self.expect(|instr| matches!(instr, MInstr::CL(s) if *s == lit.text))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metasyn, M};
+
+    fn compile(grammar: &str) -> mparse::MProgram<MInstr> {
+        let meta_mm = mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/meta.mm"))
+            .expect("meta machine program");
+        let mut m = M::new(grammar);
+        m.execute(&meta_mm);
+        mparse::parse::<MInstr>(&m.generated().expect("compilation")).expect("program")
+    }
+
+    fn covered(pgm: &mparse::MProgram<MInstr>, inputs: &[&str]) -> Coverage {
+        let mut coverage = Coverage::default();
+        for input in inputs {
+            let mut m = M::new(input);
+            m.enable_coverage();
+            m.execute(pgm);
+            coverage.merge(&m.take_coverage().expect("coverage"));
+        }
+        coverage
+    }
+
+    #[test]
+    fn counts() {
+        let src = ".SYNTAX S\nS = 'A' $'B' / 'C' T ;\nT = 'D' ;\nU = 'E' ;\n.END\n";
+        let pgm = compile(src);
+        let coverage = covered(&pgm, &["A", "A B B", "C D"]);
+        let entry = |name: &str| pgm.ic[&pgm.labels[name]];
+        assert_eq!(coverage.calls[&entry("S")], 3);
+        assert_eq!(coverage.calls[&entry("T")], 1);
+        assert_eq!(coverage.calls.get(&entry("U")), None);
+
+        let grammar = metasyn::parse(src).expect("grammar");
+        let gc = GrammarCoverage::new(&grammar, &pgm, &coverage).expect("coverage");
+        let s = &gc.rules[0];
+        let taken: Vec<(&str, Option<u64>)> = s
+            .branches
+            .iter()
+            .map(|b| (b.text.as_str(), b.taken))
+            .collect();
+        assert_eq!(
+            taken,
+            vec![("'A' $'B'", Some(2)), ("$'B'", Some(2)), ("'C' T", Some(1))]
+        );
+        assert!(gc.uncovered().is_empty());
+
+        let coverage = covered(&pgm, &["A"]);
+        let gc = GrammarCoverage::new(&grammar, &pgm, &coverage).expect("coverage");
+        let mut report = Vec::new();
+        gc.report(src, &mut report).expect("report");
+        assert_eq!(
+            String::from_utf8_lossy(&report),
+            "rule                calls   branches\n\
+             S                       1        1/3\n\
+             T                       0        0/1\n\
+             U                       0        0/1\n\
+             total                            1/5\n\
+             line 3: T never called\n\
+             line 4: U never called\n\
+             line 2: S: $'B' never repeated\n\
+             line 2: S: 'C' T never taken\n"
+        );
+        assert_eq!(
+            gc.annotate(src),
+            "    1 | .SYNTAX S\n\
+             \x20   2 | S = 'A' $'B' / 'C' T ;\n\
+             \x20     |         ^^^^   ^^^^^ never repeated, never taken\n\
+             \x20   3 | T = 'D' ;\n\
+             \x20     | ^ never called\n\
+             \x20   4 | U = 'E' ;\n\
+             \x20     | ^ never called\n\
+             \x20   5 | .END\n"
+        );
+    }
+
+    #[test]
+    fn va1_corpus() {
+        let src = include_str!("../../va1.syn");
+        let grammar = metasyn::parse(src).expect("grammar");
+        let pgm =
+            mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/va1.mm")).expect("program");
+        let coverage = covered(&pgm, &[include_str!("../../valgol1m/fig3.va1")]);
+        let gc = GrammarCoverage::new(&grammar, &pgm, &coverage).expect("coverage");
+        let uncovered: Vec<&str> = gc
+            .uncovered()
+            .iter()
+            .map(|(_, b)| b.text.as_str())
+            .collect();
+        assert!(uncovered.contains(&"'.NOT' PRIMARY .OUT('NOT')"));
+        assert!(!uncovered.iter().any(|text| text.starts_with("'.UNTIL'")));
+        assert!(gc.annotate(src).contains("never taken"));
+    }
+
+    #[test]
+    fn mismatch() {
+        let grammar = metasyn::parse(".SYNTAX S S = 'A' / 'B' ; .END").expect("grammar");
+        let other = compile(".SYNTAX S S = 'A' / 'C' ; .END");
+        let err =
+            GrammarCoverage::new(&grammar, &other, &Coverage::default()).expect_err("mismatch");
+        assert_eq!(
+            err.to_string(),
+            "program differs from the grammar at ic 4 (A001+1)"
+        );
+        let optimized = crate::optimize(&compile(include_str!("../../meta.syn")));
+        let grammar = metasyn::parse(include_str!("../../meta.syn")).expect("grammar");
+        assert!(GrammarCoverage::new(&grammar, &optimized, &Coverage::default()).is_err());
+    }
+}
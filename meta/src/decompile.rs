@@ -0,0 +1,431 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+use mparse::{Location, MProgram};
+
+use crate::metasyn::{Alternative, Builtin, Group, Item, Label, Literal, Name, OutArg, Output};
+use crate::metasyn::{Program, Repeat, Rule, Sequence, Test};
+use crate::MInstr;
+
+/// Code that does not have a shape `meta.syn` generates, located at the
+/// furthest instruction the decompiler got to.
+#[derive(Debug, PartialEq)]
+pub struct DecompileError {
+    pub at: Location,
+}
+
+impl fmt::Display for DecompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unrecognized code at {}", self.at)
+    }
+}
+
+impl Error for DecompileError {}
+
+/// Reconstructs the grammar a meta machine program was compiled from by
+/// `meta.syn`. Compiling the result gives back the same program, up to
+/// the order of labels at the same address. The spans of the returned
+/// grammar are empty.
+pub fn decompile(pgm: &MProgram<MInstr>) -> Result<Program, DecompileError> {
+    let d = Decompiler::new(pgm);
+    let error = || DecompileError {
+        at: pgm.location(d.furthest.get()),
+    };
+    let Some((MInstr::ADR(start, _), mut p)) = d.next(Pos { ic: 0, used: 0 }) else {
+        return Err(error());
+    };
+    let mut rules = Vec::new();
+    while p.ic < pgm.instrs.len() || p.used < d.labels[p.ic].len() {
+        let Some((rule, q)) = d.rule(p).into_iter().next() else {
+            return Err(error());
+        };
+        rules.push(rule);
+        p = q;
+    }
+    Ok(Program {
+        name: name(start),
+        rules,
+        span: 0..0,
+    })
+}
+
+/// A place in the code: an instruction counter and how many of the labels
+/// before that instruction have been accounted for.
+#[derive(Debug, Clone, Copy)]
+struct Pos {
+    ic: usize,
+    used: usize,
+}
+
+// Each parse returns all ways to read the code at a position, as inner
+// constructs can end at the same address as outer ones.
+type Parses<T> = Vec<(T, Pos)>;
+
+// parses by instruction counter, labels used and group nesting
+#[derive(Default)]
+struct Memo {
+    ex1: HashMap<(usize, usize, usize), Parses<Alternative>>,
+    ex2: HashMap<(usize, usize, usize), Parses<Sequence>>,
+    ex3: HashMap<(usize, usize, usize), Parses<Test>>,
+}
+
+struct Decompiler<'p> {
+    instrs: &'p [MInstr],
+    /// Labels before each instruction, and at the end.
+    labels: Vec<Vec<&'p str>>,
+    /// Labels from the instruction up to the end of its rule.
+    labels_in_rule: Vec<usize>,
+    /// Labels branched to, which cannot be rule names.
+    targets: HashSet<&'p str>,
+    furthest: Cell<usize>,
+    memo: RefCell<Memo>,
+}
+
+impl<'p> Decompiler<'p> {
+    fn new(pgm: &'p MProgram<MInstr>) -> Self {
+        let len = pgm.instrs.len();
+        let mut labels = vec![Vec::new(); len + 1];
+        for (label, addr) in pgm.labels.iter() {
+            labels[pgm.ic[addr]].push(label.as_str());
+        }
+        for here in labels.iter_mut() {
+            here.sort();
+        }
+        let mut labels_in_rule = vec![0; len + 1];
+        for ic in (0..=len).rev() {
+            labels_in_rule[ic] = labels[ic].len();
+            if !matches!(pgm.instrs.get(ic), Some(MInstr::R) | None) {
+                labels_in_rule[ic] += labels_in_rule[ic + 1];
+            }
+        }
+        let targets = pgm
+            .instrs
+            .iter()
+            .filter_map(|instr| match instr {
+                MInstr::BT(l, _) | MInstr::BF(l, _) | MInstr::B(l, _) => Some(l.as_str()),
+                _ => None,
+            })
+            .collect();
+        Decompiler {
+            instrs: &pgm.instrs,
+            labels,
+            labels_in_rule,
+            targets,
+            furthest: Cell::new(0),
+            memo: RefCell::default(),
+        }
+    }
+
+    /// The instruction at `p`, once all labels before it are accounted for.
+    fn next(&self, p: Pos) -> Option<(&'p MInstr, Pos)> {
+        if p.used < self.labels[p.ic].len() {
+            return None;
+        }
+        let instr = self.instrs.get(p.ic)?;
+        self.furthest.set(self.furthest.get().max(p.ic));
+        Some((
+            instr,
+            Pos {
+                ic: p.ic + 1,
+                used: 0,
+            },
+        ))
+    }
+
+    /// Accounts for one more label at `p`, which must be `name` if given.
+    fn label(&self, p: Pos, name: Option<&str>) -> Option<Pos> {
+        let here = &self.labels[p.ic];
+        if p.used == here.len() || name.is_some_and(|name| !here.contains(&name)) {
+            return None;
+        }
+        Some(Pos {
+            used: p.used + 1,
+            ..p
+        })
+    }
+
+    // NAME SYN* ex1 R
+    fn rule(&self, p: Pos) -> Parses<Rule> {
+        let names = self.labels[p.ic].iter();
+        let Some(rule_name) = names.copied().find(|l| !self.targets.contains(l)) else {
+            return Vec::new();
+        };
+        let Some(mut p) = self.label(p, Some(rule_name)).filter(|_| p.used == 0) else {
+            return Vec::new();
+        };
+        let mut recover = Vec::new();
+        while let Some((MInstr::SYN(s), q)) = self.next(p) {
+            recover.push(literal(s));
+            p = q;
+        }
+        let mut parses = Vec::new();
+        for (body, q) in self.ex1(p, 0) {
+            if let Some((MInstr::R, r)) = self.next(q) {
+                let rule = Rule {
+                    name: name(rule_name),
+                    recover: recover.clone(),
+                    body,
+                    span: 0..0,
+                };
+                parses.push((rule, r));
+            }
+        }
+        parses
+    }
+
+    fn ex1(&self, p: Pos, nest: usize) -> Parses<Alternative> {
+        let key = (p.ic, p.used, nest);
+        if let Some(parses) = self.memo.borrow().ex1.get(&key) {
+            return parses.clone();
+        }
+        let parses = self.parse_ex1(p, nest);
+        self.memo.borrow_mut().ex1.insert(key, parses.clone());
+        parses
+    }
+
+    fn ex2(&self, p: Pos, nest: usize) -> Parses<Sequence> {
+        let key = (p.ic, p.used, nest);
+        if let Some(parses) = self.memo.borrow().ex2.get(&key) {
+            return parses.clone();
+        }
+        let parses = self.parse_ex2(p, nest);
+        self.memo.borrow_mut().ex2.insert(key, parses.clone());
+        parses
+    }
+
+    fn ex3(&self, p: Pos, nest: usize) -> Parses<Test> {
+        let key = (p.ic, p.used, nest);
+        if let Some(parses) = self.memo.borrow().ex3.get(&key) {
+            return parses.clone();
+        }
+        let parses = self.parse_ex3(p, nest);
+        self.memo.borrow_mut().ex3.insert(key, parses.clone());
+        parses
+    }
+
+    // ex2 (BT L ex2)* L:
+    fn parse_ex1(&self, p: Pos, nest: usize) -> Parses<Alternative> {
+        let mut parses = Vec::new();
+        for (seq, q) in self.ex2(p, nest) {
+            self.ex1_rest(vec![seq], None, q, &mut parses);
+        }
+        parses
+    }
+
+    fn ex1_rest(
+        &self,
+        seqs: Vec<Sequence>,
+        end: Option<&str>,
+        p: Pos,
+        parses: &mut Parses<Alternative>,
+    ) {
+        if let Some(q) = self.label(p, end) {
+            let alt = Alternative {
+                seqs: seqs.clone(),
+                span: 0..0,
+            };
+            parses.push((alt, q));
+        }
+        if let Some((MInstr::BT(l, _), q)) = self.next(p) {
+            if end.map_or(true, |end| end == l) {
+                for (seq, r) in self.ex2(q, 0) {
+                    let mut seqs = seqs.clone();
+                    seqs.push(seq);
+                    self.ex1_rest(seqs, Some(l), r, parses);
+                }
+            }
+        }
+    }
+
+    // (ex3 BF L / output) (ex3 BE / output)* L:
+    fn parse_ex2(&self, p: Pos, nest: usize) -> Parses<Sequence> {
+        let mut parses = Vec::new();
+        for (test, q) in self.ex3(p, nest) {
+            if let Some((MInstr::BF(l, _), r)) = self.next(q) {
+                self.ex2_rest(vec![Item::Test(test)], Some(l), r, &mut parses);
+            }
+        }
+        for (output, q) in self.output(p) {
+            self.ex2_rest(vec![output], None, q, &mut parses);
+        }
+        parses
+    }
+
+    fn ex2_rest(&self, items: Vec<Item>, end: Option<&str>, p: Pos, parses: &mut Parses<Sequence>) {
+        if let Some(q) = self.label(p, end) {
+            let seq = Sequence {
+                items: items.clone(),
+                span: 0..0,
+            };
+            parses.push((seq, q));
+        }
+        for (test, q) in self.ex3(p, 0) {
+            if let Some((MInstr::BE, r)) = self.next(q) {
+                let mut items = items.clone();
+                items.push(Item::Test(test));
+                self.ex2_rest(items, end, r, parses);
+            }
+        }
+        for (output, q) in self.output(p) {
+            let mut items = items.clone();
+            items.push(output);
+            self.ex2_rest(items, end, q, parses);
+        }
+    }
+
+    // a single test instruction, ex1 in parentheses or L: ex3 BT L SET
+    fn parse_ex3(&self, p: Pos, nest: usize) -> Parses<Test> {
+        let mut parses = Vec::new();
+        if let Some((instr, q)) = self.next(p) {
+            let test = match instr {
+                MInstr::CLL(l, _) => Some(Test::Call(name(l))),
+                MInstr::TST(s) => Some(Test::Literal(literal(s))),
+                MInstr::ID => Some(Test::Builtin(Builtin::Id, 0..0)),
+                MInstr::NUM => Some(Test::Builtin(Builtin::Number, 0..0)),
+                MInstr::SR => Some(Test::Builtin(Builtin::String, 0..0)),
+                MInstr::SET => Some(Test::Builtin(Builtin::Empty, 0..0)),
+                _ => None,
+            };
+            parses.extend(test.map(|test| (test, q)));
+        }
+        for l in self.labels[p.ic].iter().skip(p.used) {
+            let Some(q) = self.label(p, Some(l)) else {
+                continue;
+            };
+            for (body, r) in self.ex3(q, 0) {
+                let Some((MInstr::BT(t, _), s)) = self.next(r) else {
+                    continue;
+                };
+                if t == l {
+                    if let Some((MInstr::SET, s)) = self.next(s) {
+                        let repeat = Repeat {
+                            body: Box::new(body),
+                            span: 0..0,
+                        };
+                        parses.push((Test::Repeat(repeat), s));
+                    }
+                }
+            }
+        }
+        // each group nesting at the same address ends with two labels
+        if 2 * (nest + 1) <= self.labels_in_rule[p.ic] - p.used {
+            for (body, q) in self.ex1(p, nest + 1) {
+                parses.push((Test::Group(Group { body, span: 0..0 }), q));
+            }
+        }
+        parses
+    }
+
+    // arg* OUT / LB arg OUT
+    fn output(&self, p: Pos) -> Parses<Item> {
+        let mut parses = Vec::new();
+        let mut args = Vec::new();
+        let mut q = p;
+        while let Some((instr, r)) = self.next(q) {
+            if let Some(arg) = out_arg(instr) {
+                args.push(arg);
+                q = r;
+                continue;
+            }
+            if let MInstr::OUT = instr {
+                let output = Output { args, span: 0..0 };
+                parses.push((Item::Output(output), r));
+            }
+            break;
+        }
+        if let Some((MInstr::LB, q)) = self.next(p) {
+            if let Some((arg, r)) = self.next(q).and_then(|(i, r)| Some((out_arg(i)?, r))) {
+                if let Some((MInstr::OUT, s)) = self.next(r) {
+                    let label = Label { arg, span: 0..0 };
+                    parses.push((Item::Label(label), s));
+                }
+            }
+        }
+        parses
+    }
+}
+
+fn out_arg(instr: &MInstr) -> Option<OutArg> {
+    match instr {
+        MInstr::GN1 => Some(OutArg::Gn1(0..0)),
+        MInstr::GN2 => Some(OutArg::Gn2(0..0)),
+        MInstr::CI => Some(OutArg::Input(0..0)),
+        MInstr::CL(s) => Some(OutArg::Literal(literal(s))),
+        _ => None,
+    }
+}
+
+fn name(text: &str) -> Name {
+    Name {
+        text: text.to_string(),
+        span: 0..0,
+    }
+}
+
+fn literal(text: &str) -> Literal {
+    Literal {
+        text: text.to_string(),
+        span: 0..0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(grammar: &str) -> String {
+        let meta_mm = mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/meta.mm"))
+            .expect("meta machine program");
+        let mut m = crate::M::new(grammar);
+        m.execute(&meta_mm);
+        m.generated().expect("compilation") + "\n"
+    }
+
+    #[test]
+    fn round_trip() {
+        for mm in [
+            include_str!("../../meta_mach_pgms/meta.mm0"),
+            include_str!("../../meta_mach_pgms/meta.mm"),
+            include_str!("../../meta_mach_pgms/va1.mm"),
+            include_str!("../../meta_mach_pgms/va2.mm"),
+        ] {
+            let p = mparse::parse::<MInstr>(mm).expect("meta machine program");
+            let grammar = decompile(&p).expect("decompilation").to_string();
+            assert_eq!(compile(&grammar), mm);
+        }
+    }
+
+    #[test]
+    fn shapes() {
+        // groups first in a sequence and nested at the same address
+        let grammar = "\
+.SYNTAX A
+
+A = ((B / 'X') $C) 'Y' .OUT('Z' *1) / .LABEL *2 $($'Q') ;
+
+B .RECOVER(';' '.END') = .OUT() ;
+
+C = .ID / .NUMBER .OUT(*) / .STRING .EMPTY ;
+
+.END
+";
+        let mm = compile(grammar);
+        let p = mparse::parse::<MInstr>(&mm).expect("meta machine program");
+        assert_eq!(decompile(&p).expect("decompilation").to_string(), grammar);
+    }
+
+    #[test]
+    fn unrecognized() {
+        let p = mparse::parse::<MInstr>(
+            "        ADR A\nA\n        TST 'X'\n        BF L\n        SET\nL\n        R\n        END\n",
+        )
+        .expect("meta machine program");
+        let err = decompile(&p).unwrap_err();
+        assert_eq!(err.to_string(), "unrecognized code at ic 3 (A+2)");
+        let meta_mm = mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/meta.mm"))
+            .expect("meta machine program");
+        assert!(decompile(&crate::optimize(&meta_mm)).is_err());
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::metasyn::{Alternative, Builtin, Item, Name, Program, Rule, Sequence, Test};
+
+/// Why a grammar cannot be used for generating sentences.
+#[derive(Debug, PartialEq)]
+pub enum GenerateError {
+    Undefined(Name),
+    /// A rule that cannot be expanded to a finite sentence.
+    Endless(Name),
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GenerateError::Undefined(name) => write!(f, "undefined rule {}", name.text),
+            GenerateError::Endless(name) => {
+                write!(f, "rule {} has no finite expansion", name.text)
+            }
+        }
+    }
+}
+
+impl Error for GenerateError {}
+
+/// Generates random sentences of a grammar. Alternatives are chosen by
+/// weight, 1 unless set with `weights`, until rules are nested deeper than
+/// `max_depth`; from there on the shortest way to finish is taken and
+/// repeats stop.
+pub struct Generator<'g> {
+    start: &'g Rule,
+    rules: HashMap<&'g str, &'g Rule>,
+    // the least nesting of calls each rule can be expanded with
+    heights: HashMap<&'g str, usize>,
+    weights: HashMap<String, Vec<u32>>,
+    // literals an identifier must not start with
+    keywords: Vec<&'g str>,
+    // identifiers to use instead of made up ones
+    ids: Vec<String>,
+    max_depth: usize,
+    max_repeat: usize,
+    rng: Rng,
+}
+
+impl<'g> Generator<'g> {
+    pub fn new(grammar: &'g Program, seed: u64) -> Result<Self, GenerateError> {
+        let rules: HashMap<&str, &Rule> = grammar
+            .rules
+            .iter()
+            .map(|rule| (rule.name.text.as_str(), rule))
+            .collect();
+        let mut keywords = Vec::new();
+        let mut undefined = Vec::new();
+        for rule in grammar.rules.iter() {
+            visit(&rule.body, &mut |test| match test {
+                Test::Call(name) if !rules.contains_key(name.text.as_str()) => {
+                    undefined.push(name.clone())
+                }
+                Test::Literal(lit) if lit.text.starts_with(|c: char| c.is_ascii_alphanumeric()) => {
+                    keywords.push(lit.text.as_str())
+                }
+                _ => (),
+            });
+        }
+        let start = match rules.get(grammar.name.text.as_str()) {
+            Some(start) => *start,
+            None => return Err(GenerateError::Undefined(grammar.name.clone())),
+        };
+        if let Some(name) = undefined.into_iter().next() {
+            return Err(GenerateError::Undefined(name));
+        }
+        let heights = heights(&rules);
+        if let Some(rule) = grammar
+            .rules
+            .iter()
+            .find(|rule| !heights.contains_key(rule.name.text.as_str()))
+        {
+            return Err(GenerateError::Endless(rule.name.clone()));
+        }
+        Ok(Generator {
+            start,
+            rules,
+            heights,
+            weights: HashMap::new(),
+            keywords,
+            ids: Vec::new(),
+            max_depth: 12,
+            max_repeat: 2,
+            rng: Rng::new(seed),
+        })
+    }
+
+    /// Sets the nesting of rules after which sentences are finished off.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Sets the most times a `$` repeat is taken.
+    pub fn max_repeat(mut self, times: usize) -> Self {
+        self.max_repeat = times;
+        self
+    }
+
+    /// Weights the alternatives of `rule` in order, alternatives left over
+    /// weighing 1. A weight of 0 leaves an alternative out unless it is
+    /// needed to finish the sentence.
+    pub fn weights(mut self, rule: &str, weights: &[u32]) -> Self {
+        self.weights.insert(rule.to_string(), weights.to_vec());
+        self
+    }
+
+    /// Draws the `.ID` tokens from `ids` instead of making them up, so
+    /// that they can be declared.
+    pub fn ids(mut self, ids: &[&str]) -> Self {
+        self.ids = ids.iter().map(|id| id.to_string()).collect();
+        self
+    }
+
+    /// Generates a sentence, its tokens separated by spaces.
+    pub fn sentence(&mut self) -> String {
+        let mut tokens = Vec::new();
+        self.rule(self.start, 0, &mut tokens);
+        tokens.join(" ")
+    }
+
+    fn rule(&mut self, rule: &'g Rule, depth: usize, tokens: &mut Vec<String>) {
+        let weights = self.weights.get(&rule.name.text).cloned();
+        self.alternative(&rule.body, weights.as_deref(), depth + 1, tokens);
+    }
+
+    fn alternative(
+        &mut self,
+        alt: &'g Alternative,
+        weights: Option<&[u32]>,
+        depth: usize,
+        tokens: &mut Vec<String>,
+    ) {
+        let weight = |i: usize| weights.and_then(|w| w.get(i).copied()).unwrap_or(1);
+        let total: u32 = (0..alt.seqs.len()).map(weight).sum();
+        let seq = if depth > self.max_depth || total == 0 {
+            alt.seqs
+                .iter()
+                .min_by_key(|seq| self.seq_height(seq))
+                .unwrap()
+        } else {
+            let mut pick = self.rng.below(total as u64) as u32;
+            let mut i = 0;
+            while pick >= weight(i) {
+                pick -= weight(i);
+                i += 1;
+            }
+            &alt.seqs[i]
+        };
+        for item in seq.items.iter() {
+            if let Item::Test(test) = item {
+                self.test(test, depth, tokens);
+            }
+        }
+    }
+
+    fn test(&mut self, test: &'g Test, depth: usize, tokens: &mut Vec<String>) {
+        match test {
+            Test::Call(name) => self.rule(self.rules[name.text.as_str()], depth, tokens),
+            Test::Literal(lit) => tokens.push(lit.text.clone()),
+            Test::Builtin(Builtin::Id, _) => {
+                let id = self.id();
+                tokens.push(id);
+            }
+            Test::Builtin(Builtin::Number, _) => {
+                let number = self.number();
+                tokens.push(number);
+            }
+            Test::Builtin(Builtin::String, _) => {
+                let string = self.string();
+                tokens.push(string);
+            }
+            Test::Builtin(Builtin::Empty, _) => (),
+            Test::Group(group) => self.alternative(&group.body, None, depth, tokens),
+            Test::Repeat(repeat) => {
+                let times = match depth > self.max_depth {
+                    true => 0,
+                    false => self.rng.below(self.max_repeat as u64 + 1),
+                };
+                for _ in 0..times {
+                    self.test(&repeat.body, depth, tokens);
+                }
+            }
+        }
+    }
+
+    fn seq_height(&self, seq: &Sequence) -> usize {
+        seq_height(seq, &|name| self.heights[name])
+    }
+
+    fn id(&mut self) -> String {
+        const FIRST: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        const REST: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        if !self.ids.is_empty() {
+            return self.ids[self.rng.below(self.ids.len() as u64)].clone();
+        }
+        loop {
+            let mut id = String::from(self.rng.pick(FIRST));
+            for _ in 0..self.rng.below(4) {
+                id.push(self.rng.pick(REST));
+            }
+            // a literal test would match the start of it
+            if !self.keywords.iter().any(|kw| id.starts_with(kw)) {
+                return id;
+            }
+        }
+    }
+
+    fn number(&mut self) -> String {
+        let mut number = self.rng.below(1000).to_string();
+        if self.rng.below(4) == 0 {
+            number.push('.');
+            number.push_str(&self.rng.below(100).to_string());
+        }
+        number
+    }
+
+    fn string(&mut self) -> String {
+        const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 .,*=+-";
+        let mut string = String::from("'");
+        for _ in 0..self.rng.below(8) {
+            string.push(self.rng.pick(CHARS));
+        }
+        string.push('\'');
+        string
+    }
+}
+
+// least nesting of calls, to a fixed point; rules that never finish are
+// left out
+fn heights<'g>(rules: &HashMap<&'g str, &'g Rule>) -> HashMap<&'g str, usize> {
+    let mut heights: HashMap<&str, usize> = HashMap::new();
+    loop {
+        let mut changed = false;
+        for (name, rule) in rules.iter() {
+            let height = rule
+                .body
+                .seqs
+                .iter()
+                .map(|seq| seq_height(seq, &|name| *heights.get(name).unwrap_or(&usize::MAX)))
+                .min()
+                .unwrap_or(usize::MAX)
+                .saturating_add(1);
+            if height != usize::MAX && heights.get(name) != Some(&height) {
+                heights.insert(name, height);
+                changed = true;
+            }
+        }
+        if !changed {
+            return heights;
+        }
+    }
+}
+
+fn seq_height(seq: &Sequence, rule_height: &dyn Fn(&str) -> usize) -> usize {
+    fn test(t: &Test, rule_height: &dyn Fn(&str) -> usize) -> usize {
+        match t {
+            Test::Call(name) => rule_height(&name.text),
+            Test::Group(group) => group
+                .body
+                .seqs
+                .iter()
+                .map(|seq| seq_height(seq, rule_height))
+                .min()
+                .unwrap_or(0),
+            Test::Literal(_) | Test::Builtin(_, _) | Test::Repeat(_) => 0,
+        }
+    }
+    seq.items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Test(t) => Some(test(t, rule_height)),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn visit<'g>(alt: &'g Alternative, f: &mut impl FnMut(&'g Test)) {
+    for seq in alt.seqs.iter() {
+        for item in seq.items.iter() {
+            if let Item::Test(t) = item {
+                visit_test(t, f);
+            }
+        }
+    }
+}
+
+fn visit_test<'g>(t: &'g Test, f: &mut impl FnMut(&'g Test)) {
+    f(t);
+    match t {
+        Test::Group(group) => visit(&group.body, f),
+        Test::Repeat(repeat) => visit_test(&repeat.body, f),
+        _ => (),
+    }
+}
+
+// xorshift64*
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> usize {
+        (self.next() % n) as usize
+    }
+
+    fn pick(&mut self, chars: &[u8]) -> char {
+        chars[self.below(chars.len() as u64)] as char
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metasyn, Interpreter, M};
+
+    fn accepted(grammar: &Program, sentence: &str) -> bool {
+        let mut m = M::new(sentence);
+        Interpreter::new(grammar).expect("rules").run(&mut m);
+        m.generated().is_ok()
+    }
+
+    #[test]
+    fn sentences_parse() {
+        for src in [
+            include_str!("../../meta.syn"),
+            include_str!("../../va1.syn"),
+            include_str!("../../va2.syn"),
+        ] {
+            let grammar = metasyn::parse(src).expect("grammar");
+            let mut gen = Generator::new(&grammar, 7).expect("generator");
+            for _ in 0..50 {
+                let sentence = gen.sentence();
+                assert!(accepted(&grammar, &sentence), "{sentence}");
+            }
+        }
+    }
+
+    #[test]
+    fn limits_and_weights() {
+        let grammar = metasyn::parse(".SYNTAX E E = '(' E ')' / 'X' $'Y' ; .END").expect("grammar");
+        let mut gen = Generator::new(&grammar, 1).expect("generator").max_depth(3);
+        for _ in 0..20 {
+            assert!(gen.sentence().matches('(').count() <= 3);
+        }
+        let mut gen = Generator::new(&grammar, 1)
+            .expect("generator")
+            .weights("E", &[0])
+            .max_repeat(0);
+        assert_eq!(gen.sentence(), "X");
+        let mut gen = Generator::new(&grammar, 1)
+            .expect("generator")
+            .weights("E", &[1, 0])
+            .max_depth(2);
+        assert_eq!(gen.sentence().matches('(').count(), 2);
+        // the same seed, the same sentences
+        let sentences = |seed| {
+            let mut gen = Generator::new(&grammar, seed).expect("generator");
+            (0..5).map(|_| gen.sentence()).collect::<Vec<_>>()
+        };
+        assert_eq!(sentences(5), sentences(5));
+        assert_ne!(sentences(5), sentences(6));
+        let grammar = metasyn::parse(".SYNTAX S S = $.ID ; .END").expect("grammar");
+        let mut gen = Generator::new(&grammar, 2)
+            .expect("generator")
+            .ids(&["A", "B"])
+            .max_repeat(10);
+        for _ in 0..5 {
+            assert!(gen
+                .sentence()
+                .split(' ')
+                .all(|id| ["A", "B", ""].contains(&id)));
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        let grammar = metasyn::parse(".SYNTAX S S = $(.ID / .NUMBER / .STRING / 'IF') ; .END")
+            .expect("grammar");
+        let mut gen = Generator::new(&grammar, 3)
+            .expect("generator")
+            .max_repeat(30);
+        for _ in 0..10 {
+            let sentence = gen.sentence();
+            assert!(accepted(&grammar, &sentence), "{sentence}");
+            let mut m = M::new(&sentence);
+            while m.sr() || m.num() || m.id() {
+                assert!(m.last == "IF" || !m.last.starts_with("IF"), "{sentence}");
+            }
+            assert_eq!(m.left().trim(), "");
+        }
+    }
+
+    #[test]
+    fn unusable() {
+        let grammar = metasyn::parse(".SYNTAX S S = A ; A = 'X' A ; .END").expect("grammar");
+        let err = Generator::new(&grammar, 0).err().expect("endless rule");
+        assert_eq!(err.to_string(), "rule S has no finite expansion");
+        let grammar = metasyn::parse(".SYNTAX S S = A / 'X' ; .END").expect("grammar");
+        let err = Generator::new(&grammar, 0).err().expect("undefined rule");
+        assert_eq!(err.to_string(), "undefined rule A");
+    }
+}
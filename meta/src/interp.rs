@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::metasyn::{Alternative, Item, OutArg, Program, Rule, Sequence, Test};
+use crate::metasyn::{Builtin, Name};
+use crate::{SynError, M};
+
+/// A rule called or named as the start rule but never defined.
+#[derive(Debug, PartialEq)]
+pub struct UndefinedRule(pub Name);
+
+impl fmt::Display for UndefinedRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "undefined rule {}", self.0.text)
+    }
+}
+
+impl Error for UndefinedRule {}
+
+/// Runs a grammar directly on the input of a `M`, with the same
+/// primitives, switch and label stack the grammar compiled by `meta.mm`
+/// would use under `M::execute`, so both generate the same output.
+pub struct Interpreter<'g> {
+    start: &'g Rule,
+    rules: HashMap<&'g str, &'g Rule>,
+}
+
+impl<'g> Interpreter<'g> {
+    pub fn new(grammar: &'g Program) -> Result<Self, UndefinedRule> {
+        // like labels, a later definition replaces an earlier one
+        let rules: HashMap<&str, &Rule> = grammar
+            .rules
+            .iter()
+            .map(|rule| (rule.name.text.as_str(), rule))
+            .collect();
+        let mut calls = vec![&grammar.name];
+        for rule in grammar.rules.iter() {
+            called(&rule.body, &mut calls);
+        }
+        if let Some(name) = calls
+            .iter()
+            .find(|name| !rules.contains_key(name.text.as_str()))
+        {
+            return Err(UndefinedRule((*name).clone()));
+        }
+        let start = rules[grammar.name.text.as_str()];
+        Ok(Interpreter { start, rules })
+    }
+
+    /// Recognizes the input of `m`, leaving the result to `M::generated`.
+    pub fn run(&self, m: &mut M) {
+        m.cll(0);
+        let _ = self.rule(m, self.start);
+    }
+
+    // the body of a rule called with `M::cll`, returning from it unless
+    // it fails and does not recover
+    fn rule(&self, m: &mut M, rule: &Rule) -> Result<(), SynError> {
+        for lit in rule.recover.iter() {
+            m.syn(&lit.text);
+        }
+        match self.alternative(m, &rule.body) {
+            Ok(()) => {
+                m.r();
+                Ok(())
+            }
+            Err(e) if rule.recover.is_empty() => Err(e),
+            Err(e) => m.recover().map(|_| ()).ok_or(e),
+        }
+    }
+
+    fn alternative(&self, m: &mut M, alt: &Alternative) -> Result<(), SynError> {
+        for seq in alt.seqs.iter() {
+            self.sequence(m, seq)?;
+            if m.sw {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn sequence(&self, m: &mut M, seq: &Sequence) -> Result<(), SynError> {
+        for (i, item) in seq.items.iter().enumerate() {
+            match item {
+                Item::Test(test) => {
+                    self.test(m, test)?;
+                    if !m.sw {
+                        // only a failing first test lets the next
+                        // alternative be tried
+                        if i == 0 {
+                            return Ok(());
+                        }
+                        return Err(SynError::Unexpected);
+                    }
+                }
+                Item::Output(output) => {
+                    for arg in output.args.iter() {
+                        out_arg(m, arg);
+                    }
+                    m.out();
+                }
+                Item::Label(label) => {
+                    m.lb();
+                    out_arg(m, &label.arg);
+                    m.out();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn test(&self, m: &mut M, test: &Test) -> Result<(), SynError> {
+        match test {
+            Test::Call(name) => {
+                m.cll(1);
+                self.rule(m, self.rules[name.text.as_str()])?;
+            }
+            Test::Literal(lit) => {
+                m.tst(&lit.text);
+            }
+            Test::Builtin(Builtin::Id, _) => {
+                m.id();
+            }
+            Test::Builtin(Builtin::Number, _) => {
+                m.num();
+            }
+            Test::Builtin(Builtin::String, _) => {
+                m.sr();
+            }
+            Test::Builtin(Builtin::Empty, _) => m.set(),
+            Test::Group(group) => self.alternative(m, &group.body)?,
+            Test::Repeat(repeat) => {
+                loop {
+                    self.test(m, &repeat.body)?;
+                    if !m.sw {
+                        break;
+                    }
+                }
+                m.set();
+            }
+        }
+        Ok(())
+    }
+}
+
+fn out_arg(m: &mut M, arg: &OutArg) {
+    match arg {
+        OutArg::Gn1(_) => m.gn1(),
+        OutArg::Gn2(_) => m.gn2(),
+        OutArg::Input(_) => m.ci(),
+        OutArg::Literal(lit) => m.cl(&lit.text),
+    }
+}
+
+fn called<'g>(alt: &'g Alternative, calls: &mut Vec<&'g Name>) {
+    fn test<'g>(t: &'g Test, calls: &mut Vec<&'g Name>) {
+        match t {
+            Test::Call(name) => calls.push(name),
+            Test::Group(group) => called(&group.body, calls),
+            Test::Repeat(repeat) => test(&repeat.body, calls),
+            Test::Literal(_) | Test::Builtin(_, _) => (),
+        }
+    }
+    for seq in alt.seqs.iter() {
+        for item in seq.items.iter() {
+            if let Item::Test(t) = item {
+                test(t, calls);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metasyn;
+    use crate::{Diagnostic, MInstr};
+
+    // output, unrecognized rest and errors recovered from of the compiled
+    // and the interpreted grammar
+    fn both(grammar: &str, input: &str) -> [(Option<String>, String, Vec<Diagnostic>); 2] {
+        let meta_mm = mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/meta.mm"))
+            .expect("meta machine program");
+        let mut m = M::new(grammar);
+        m.execute(&meta_mm);
+        let compiled = mparse::parse::<MInstr>(&m.generated().expect("compilation"))
+            .expect("compiled grammar");
+        let mut m = M::new(input);
+        m.execute(&compiled);
+        let executed = (m.generated().ok(), m.left(), m.diagnostics().to_vec());
+
+        let program = metasyn::parse(grammar).expect("grammar");
+        let mut m = M::new(input);
+        Interpreter::new(&program).expect("rules").run(&mut m);
+        [
+            executed,
+            (m.generated().ok(), m.left(), m.diagnostics().to_vec()),
+        ]
+    }
+
+    #[test]
+    fn same_as_compiled() {
+        let meta_syn = include_str!("../../meta.syn");
+        let va1_syn = include_str!("../../va1.syn");
+        let va2_syn = include_str!("../../va2.syn");
+        let cases = [
+            (meta_syn, meta_syn),
+            (meta_syn, va1_syn),
+            (meta_syn, va2_syn),
+            (va1_syn, include_str!("../../valgol1m/fig3.va1")),
+            (va1_syn, include_str!("../../valgol1m/nested.va1")),
+            (va2_syn, include_str!("../../valgol2m/primes.va2")),
+            (va2_syn, include_str!("../../valgol2m/fib.va2")),
+        ];
+        for (grammar, input) in cases {
+            let [executed, interpreted] = both(grammar, input);
+            assert!(executed.0.is_some());
+            assert_eq!(interpreted, executed);
+        }
+        // meta.syn still describes meta.mm
+        let [_, (out, _, _)] = both(meta_syn, meta_syn);
+        assert_eq!(
+            out.map(|out| out + "\n"),
+            Some(include_str!("../../meta_mach_pgms/meta.mm").to_string())
+        );
+    }
+
+    #[test]
+    fn same_failures() {
+        let va1_syn = include_str!("../../va1.syn");
+        for input in [
+            ".BEGIN X = 1 .END",
+            ".BEGIN .REAL X; 1 = X; EDIT(X, 'A' .END",
+            ".BEGIN .REAL X; .UNTIL X 1 = X; PRINT .END",
+            ".BEGIN PRINT .END trailing",
+            "",
+            ".BEGIN .REAL X; X + = X; .IF X .THEN 1 = .ELSE PRINT; EDIT(X 2) .END",
+            ".BEGIN .UNTIL X .DO .BEGIN 1 = ; PRINT",
+        ] {
+            let [executed, interpreted] = both(va1_syn, input);
+            assert_eq!(executed.0, None, "{input}");
+            assert_eq!(interpreted, executed, "{input}");
+        }
+    }
+
+    #[test]
+    fn undefined_rules() {
+        let p = metasyn::parse(".SYNTAX A A = B / 'X' ; .END").expect("grammar");
+        let err = Interpreter::new(&p).err().expect("undefined rule");
+        assert_eq!(err.to_string(), "undefined rule B");
+        assert_eq!(err.0.span, 14..15);
+        let p = metasyn::parse(".SYNTAX S A = 'X' ; .END").expect("grammar");
+        assert!(Interpreter::new(&p).is_err());
+    }
+}
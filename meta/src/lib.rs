@@ -1,6 +1,12 @@
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
+
 use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::io;
 
+use mparse::dot::{ControlFlow, Flow};
+use mparse::profile::Profile;
 use mparse::AAAUse;
 use mparse::ParseableInstr;
 
@@ -12,6 +18,24 @@ pub enum Recognition {
 
 pub use Recognition::*;
 
+mod coverage;
+mod decompile;
+mod generate;
+mod interp;
+pub mod metasyn;
+mod opt;
+mod railroad;
+pub mod spec;
+mod synfmt;
+
+pub use coverage::{Branch, Coverage, CoverageError, GrammarCoverage};
+pub use decompile::{decompile, DecompileError};
+pub use generate::{GenerateError, Generator};
+pub use interp::{Interpreter, UndefinedRule};
+pub use opt::optimize;
+pub use railroad::railroad_html;
+pub use synfmt::format_grammar;
+
 #[derive(Debug)]
 pub enum SynError {
     Unexpected,
@@ -19,6 +43,34 @@ pub enum SynError {
 
 pub type MResult = Result<Recognition, SynError>;
 
+/// A start rule not labelled in the program.
+#[derive(Debug, PartialEq)]
+pub struct UnknownLabel(pub String);
+
+impl fmt::Display for UnknownLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown label {}", self.0)
+    }
+}
+
+impl Error for UnknownLabel {}
+
+/// A syntax error recovered from by skipping to a literal of a rule's
+/// `.RECOVER` list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Byte offset of the input not recognized.
+    pub pos: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "syntax error at line {}, column {}", self.line, self.col)
+    }
+}
+
 #[derive(Debug)]
 pub struct M<'a> {
     input: &'a str,
@@ -29,12 +81,22 @@ pub struct M<'a> {
     b_cnt: u16,
     output: String,
     stk: Vec<MStackVal>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+    diagnostics: Vec<Diagnostic>,
+    /// Where the input resumed after the last recovery.
+    resumed: Option<usize>,
 }
 
 #[derive(Debug)]
 enum MStackVal {
     Lb(String),
-    Back { ric: usize, blanks: bool },
+    /// `sync` holds the literals the rule recovers at.
+    Back {
+        ric: usize,
+        blanks: bool,
+        sync: Vec<String>,
+    },
 }
 
 impl<'a> M<'a> {
@@ -48,9 +110,31 @@ impl<'a> M<'a> {
             b_cnt: 0,
             output: " ".repeat(8),
             stk: Vec::new(),
+            profile: None,
+            coverage: None,
+            diagnostics: Vec::new(),
+            resumed: None,
         }
     }
 
+    /// Makes `execute` collect a `Profile` of the run.
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Makes `execute` record the rules called and the branches taken.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::default());
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
     fn eat_ws(&mut self) {
         let mut rest = &self.input[self.pos..];
         while !rest.is_empty() && rest.chars().next().unwrap().is_ascii_whitespace() {
@@ -159,7 +243,11 @@ impl<'a> M<'a> {
                 _ => (),
             }
         }
-        self.stk.push(MStackVal::Back { ric, blanks });
+        self.stk.push(MStackVal::Back {
+            ric,
+            blanks,
+            sync: Vec::new(),
+        });
         self.stk.push(MStackVal::Lb("".to_string()));
         self.stk.push(MStackVal::Lb("".to_string()));
     }
@@ -167,7 +255,7 @@ impl<'a> M<'a> {
     pub fn r(&mut self) -> usize {
         let stk_sz = self.stk.len();
         if stk_sz >= 3 {
-            if let MStackVal::Back { ric, blanks } = self.stk[stk_sz - 3] {
+            if let MStackVal::Back { ric, blanks, .. } = self.stk[stk_sz - 3] {
                 self.stk.drain(stk_sz - 3..);
                 if blanks {
                     self.stk.push(MStackVal::Lb("".to_string()));
@@ -179,6 +267,77 @@ impl<'a> M<'a> {
         panic!("machine state stack unmatched return")
     }
 
+    /// Makes the innermost rule recover at `s`.
+    pub fn syn(&mut self, s: &str) {
+        let stk_sz = self.stk.len();
+        if stk_sz >= 3 {
+            if let MStackVal::Back { sync, .. } = &mut self.stk[stk_sz - 3] {
+                sync.push(s.to_string());
+                return;
+            }
+        }
+        panic!("malformed machine state stack")
+    }
+
+    /// After a failed `be`, returns from the innermost rule with literals
+    /// to recover at as if it had succeeded, recording a diagnostic and
+    /// skipping the input up to one of the literals. `None` when no rule
+    /// recovers, or nothing was recognized since the last recovery.
+    pub fn recover(&mut self) -> Option<usize> {
+        self.eat_ws();
+        if self.resumed == Some(self.pos) {
+            return None;
+        }
+        let frame = self
+            .stk
+            .iter()
+            .rposition(|v| matches!(v, MStackVal::Back { sync, .. } if !sync.is_empty()))?;
+        let MStackVal::Back { sync, .. } = &self.stk[frame] else {
+            unreachable!()
+        };
+        let sync = sync.clone();
+        let (line, col) = metasyn::line_col(self.input, self.pos);
+        self.diagnostics.push(Diagnostic {
+            pos: self.pos,
+            line,
+            col,
+        });
+        self.output.clear();
+        // the rules it called
+        let inner = self.stk[frame + 1..]
+            .iter()
+            .filter(|v| matches!(v, MStackVal::Back { .. }))
+            .count();
+        for _ in 0..inner {
+            self.r();
+        }
+        loop {
+            self.eat_ws();
+            let rest = &self.input[self.pos..];
+            match rest.chars().next() {
+                Some(c) if !sync.iter().any(|s| rest.starts_with(s.as_str())) => {
+                    self.pos += c.len_utf8()
+                }
+                _ => break,
+            }
+        }
+        self.resumed = Some(self.pos);
+        self.sw = true;
+        Some(self.r())
+    }
+
+    // rules active
+    fn depth(&self) -> usize {
+        self.stk
+            .iter()
+            .filter(|v| matches!(v, MStackVal::Back { .. }))
+            .count()
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
     pub fn set(&mut self) {
         self.sw = true;
     }
@@ -190,14 +349,21 @@ impl<'a> M<'a> {
         Ok(Recognized)
     }
 
+    // output stops with the first syntax error
+    fn emit(&mut self, s: &str) {
+        if self.diagnostics.is_empty() {
+            self.output.push_str(s);
+        }
+    }
+
     pub fn cl(&mut self, s: &str) {
-        self.output.push_str(s);
-        self.output.push(' ');
+        self.emit(s);
+        self.emit(" ");
     }
 
     pub fn ci(&mut self) {
         if self.sw {
-            self.output.push_str(self.last);
+            self.emit(self.last);
         }
     }
 
@@ -213,8 +379,8 @@ impl<'a> M<'a> {
                 } else {
                     newlb = s.clone();
                 }
-                self.output.push_str(&newlb);
-                self.output.push(' ');
+                self.emit(&newlb);
+                self.emit(" ");
                 return;
             }
         }
@@ -233,8 +399,8 @@ impl<'a> M<'a> {
                 } else {
                     newlb = s.clone();
                 }
-                self.output.push_str(&newlb);
-                self.output.push(' ');
+                self.emit(&newlb);
+                self.emit(" ");
                 return;
             }
         }
@@ -242,8 +408,8 @@ impl<'a> M<'a> {
     }
 
     pub fn out(&mut self) {
-        self.output.push('\n');
-        self.output.push_str(&" ".repeat(8));
+        self.emit("\n");
+        self.emit(&" ".repeat(8));
     }
 
     pub fn lb(&mut self) {
@@ -258,22 +424,63 @@ impl<'a> M<'a> {
         self.input[self.pos..].trim_start().to_string()
     }
 
+    /// Like `generated`, but only a prefix of the input needs to have been
+    /// recognized, its length, with any blanks after it, returned with the
+    /// output.
+    pub fn generated_prefix(&self) -> Result<(String, usize), SynError> {
+        self.be()?;
+        if !self.diagnostics.is_empty() {
+            return Err(SynError::Unexpected);
+        }
+        Ok((self.output.to_string(), self.pos))
+    }
+
     pub fn generated(&self) -> Result<String, SynError> {
         self.be()?;
-        if !self.left().is_empty() {
+        if !self.left().is_empty() || !self.diagnostics.is_empty() {
             return Err(SynError::Unexpected);
         }
         Ok(self.output.to_string())
     }
 
     pub fn execute(&mut self, pgm: &mparse::MProgram<MInstr>) {
-        let mut ic: usize;
-        self.cll(0);
         match &pgm.instrs[0] {
-            MInstr::ADR(_, start) => ic = *start,
+            MInstr::ADR(_, start) => self.execute_from(pgm, *start),
             _ => panic!("invalid program prolog"),
         }
+    }
+
+    /// Executes `pgm` starting with the rule labelled `rule` instead of
+    /// the `ADR` target.
+    pub fn execute_rule(
+        &mut self,
+        pgm: &mparse::MProgram<MInstr>,
+        rule: &str,
+    ) -> Result<(), UnknownLabel> {
+        match pgm.labels.get(rule).and_then(|addr| pgm.ic.get(addr)) {
+            Some(entry) => {
+                self.execute_from(pgm, *entry);
+                Ok(())
+            }
+            None => Err(UnknownLabel(rule.to_string())),
+        }
+    }
+
+    // calls the rule at `start`
+    fn execute_from(&mut self, pgm: &mparse::MProgram<MInstr>, start: usize) {
+        let mut ic = start;
+        self.cll(0);
+        if let Some(prof) = &mut self.profile {
+            *prof = Profile::new(pgm.instrs.len());
+            prof.call(ic);
+        }
+        if let Some(cov) = &mut self.coverage {
+            cov.call(ic);
+        }
         loop {
+            if let Some(prof) = &mut self.profile {
+                prof.instr(ic);
+            }
             match &pgm.instrs[ic] {
                 MInstr::Undef => panic!("Undef unexpected in program"),
                 MInstr::ADR(_, _) => panic!("ADR unexpected after prolog"),
@@ -292,9 +499,18 @@ impl<'a> M<'a> {
                 MInstr::CLL(_, procc) => {
                     self.cll(ic + 1);
                     ic = *procc;
+                    if let Some(prof) = &mut self.profile {
+                        prof.call(ic);
+                    }
+                    if let Some(cov) = &mut self.coverage {
+                        cov.call(ic);
+                    }
                     continue;
                 }
                 MInstr::R => {
+                    if let Some(prof) = &mut self.profile {
+                        prof.ret(Some(self.sw));
+                    }
                     ic = self.r();
                     if ic == 0 {
                         break;
@@ -302,17 +518,24 @@ impl<'a> M<'a> {
                     continue;
                 }
                 MInstr::SET => self.set(),
+                MInstr::SYN(s) => self.syn(s),
                 MInstr::B(_, jic) => {
                     ic = *jic;
                     continue;
                 }
                 MInstr::BT(_, jic) => {
+                    if let Some(cov) = &mut self.coverage {
+                        cov.branch(ic, self.sw);
+                    }
                     if self.sw {
                         ic = *jic;
                         continue;
                     }
                 }
                 MInstr::BF(_, jic) => {
+                    if let Some(cov) = &mut self.coverage {
+                        cov.branch(ic, !self.sw);
+                    }
                     if !self.sw {
                         ic = *jic;
                         continue;
@@ -320,7 +543,24 @@ impl<'a> M<'a> {
                 }
                 MInstr::BE => match self.be() {
                     Ok(Recognized) => (),
-                    _ => break,
+                    _ => {
+                        let depth = self.depth();
+                        let Some(ric) = self.recover() else {
+                            break;
+                        };
+                        let unwound = depth - self.depth();
+                        if let Some(prof) = &mut self.profile {
+                            for _ in 1..unwound {
+                                prof.ret(Some(false));
+                            }
+                            prof.ret(Some(true));
+                        }
+                        ic = ric;
+                        if ic == 0 {
+                            break;
+                        }
+                        continue;
+                    }
                 },
                 MInstr::CL(s) => self.cl(s),
                 MInstr::CI => self.ci(),
@@ -331,10 +571,13 @@ impl<'a> M<'a> {
             };
             ic += 1;
         }
+        if let Some(prof) = &mut self.profile {
+            prof.finish();
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MInstr {
     TST(String),
     ID,
@@ -343,6 +586,7 @@ pub enum MInstr {
     CLL(String, usize),
     R,
     SET,
+    SYN(String),
     B(String, usize),
     BT(String, usize),
     BF(String, usize),
@@ -360,6 +604,7 @@ pub enum MInstr {
 impl ParseableInstr for MInstr {
     const UNDEF: Self = MInstr::Undef;
     const ACCEPT_BLK: bool = false;
+    const ACCEPT_END_LABEL: bool = false;
 
     fn is_undefined(&self) -> bool {
         matches!(self, MInstr::Undef)
@@ -384,6 +629,7 @@ impl ParseableInstr for MInstr {
         match ins {
             "TST" => MInstr::TST(s),
             "CL" => MInstr::CL(s),
+            "SYN" => MInstr::SYN(s),
             _ => MInstr::Undef,
         }
     }
@@ -432,42 +678,369 @@ impl ParseableInstr for MInstr {
     }
 }
 
+impl ControlFlow for MInstr {
+    fn flow(&self) -> Flow {
+        match self {
+            // the program ends when the rule at the ADR target returns
+            MInstr::ADR(_, ic) | MInstr::B(_, ic) => Flow::Jump(*ic),
+            MInstr::BT(_, ic) | MInstr::BF(_, ic) => Flow::Branch(*ic),
+            MInstr::CLL(_, ic) => Flow::Call(*ic),
+            MInstr::R | MInstr::Undef => Flow::Stop,
+            _ => Flow::Next,
+        }
+    }
+}
+
+impl fmt::Display for MInstr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MInstr::TST(s) => write!(f, "TST '{s}'"),
+            MInstr::CL(s) => write!(f, "CL '{s}'"),
+            MInstr::SYN(s) => write!(f, "SYN '{s}'"),
+            MInstr::CLL(aaa, _) => write!(f, "CLL {aaa}"),
+            MInstr::B(aaa, _) => write!(f, "B {aaa}"),
+            MInstr::BT(aaa, _) => write!(f, "BT {aaa}"),
+            MInstr::BF(aaa, _) => write!(f, "BF {aaa}"),
+            MInstr::ADR(aaa, _) => write!(f, "ADR {aaa}"),
+            MInstr::Undef => f.write_str("???"),
+            noarg => write!(f, "{noarg:?}"),
+        }
+    }
+}
+
 pub fn run(opts: Options) -> Result<(), Box<dyn Error>> {
+    match opts.command {
+        Command::Format => format_file(&opts.mpgm_path, opts.check),
+        Command::Interpret => {
+            let grammar = read_grammar(&opts.mpgm_path)?;
+            let interpreter = Interpreter::new(&grammar)?;
+            let source = fs::read_to_string(&opts.source_path)?;
+            let mut m = M::new(&source);
+            interpreter.run(&mut m);
+            print_generated(&m)
+        }
+        Command::Generate => {
+            let grammar = read_grammar(&opts.mpgm_path)?;
+            println!("{}", Generator::new(&grammar, opts.seed)?.sentence());
+            Ok(())
+        }
+        Command::Railroad => {
+            let grammar = read_grammar(&opts.mpgm_path)?;
+            print!("{}", railroad_html(&grammar, opts.outputs));
+            Ok(())
+        }
+        Command::Coverage => report_coverage(&load_program(&opts)?, &opts),
+        Command::Test => run_specs(&load_program(&opts)?, &opts.corpus),
+        Command::Dot => {
+            print!("{}", mparse::dot::to_dot(&load_program(&opts)?));
+            Ok(())
+        }
+        Command::Calls => {
+            print!("{}", mparse::dot::call_graph(&load_program(&opts)?));
+            Ok(())
+        }
+        Command::Decompile => {
+            print!("{}", decompile(&load_program(&opts)?)?);
+            Ok(())
+        }
+        Command::Compile => compile_source(&load_program(&opts)?, &opts),
+    }
+}
+
+fn read_grammar(path: &str) -> Result<metasyn::Program, Box<dyn Error>> {
+    Ok(metasyn::parse(&fs::read_to_string(path)?)?)
+}
+
+fn load_program(opts: &Options) -> Result<mparse::MProgram<MInstr>, Box<dyn Error>> {
     let p = mparse::load::<MInstr>(&opts.mpgm_path)?;
+    match opts.optimize {
+        true => Ok(optimize(&p)),
+        false => Ok(p),
+    }
+}
+
+fn compile_source(p: &mparse::MProgram<MInstr>, opts: &Options) -> Result<(), Box<dyn Error>> {
     let source = fs::read_to_string(&opts.source_path)?;
     let mut m = M::new(&source);
-    m.execute(&p);
+    if opts.profile || opts.folded_path.is_some() {
+        m.enable_profile();
+    }
+    match &opts.start {
+        Some(rule) => m.execute_rule(p, rule)?,
+        None => m.execute(p),
+    }
+    if let Some(prof) = m.take_profile() {
+        if opts.profile {
+            prof.report(p, &mut io::stderr())?;
+        }
+        if let Some(path) = &opts.folded_path {
+            prof.write_folded(p, &mut fs::File::create(path)?)?;
+        }
+    }
+    if opts.prefix {
+        return print_prefix(&m, &source);
+    }
+    print_generated(&m)
+}
+
+fn print_prefix(m: &M, source: &str) -> Result<(), Box<dyn Error>> {
+    match m.generated_prefix() {
+        Ok((out, len)) => {
+            println!("{}", out);
+            if !source[len..].trim().is_empty() {
+                let (line, col) = metasyn::line_col(source, len);
+                eprintln!("recognized up to line {line}, column {col}");
+            }
+            Ok(())
+        }
+        Err(_) => {
+            print_errors(m);
+            Err(From::from("compilation failed"))
+        }
+    }
+}
+
+// the errors recovered from, then where the machine stopped if it did
+fn print_errors(m: &M) {
+    for diagnostic in m.diagnostics() {
+        println!("{diagnostic}");
+    }
+    if m.be().is_err() || !m.left().is_empty() {
+        println!("unexpected:\n{}", m.left());
+    }
+}
+
+fn report_coverage(pgm: &mparse::MProgram<MInstr>, opts: &Options) -> Result<(), Box<dyn Error>> {
+    let src = fs::read_to_string(&opts.source_path)?;
+    let grammar = metasyn::parse(&src)?;
+    let mut coverage = Coverage::default();
+    for path in opts.corpus.iter() {
+        let source = fs::read_to_string(path)?;
+        let mut m = M::new(&source);
+        m.enable_coverage();
+        m.execute(pgm);
+        if m.generated().is_err() {
+            eprintln!("{path}: not recognized");
+        }
+        coverage.merge(&m.take_coverage().unwrap_or_default());
+    }
+    let covered = GrammarCoverage::new(&grammar, pgm, &coverage)?;
+    if opts.annotate {
+        print!("{}", covered.annotate(&src));
+    } else {
+        covered.report(&src, &mut io::stdout())?;
+    }
+    Ok(())
+}
+
+fn run_specs(pgm: &mparse::MProgram<MInstr>, paths: &[String]) -> Result<(), Box<dyn Error>> {
+    let (mut passed, mut failed) = (0, 0);
+    for path in paths {
+        let cases =
+            spec::parse_spec(&fs::read_to_string(path)?).map_err(|e| format!("{path}: {e}"))?;
+        for case in cases.iter() {
+            match spec::run_case(pgm, case) {
+                Ok(()) => passed += 1,
+                Err(why) => {
+                    failed += 1;
+                    println!("FAILED {} ({path}:{})\n{why}", case.name, case.line);
+                }
+            }
+        }
+    }
+    println!("{passed} passed, {failed} failed");
+    if failed > 0 {
+        return Err(From::from("tests failed"));
+    }
+    Ok(())
+}
+
+fn format_file(path: &str, check: bool) -> Result<(), Box<dyn Error>> {
+    let src = fs::read_to_string(path)?;
+    let formatted = format_grammar(&metasyn::parse(&src)?);
+    if formatted == src {
+        return Ok(());
+    }
+    if check {
+        let line = src
+            .lines()
+            .zip(formatted.lines())
+            .position(|(a, b)| a != b)
+            .unwrap_or(src.lines().count().min(formatted.lines().count()))
+            + 1;
+        return Err(From::from(format!(
+            "{path} is not formatted, from line {line}"
+        )));
+    }
+    fs::write(path, formatted)?;
+    Ok(())
+}
+
+fn print_generated(m: &M) -> Result<(), Box<dyn Error>> {
     match m.generated() {
         Ok(out) => {
             println!("{}", out);
             Ok(())
         }
         Err(_) => {
-            println!("unexpected:\n{}", m.left());
+            print_errors(m);
             Err(From::from("compilation failed"))
         }
     }
 }
 
+/// What `meta` does, named by the first argument unless compiling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// `meta PGM SOURCE` compiles the source with the program.
+    Compile,
+    /// `meta dot PGM` prints the basic block graph of the program.
+    Dot,
+    /// `meta calls PGM` prints the rule call graph of the program.
+    Calls,
+    /// `meta decompile PGM` prints the grammar the program was compiled from.
+    Decompile,
+    /// `meta fmt GRAMMAR` rewrites a `.syn` grammar in canonical layout.
+    Format,
+    /// `meta interpret GRAMMAR SOURCE` runs a `.syn` grammar without
+    /// compiling it first.
+    Interpret,
+    /// `meta railroad GRAMMAR` prints an HTML page of syntax diagrams.
+    Railroad,
+    /// `meta generate GRAMMAR` prints a random sentence of the grammar.
+    Generate,
+    /// `meta coverage PGM GRAMMAR FILE...` reports the alternatives of
+    /// the grammar taken compiling the files.
+    Coverage,
+    /// `meta test PGM SPEC...` runs the cases of `spec` files.
+    Test,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "dot" => Some(Command::Dot),
+            "calls" => Some(Command::Calls),
+            "decompile" => Some(Command::Decompile),
+            "fmt" => Some(Command::Format),
+            "interpret" => Some(Command::Interpret),
+            "railroad" => Some(Command::Railroad),
+            "generate" => Some(Command::Generate),
+            "coverage" => Some(Command::Coverage),
+            "test" => Some(Command::Test),
+            _ => None,
+        }
+    }
+}
+
 pub struct Options {
+    pub command: Command,
+    /// The grammar when formatting, interpreting or drawing diagrams.
     pub mpgm_path: String,
+    /// Empty unless compiling or interpreting, the grammar of the program
+    /// for `meta coverage`.
     pub source_path: String,
+    pub optimize: bool,
+    /// With `--check`, `meta fmt` fails instead when the layout differs.
+    pub check: bool,
+    /// With `--outputs`, the diagrams include the output clauses.
+    pub outputs: bool,
+    /// Set with `--seed N`, picks the sentence generated.
+    pub seed: u64,
+    /// With `--annotate`, the report is a listing of the grammar instead.
+    pub annotate: bool,
+    /// The files compiled for `meta coverage`, the specs for `meta test`.
+    pub corpus: Vec<String>,
+    /// With `--start RULE`, compiling starts with `RULE` instead of the
+    /// `ADR` target.
+    pub start: Option<String>,
+    /// With `--prefix`, the input only needs to start with what is
+    /// recognized.
+    pub prefix: bool,
+    pub profile: bool,
+    pub folded_path: Option<String>,
 }
 
 impl Options {
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Self, &'static str> {
-        args.next();
-        let mpgm_path = match args.next() {
+    pub fn build(args: impl Iterator<Item = String>) -> Result<Self, &'static str> {
+        let mut optimize = false;
+        let mut profile = false;
+        let mut check = false;
+        let mut outputs = false;
+        let mut seed = 0;
+        let mut annotate = false;
+        let mut start = None;
+        let mut prefix = false;
+        let mut folded_path = None;
+        let mut positional = Vec::new();
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--optimize" => optimize = true,
+                "--profile" => profile = true,
+                "--check" => check = true,
+                "--outputs" => outputs = true,
+                "--annotate" => annotate = true,
+                "--prefix" => prefix = true,
+                "--start" => match args.next() {
+                    Some(rule) => start = Some(rule),
+                    None => return Err("missing start rule argument"),
+                },
+                "--seed" => match args.next().map(|arg| arg.parse()) {
+                    Some(Ok(n)) => seed = n,
+                    Some(Err(_)) => return Err("invalid seed argument"),
+                    None => return Err("missing seed argument"),
+                },
+                "--folded" => match args.next() {
+                    Some(path) => folded_path = Some(path),
+                    None => return Err("missing folded stacks file argument"),
+                },
+                _ => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter().peekable();
+        let command = match positional.peek().and_then(|arg| Command::from_name(arg)) {
+            Some(command) => {
+                positional.next();
+                command
+            }
+            None => Command::Compile,
+        };
+        let mpgm_path = match positional.next() {
             Some(arg) => arg,
             None => return Err("missing meta machine program path argument"),
         };
-        let source_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("missing source file path argument"),
+        let source_path = match command {
+            Command::Compile | Command::Interpret | Command::Coverage => match positional.next() {
+                Some(arg) => arg,
+                None => return Err("missing source file path argument"),
+            },
+            _ => String::new(),
+        };
+        let corpus: Vec<String> = match command {
+            Command::Coverage | Command::Test => positional.by_ref().collect(),
+            _ => Vec::new(),
         };
+        if matches!(command, Command::Coverage | Command::Test) && corpus.is_empty() {
+            return Err("missing source file path argument");
+        }
+        if positional.next().is_some() {
+            return Err("too many arguments");
+        }
         Ok(Options {
+            command,
             mpgm_path,
             source_path,
+            optimize,
+            check,
+            outputs,
+            seed,
+            annotate,
+            corpus,
+            start,
+            prefix,
+            profile,
+            folded_path,
         })
     }
 }
@@ -639,6 +1212,184 @@ mod tests {
         assert_eq!(m.output.as_str(), "        SETXYZ")
     }
 
+    #[test]
+    fn graphs() {
+        let p = mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/meta.mm")).expect("mm");
+        let calls = mparse::dot::call_graph(&p);
+        let edges: Vec<&str> = calls.lines().filter(|l| l.contains("->")).collect();
+        assert_eq!(edges.len(), 9);
+        assert!(calls.contains("  r174 [label=\"PROGRAM\"];\n"));
+        assert!(calls.contains("  r43 [label=\"EX3\"];\n"));
+        // EX3 calls itself for `$` and EX1 for parenthesized expressions
+        assert!(edges.contains(&"  r43 -> r43;"));
+        assert!(edges.contains(&"  r43 -> r118;"));
+        let dot = mparse::dot::to_dot(&p);
+        assert!(dot.contains("  b21 [label=\"A002\\lA005\\l21    R\\l\"];\n"));
+        assert!(dot.contains("  b1 [label=\"OUT1\\l1     TST '*1'\\l2     BF A001\\l\"];\n"));
+        assert!(!dot.contains("end"));
+    }
+
+    #[test]
+    fn execute_rule() {
+        let p =
+            mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/va1.mm")).expect("program");
+        let mut m = M::new("A + 1");
+        m.execute_rule(&p, "EXP").expect("rule");
+        assert_eq!(
+            m.generated().expect("expression"),
+            "        LD  A\n        LDL 1\n        ADD \n        "
+        );
+        // the whole input must be recognized, except for a prefix match
+        let mut m = M::new("A + 1 ) = B");
+        m.execute_rule(&p, "EXP").expect("rule");
+        assert!(m.generated().is_err());
+        let (out, len) = m.generated_prefix().expect("prefix");
+        assert_eq!(out, "        LD  A\n        LDL 1\n        ADD \n        ");
+        assert_eq!(len, 6);
+        let mut m = M::new("+ 1");
+        m.execute_rule(&p, "EXP").expect("rule");
+        assert!(m.generated_prefix().is_err());
+        let mut m = M::new("A");
+        assert_eq!(
+            m.execute_rule(&p, "EXPR"),
+            Err(UnknownLabel("EXPR".to_string()))
+        );
+    }
+
+    #[test]
+    fn recovery() {
+        let p =
+            mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/va1.mm")).expect("program");
+        let source =
+            ".BEGIN .REAL X;\nX + = X;\nPRINT;\n.IF X .THEN 1 = .ELSE PRINT;\nEDIT(X 2)\n.END";
+        let mut m = M::new(source);
+        m.enable_profile();
+        m.execute(&p);
+        let at: Vec<(usize, usize)> = m.diagnostics().iter().map(|d| (d.line, d.col)).collect();
+        assert_eq!(at, [(2, 5), (4, 17), (5, 8)]);
+        assert_eq!(
+            m.diagnostics()[0].to_string(),
+            "syntax error at line 2, column 5"
+        );
+        // recognized to the end, but without output
+        assert!(m.left().is_empty());
+        assert!(m.generated().is_err());
+        assert_eq!(m.output, "");
+        let prof = m.take_profile().expect("profile");
+        let st = p.ic[&p.labels["ST"]];
+        assert_eq!(prof.rules[&st].successes, 6);
+
+        // a rule recovering without progress stops the machine
+        let meta_mm = mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/meta.mm"))
+            .expect("meta machine program");
+        let mut m = M::new(".SYNTAX A A = $B ; B .RECOVER(';') = .EMPTY 'Y' ; .END");
+        m.execute(&meta_mm);
+        let p = mparse::parse::<MInstr>(&m.generated().expect("compilation")).expect("program");
+        let mut m = M::new("Z");
+        m.execute(&p);
+        let at: Vec<usize> = m.diagnostics().iter().map(|d| d.pos).collect();
+        assert_eq!(at, [0]);
+        assert!(m.be().is_err());
+    }
+
+    #[test]
+    fn execute_profile() {
+        let p = mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/meta.mm"))
+            .expect("meta machine program");
+        let mut m = M::new(include_str!("../../meta.syn"));
+        m.enable_profile();
+        m.execute(&p);
+        assert_eq!(
+            m.generated().expect("compilation"),
+            include_str!("../../meta_mach_pgms/meta.mm").trim_end_matches('\n')
+        );
+        let prof = m.take_profile().expect("profile");
+        let st = p.ic[&p.labels["ST"]];
+        assert_eq!(prof.rules[&st].calls, 9);
+        assert_eq!(prof.rules[&st].successes, 8);
+        assert_eq!(prof.rules[&st].failures, 1);
+        let program = p.ic[&p.labels["PROGRAM"]];
+        assert_eq!(prof.rules[&program].total_cost, prof.executed());
+        let mut folded = Vec::new();
+        prof.write_folded(&p, &mut folded).expect("folded");
+        let folded = String::from_utf8(folded).expect("utf8");
+        assert!(folded.starts_with("PROGRAM 32\nPROGRAM;ST 163\n"));
+        assert!(folded.contains("\nPROGRAM;ST;EX1;EX2;EX3;EX1;EX2;EX3 "));
+    }
+
+    #[test]
+    fn options() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let opts = Options::build(args(&["meta", "a.mm", "b.syn"]).into_iter()).expect("options");
+        assert_eq!(opts.mpgm_path, "a.mm");
+        assert_eq!(opts.command, Command::Compile);
+        assert_eq!(opts.source_path, "b.syn");
+        assert!(!opts.profile);
+        let opts = Options::build(
+            args(&["meta", "--profile", "a.mm", "--folded", "f", "b.syn"]).into_iter(),
+        )
+        .expect("options");
+        assert_eq!(opts.source_path, "b.syn");
+        assert!(opts.profile);
+        assert!(!opts.optimize);
+        assert_eq!(opts.folded_path.as_deref(), Some("f"));
+        let opts = Options::build(args(&["meta", "a.mm", "--optimize", "b.syn"]).into_iter())
+            .expect("options");
+        assert!(opts.optimize);
+        assert!(Options::build(args(&["meta", "a.mm"]).into_iter()).is_err());
+        let opts = Options::build(args(&["meta", "calls", "a.mm"]).into_iter()).expect("options");
+        assert_eq!(opts.mpgm_path, "a.mm");
+        assert_eq!(opts.command, Command::Calls);
+        let opts =
+            Options::build(args(&["meta", "decompile", "a.mm"]).into_iter()).expect("options");
+        assert_eq!(opts.command, Command::Decompile);
+        let opts = Options::build(args(&["meta", "fmt", "--check", "a.syn"]).into_iter())
+            .expect("options");
+        assert_eq!(opts.command, Command::Format);
+        assert!(opts.check);
+        assert_eq!(opts.mpgm_path, "a.syn");
+        assert!(Options::build(args(&["meta", "dot", "a.mm", "b.syn"]).into_iter()).is_err());
+        let opts = Options::build(args(&["meta", "interpret", "a.syn", "b.va1"]).into_iter())
+            .expect("options");
+        assert_eq!(opts.command, Command::Interpret);
+        assert_eq!(opts.source_path, "b.va1");
+        assert!(Options::build(args(&["meta", "interpret", "a.syn"]).into_iter()).is_err());
+        let opts = Options::build(args(&["meta", "railroad", "--outputs", "a.syn"]).into_iter())
+            .expect("options");
+        assert_eq!(opts.command, Command::Railroad);
+        assert!(opts.outputs);
+        assert_eq!(opts.mpgm_path, "a.syn");
+        let opts = Options::build(args(&["meta", "generate", "--seed", "42", "a.syn"]).into_iter())
+            .expect("options");
+        assert_eq!(opts.command, Command::Generate);
+        assert_eq!(opts.seed, 42);
+        let opts = Options::build(
+            args(&["meta", "coverage", "--annotate", "a.mm", "a.syn", "x", "y"]).into_iter(),
+        )
+        .expect("options");
+        assert_eq!(opts.command, Command::Coverage);
+        assert!(opts.annotate);
+        assert_eq!(opts.source_path, "a.syn");
+        assert_eq!(opts.corpus, vec!["x", "y"]);
+        assert!(Options::build(args(&["meta", "coverage", "a.mm", "a.syn"]).into_iter()).is_err());
+        let opts = Options::build(args(&["meta", "test", "a.mm", "a.spec", "b.spec"]).into_iter())
+            .expect("options");
+        assert_eq!(opts.command, Command::Test);
+        assert_eq!(opts.corpus, vec!["a.spec", "b.spec"]);
+        let opts = Options::build(
+            args(&["meta", "--start", "EXP", "--prefix", "a.mm", "b.va1"]).into_iter(),
+        )
+        .expect("options");
+        assert_eq!(opts.start.as_deref(), Some("EXP"));
+        assert!(opts.prefix);
+        assert_eq!(opts.source_path, "b.va1");
+        assert!(Options::build(args(&["meta", "a.mm", "b.va1", "--start"]).into_iter()).is_err());
+        assert!(
+            Options::build(args(&["meta", "generate", "--seed", "x", "a.syn"]).into_iter())
+                .is_err()
+        );
+    }
+
     #[test]
     fn m_out_lb() {
         let mut m = M::new("");
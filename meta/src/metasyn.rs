@@ -0,0 +1,639 @@
+//! A typed model of META II grammars, as read by `meta.syn`.
+//!
+//! Tokens are recognized the way the meta machine does: literals match by
+//! prefix after skipping ASCII whitespace, identifiers are an ASCII letter
+//! followed by letters and digits, and strings run from quote to quote.
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// Byte offsets into the grammar source.
+pub type Span = Range<usize>;
+
+/// `.SYNTAX name rules .END`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// The rule execution starts with.
+    pub name: Name,
+    pub rules: Vec<Rule>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Name {
+    pub text: String,
+    pub span: Span,
+}
+
+/// `name = body ;` or `name .RECOVER( literals ) = body ;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: Name,
+    /// On a syntax error inside the rule, the input is skipped up to one
+    /// of these and the rule succeeds.
+    pub recover: Vec<Literal>,
+    pub body: Alternative,
+    pub span: Span,
+}
+
+/// Sequences separated by `/`, tried in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternative {
+    pub seqs: Vec<Sequence>,
+    pub span: Span,
+}
+
+/// Items that must all succeed once the first test has.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sequence {
+    pub items: Vec<Item>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Test(Test),
+    Output(Output),
+    Label(Label),
+}
+
+/// Something that recognizes input and sets the switch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Test {
+    /// Calls another rule.
+    Call(Name),
+    Literal(Literal),
+    Builtin(Builtin, Span),
+    Group(Group),
+    Repeat(Repeat),
+}
+
+/// A quoted string, `text` without the quotes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Literal {
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    /// `.ID`
+    Id,
+    /// `.NUMBER`
+    Number,
+    /// `.STRING`
+    String,
+    /// `.EMPTY`
+    Empty,
+}
+
+/// `( body )`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub body: Alternative,
+    pub span: Span,
+}
+
+/// `$ body`, zero or more times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repeat {
+    pub body: Box<Test>,
+    pub span: Span,
+}
+
+/// `.OUT( args )`, one output line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub args: Vec<OutArg>,
+    pub span: Span,
+}
+
+/// `.LABEL arg`, output in the label column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub arg: OutArg,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutArg {
+    /// `*1`, the first generated label of the rule.
+    Gn1(Span),
+    /// `*2`, the second generated label of the rule.
+    Gn2(Span),
+    /// `*`, the last recognized input.
+    Input(Span),
+    Literal(Literal),
+}
+
+impl Item {
+    pub fn span(&self) -> Span {
+        match self {
+            Item::Test(test) => test.span(),
+            Item::Output(output) => output.span.clone(),
+            Item::Label(label) => label.span.clone(),
+        }
+    }
+}
+
+impl Test {
+    pub fn span(&self) -> Span {
+        match self {
+            Test::Call(name) => name.span.clone(),
+            Test::Literal(lit) => lit.span.clone(),
+            Test::Builtin(_, span) => span.clone(),
+            Test::Group(group) => group.span.clone(),
+            Test::Repeat(repeat) => repeat.span.clone(),
+        }
+    }
+}
+
+impl OutArg {
+    pub fn span(&self) -> Span {
+        match self {
+            OutArg::Gn1(span) | OutArg::Gn2(span) | OutArg::Input(span) => span.clone(),
+            OutArg::Literal(lit) => lit.span.clone(),
+        }
+    }
+}
+
+impl Program {
+    pub fn rule(&self, name: &str) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.name.text == name)
+    }
+}
+
+// Plain text forms with a rule per paragraph, which `parse` reads back.
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, ".SYNTAX {}\n", self.name.text)?;
+        for rule in self.rules.iter() {
+            writeln!(f, "{rule}\n")?;
+        }
+        writeln!(f, ".END")
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name.text)?;
+        if !self.recover.is_empty() {
+            f.write_str(" .RECOVER(")?;
+            for (i, lit) in self.recover.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "'{}'", lit.text)?;
+            }
+            f.write_str(")")?;
+        }
+        write!(f, " = {} ;", self.body)
+    }
+}
+
+impl fmt::Display for Alternative {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, seq) in self.seqs.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" / ")?;
+            }
+            write!(f, "{seq}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            match item {
+                Item::Test(test) => write!(f, "{test}")?,
+                Item::Output(output) => write!(f, "{output}")?,
+                Item::Label(label) => write!(f, ".LABEL {}", label.arg)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Test {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Test::Call(name) => f.write_str(&name.text),
+            Test::Literal(lit) => write!(f, "'{}'", lit.text),
+            Test::Builtin(builtin, _) => write!(f, "{builtin}"),
+            Test::Group(group) => write!(f, "({})", group.body),
+            Test::Repeat(repeat) => write!(f, "${}", repeat.body),
+        }
+    }
+}
+
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Builtin::Id => ".ID",
+            Builtin::Number => ".NUMBER",
+            Builtin::String => ".STRING",
+            Builtin::Empty => ".EMPTY",
+        })
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(".OUT(")?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{arg}")?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl fmt::Display for OutArg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutArg::Gn1(_) => f.write_str("*1"),
+            OutArg::Gn2(_) => f.write_str("*2"),
+            OutArg::Input(_) => f.write_str("*"),
+            OutArg::Literal(lit) => write!(f, "'{}'", lit.text),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub expected: &'static str,
+    pub pos: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected {} at line {}, column {}",
+            self.expected, self.line, self.col
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+/// Line and column, both from 1, of the byte offset `pos` into `src`.
+pub(crate) fn line_col(src: &str, pos: usize) -> (usize, usize) {
+    let before = &src[..pos];
+    let line = before.matches('\n').count() + 1;
+    (line, pos - before.rfind('\n').map_or(0, |nl| nl + 1) + 1)
+}
+
+/// Parses a grammar, which must make up all of `src`.
+pub fn parse(src: &str) -> Result<Program, ParseError> {
+    let mut p = Parser { src, pos: 0 };
+    let program = p.program()?;
+    p.skip_ws();
+    if p.pos < src.len() {
+        return Err(p.error("end of input"));
+    }
+    Ok(program)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len()
+            - rest
+                .trim_start_matches(|c: char| c.is_ascii_whitespace())
+                .len();
+    }
+
+    fn tst(&mut self, s: &str) -> Option<Span> {
+        self.skip_ws();
+        if !self.src[self.pos..].starts_with(s) {
+            return None;
+        }
+        self.pos += s.len();
+        Some(self.pos - s.len()..self.pos)
+    }
+
+    fn expect(&mut self, s: &'static str, expected: &'static str) -> Result<Span, ParseError> {
+        self.tst(s).ok_or_else(|| self.error(expected))
+    }
+
+    fn id(&mut self) -> Option<Name> {
+        self.skip_ws();
+        let rest = &self.src[self.pos..];
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        let start = self.pos;
+        self.pos += len;
+        Some(Name {
+            text: rest[..len].to_string(),
+            span: start..self.pos,
+        })
+    }
+
+    fn sr(&mut self) -> Option<Literal> {
+        self.skip_ws();
+        let rest = self.src[self.pos..].strip_prefix('\'')?;
+        let len = rest.find('\'')?;
+        let start = self.pos;
+        self.pos += len + 2;
+        Some(Literal {
+            text: rest[..len].to_string(),
+            span: start..self.pos,
+        })
+    }
+
+    fn error(&self, expected: &'static str) -> ParseError {
+        let (line, col) = line_col(self.src, self.pos);
+        ParseError {
+            expected,
+            pos: self.pos,
+            line,
+            col,
+        }
+    }
+
+    fn program(&mut self) -> Result<Program, ParseError> {
+        let start = self.expect(".SYNTAX", "'.SYNTAX'")?.start;
+        let name = self.id().ok_or_else(|| self.error("a rule name"))?;
+        let mut rules = Vec::new();
+        while let Some(rule) = self.rule()? {
+            rules.push(rule);
+        }
+        let end = self.expect(".END", "a rule or '.END'")?.end;
+        Ok(Program {
+            name,
+            rules,
+            span: start..end,
+        })
+    }
+
+    fn rule(&mut self) -> Result<Option<Rule>, ParseError> {
+        let Some(name) = self.id() else {
+            return Ok(None);
+        };
+        let mut recover = Vec::new();
+        if self.tst(".RECOVER").is_some() {
+            self.expect("(", "'('")?;
+            recover.push(self.sr().ok_or_else(|| self.error("a string"))?);
+            while let Some(lit) = self.sr() {
+                recover.push(lit);
+            }
+            self.expect(")", "a string or ')'")?;
+        }
+        self.expect("=", "'='")?;
+        let body = self.required_alternative()?;
+        let end = self.expect(";", "';'")?.end;
+        let span = name.span.start..end;
+        Ok(Some(Rule {
+            name,
+            recover,
+            body,
+            span,
+        }))
+    }
+
+    fn required_alternative(&mut self) -> Result<Alternative, ParseError> {
+        self.alternative()?
+            .ok_or_else(|| self.error("a test or an output"))
+    }
+
+    fn alternative(&mut self) -> Result<Option<Alternative>, ParseError> {
+        let Some(first) = self.sequence()? else {
+            return Ok(None);
+        };
+        let mut seqs = vec![first];
+        while self.tst("/").is_some() {
+            let seq = self
+                .sequence()?
+                .ok_or_else(|| self.error("a test or an output"))?;
+            seqs.push(seq);
+        }
+        let span = seqs[0].span.start..seqs[seqs.len() - 1].span.end;
+        Ok(Some(Alternative { seqs, span }))
+    }
+
+    fn sequence(&mut self) -> Result<Option<Sequence>, ParseError> {
+        let mut items = Vec::new();
+        while let Some(item) = self.item()? {
+            items.push(item);
+        }
+        if items.is_empty() {
+            return Ok(None);
+        }
+        let span = items[0].span().start..items[items.len() - 1].span().end;
+        Ok(Some(Sequence { items, span }))
+    }
+
+    fn item(&mut self) -> Result<Option<Item>, ParseError> {
+        if let Some(test) = self.test()? {
+            return Ok(Some(Item::Test(test)));
+        }
+        if let Some(start) = self.tst(".OUT").map(|s| s.start) {
+            self.expect("(", "'('")?;
+            let mut args = Vec::new();
+            while let Some(arg) = self.out_arg() {
+                args.push(arg);
+            }
+            let end = self.expect(")", "an output argument or ')'")?.end;
+            return Ok(Some(Item::Output(Output {
+                args,
+                span: start..end,
+            })));
+        }
+        if let Some(start) = self.tst(".LABEL").map(|s| s.start) {
+            let arg = self
+                .out_arg()
+                .ok_or_else(|| self.error("an output argument"))?;
+            let span = start..arg.span().end;
+            return Ok(Some(Item::Label(Label { arg, span })));
+        }
+        Ok(None)
+    }
+
+    fn test(&mut self) -> Result<Option<Test>, ParseError> {
+        if let Some(name) = self.id() {
+            return Ok(Some(Test::Call(name)));
+        }
+        if let Some(lit) = self.sr() {
+            return Ok(Some(Test::Literal(lit)));
+        }
+        for (s, builtin) in [
+            (".ID", Builtin::Id),
+            (".NUMBER", Builtin::Number),
+            (".STRING", Builtin::String),
+        ] {
+            if let Some(span) = self.tst(s) {
+                return Ok(Some(Test::Builtin(builtin, span)));
+            }
+        }
+        if let Some(start) = self.tst("(").map(|s| s.start) {
+            let body = self.required_alternative()?;
+            let end = self.expect(")", "')'")?.end;
+            return Ok(Some(Test::Group(Group {
+                body,
+                span: start..end,
+            })));
+        }
+        if let Some(span) = self.tst(".EMPTY") {
+            return Ok(Some(Test::Builtin(Builtin::Empty, span)));
+        }
+        if let Some(start) = self.tst("$").map(|s| s.start) {
+            let body = self.test()?.ok_or_else(|| self.error("a test"))?;
+            let span = start..body.span().end;
+            return Ok(Some(Test::Repeat(Repeat {
+                body: Box::new(body),
+                span,
+            })));
+        }
+        Ok(None)
+    }
+
+    fn out_arg(&mut self) -> Option<OutArg> {
+        if let Some(span) = self.tst("*1") {
+            return Some(OutArg::Gn1(span));
+        }
+        if let Some(span) = self.tst("*2") {
+            return Some(OutArg::Gn2(span));
+        }
+        if let Some(span) = self.tst("*") {
+            return Some(OutArg::Input(span));
+        }
+        self.sr().map(OutArg::Literal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule_names(p: &Program) -> Vec<&str> {
+        p.rules.iter().map(|r| r.name.text.as_str()).collect()
+    }
+
+    #[test]
+    fn bundled_grammars() {
+        let src = include_str!("../../meta.syn");
+        let p = parse(src).expect("meta.syn");
+        assert_eq!(p.name.text, "PROGRAM");
+        assert_eq!(
+            rule_names(&p),
+            ["OUT1", "OUTPUT", "EX3", "EX2", "EX1", "RECOVER", "ST", "PROGRAM"]
+        );
+        let st = p.rule("ST").expect("ST");
+        assert_eq!(
+            &src[st.span.clone()],
+            "ST = .ID .LABEL * (RECOVER / .EMPTY) '=' EX1 ';' .OUT('R') ;"
+        );
+        assert_eq!(p.rule("EX3").expect("EX3").body.seqs.len(), 8);
+        assert_eq!(&src[p.span.clone()], src.trim_end());
+
+        let p = parse(include_str!("../../va1.syn")).expect("va1.syn");
+        assert_eq!(p.rules.len(), 16);
+        let recover: Vec<&str> = p
+            .rule("ST")
+            .expect("ST")
+            .recover
+            .iter()
+            .map(|lit| lit.text.as_str())
+            .collect();
+        assert_eq!(recover, [";", ".ELSE", ".END"]);
+        let p = parse(include_str!("../../va2.syn")).expect("va2.syn");
+        assert_eq!(p.rule("ST").expect("ST").body.seqs.len(), 10);
+    }
+
+    #[test]
+    fn structure() {
+        let src = ".SYNTAX A A = X $(.ID .OUT('ID' *1)) .LABEL *2 / 'Y' .EMPTY ; .END";
+        let p = parse(src).expect("grammar");
+        let body = &p.rules[0].body;
+        assert_eq!(
+            &src[body.span.clone()],
+            "X $(.ID .OUT('ID' *1)) .LABEL *2 / 'Y' .EMPTY"
+        );
+        let [first, second] = &body.seqs[..] else {
+            panic!("two alternatives expected");
+        };
+        let [Item::Test(Test::Call(x)), Item::Test(Test::Repeat(rep)), Item::Label(lb)] =
+            &first.items[..]
+        else {
+            panic!("unexpected first sequence {first:?}");
+        };
+        assert_eq!(x.text, "X");
+        assert_eq!(&src[rep.span.clone()], "$(.ID .OUT('ID' *1))");
+        let Test::Group(group) = &*rep.body else {
+            panic!("group expected");
+        };
+        assert_eq!(
+            group.body.seqs[0].items[1],
+            Item::Output(Output {
+                args: vec![
+                    OutArg::Literal(Literal {
+                        text: "ID".to_string(),
+                        span: 27..31
+                    }),
+                    OutArg::Gn1(32..34)
+                ],
+                span: 22..35
+            })
+        );
+        assert_eq!(lb.arg, OutArg::Gn2(44..46));
+        assert!(matches!(
+            &second.items[..],
+            [
+                Item::Test(Test::Literal(Literal { text, .. })),
+                Item::Test(Test::Builtin(Builtin::Empty, _))
+            ] if text == "Y"
+        ));
+    }
+
+    #[test]
+    fn display() {
+        let src =
+            ".SYNTAX A\nA = X $(.ID .OUT('ID' *1)) .LABEL *2 /\n'Y' .EMPTY;\nX .RECOVER(';'\n'.END') = .OUT() ; .END";
+        let p = parse(src).expect("grammar");
+        let text = p.to_string();
+        assert_eq!(
+            text,
+            ".SYNTAX A\n\nA = X $(.ID .OUT('ID' *1)) .LABEL *2 / 'Y' .EMPTY ;\n\nX .RECOVER(';' '.END') = .OUT() ;\n\n.END\n"
+        );
+        assert_eq!(parse(&text).expect("printed grammar").to_string(), text);
+    }
+
+    #[test]
+    fn errors() {
+        let err = parse(".SYNTAX A\nA = 'X'\n.END").unwrap_err();
+        assert_eq!(err.to_string(), "expected ';' at line 3, column 1");
+        assert_eq!(err.pos, 18);
+        let err = parse(".SYNTAX A A = .OUT('X' ; .END").unwrap_err();
+        assert_eq!(err.expected, "an output argument or ')'");
+        let err = parse(".SYNTAX A A = / 'X' ; .END").unwrap_err();
+        assert_eq!(err.expected, "a test or an output");
+        let err = parse(".SYNTAX A A = 'X' ; .END .END").unwrap_err();
+        assert_eq!(err.expected, "end of input");
+        let err = parse(".SYNTAX A A .RECOVER() = 'X' ; .END").unwrap_err();
+        assert_eq!(err.expected, "a string");
+        // the machine matches literals by prefix, so `.IDX` is `.ID X`
+        let p = parse(".SYNTAX A A = .IDX ; .END").expect("grammar");
+        assert_eq!(p.rules[0].body.seqs[0].items.len(), 2);
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::{HashMap, HashSet};
+
+use mparse::{Line, MProgram};
+
+use crate::MInstr;
+
+/// What is known about the switch before an instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sw {
+    Unknown,
+    Known(bool),
+}
+
+impl Sw {
+    fn join(self, other: Sw) -> Sw {
+        if self == other {
+            self
+        } else {
+            Sw::Unknown
+        }
+    }
+}
+
+/// Instructions with the labels placed before each of them.
+struct Code {
+    labels: Vec<Vec<String>>,
+    instrs: Vec<Option<MInstr>>,
+    tail: Vec<String>,
+}
+
+/// Returns an equivalent program without branches to the next
+/// instruction, switch tests whose outcome is known, unreachable code and
+/// unreferenced or duplicate labels. Rule labels are kept.
+pub fn optimize(pgm: &MProgram<MInstr>) -> MProgram<MInstr> {
+    let mut code = Code::new(pgm);
+    loop {
+        let mut changed = code.thread();
+        changed |= code.fold();
+        changed |= code.drop_next_branches();
+        changed |= code.drop_unreachable();
+        changed |= code.merge_labels();
+        if !changed {
+            break;
+        }
+    }
+    mparse::parse(&mparse::render(&code.lines()))
+        .expect("internal error: optimized program does not assemble")
+}
+
+fn target(instr: &MInstr) -> Option<&str> {
+    match instr {
+        MInstr::B(l, _) | MInstr::BT(l, _) | MInstr::BF(l, _) => Some(l),
+        _ => None,
+    }
+}
+
+fn retarget(instr: &mut MInstr, label: String) {
+    match instr {
+        MInstr::B(l, _) | MInstr::BT(l, _) | MInstr::BF(l, _) => *l = label,
+        _ => panic!("internal error: retarget of a non branch"),
+    }
+}
+
+fn entry(instr: &MInstr) -> Option<&str> {
+    match instr {
+        MInstr::CLL(l, _) | MInstr::ADR(l, _) => Some(l),
+        _ => None,
+    }
+}
+
+impl Code {
+    fn new(pgm: &MProgram<MInstr>) -> Self {
+        let mut code = Code {
+            labels: Vec::new(),
+            instrs: Vec::new(),
+            tail: Vec::new(),
+        };
+        for line in pgm.lines() {
+            match line {
+                Line::Label(label) => code.tail.push(label),
+                Line::Instr(instr) => {
+                    code.labels.push(std::mem::take(&mut code.tail));
+                    code.instrs.push(Some(instr));
+                }
+                Line::Blk(_) => panic!("internal error: BLK in a meta program"),
+            }
+        }
+        code
+    }
+
+    fn lines(&self) -> Vec<Line<MInstr>> {
+        let mut lines = Vec::new();
+        for (labels, instr) in self.labels.iter().zip(self.instrs.iter()) {
+            lines.extend(labels.iter().cloned().map(Line::Label));
+            lines.extend(instr.clone().map(Line::Instr));
+        }
+        lines.extend(self.tail.iter().cloned().map(Line::Label));
+        lines
+    }
+
+    /// Removes deleted instructions, their labels move to the next one.
+    fn compact(&mut self) {
+        let mut labels = Vec::new();
+        let mut instrs = Vec::new();
+        let mut pending = Vec::new();
+        for (ls, instr) in self.labels.drain(..).zip(self.instrs.drain(..)) {
+            pending.extend(ls);
+            if instr.is_some() {
+                labels.push(std::mem::take(&mut pending));
+                instrs.push(instr);
+            }
+        }
+        pending.append(&mut self.tail);
+        self.labels = labels;
+        self.instrs = instrs;
+        self.tail = pending;
+    }
+
+    fn positions(&self) -> HashMap<String, usize> {
+        let mut pos = HashMap::new();
+        for (i, labels) in self.labels.iter().enumerate() {
+            for label in labels {
+                pos.insert(label.clone(), i);
+            }
+        }
+        for label in self.tail.iter() {
+            pos.insert(label.clone(), self.instrs.len());
+        }
+        pos
+    }
+
+    fn instr(&self, i: usize) -> Option<&MInstr> {
+        self.instrs.get(i).and_then(|instr| instr.as_ref())
+    }
+
+    /// Successors of instruction `i` with the switch they see, `None`
+    /// when the instruction leaves it unchanged.
+    fn successors(&self, i: usize, pos: &HashMap<String, usize>) -> Vec<(usize, Option<Sw>)> {
+        let Some(instr) = self.instr(i) else {
+            return Vec::new();
+        };
+        let known = |sw| Some(Sw::Known(sw));
+        match instr {
+            MInstr::B(l, _) => vec![(pos[l], None)],
+            MInstr::BT(l, _) => vec![(pos[l], known(true)), (i + 1, known(false))],
+            MInstr::BF(l, _) => vec![(pos[l], known(false)), (i + 1, known(true))],
+            MInstr::R | MInstr::ADR(_, _) | MInstr::Undef => Vec::new(),
+            MInstr::SET | MInstr::BE => vec![(i + 1, known(true))],
+            MInstr::TST(_) | MInstr::ID | MInstr::NUM | MInstr::SR | MInstr::CLL(_, _) => {
+                vec![(i + 1, Some(Sw::Unknown))]
+            }
+            MInstr::CL(_)
+            | MInstr::CI
+            | MInstr::GN1
+            | MInstr::GN2
+            | MInstr::LB
+            | MInstr::OUT
+            | MInstr::SYN(_) => vec![(i + 1, None)],
+        }
+    }
+
+    /// Switch state before each reachable instruction, `None` when it is
+    /// unreachable from the rule entries.
+    fn switch_states(&self) -> Vec<Option<Sw>> {
+        let pos = self.positions();
+        let mut states: Vec<Option<Sw>> = vec![None; self.instrs.len() + 1];
+        let mut work = Vec::new();
+        for instr in self.instrs.iter().flatten() {
+            if let Some(l) = entry(instr) {
+                if states[pos[l]].is_none() {
+                    states[pos[l]] = Some(Sw::Unknown);
+                    work.push(pos[l]);
+                }
+            }
+        }
+        while let Some(i) = work.pop() {
+            let before = states[i].unwrap();
+            for (next, sw) in self.successors(i, &pos) {
+                let sw = sw.unwrap_or(before);
+                let joined = match states[next] {
+                    None => sw,
+                    Some(old) => old.join(sw),
+                };
+                if states[next] != Some(joined) {
+                    states[next] = Some(joined);
+                    work.push(next);
+                }
+            }
+        }
+        states
+    }
+
+    /// Retargets branches to an unconditional branch, or to a branch
+    /// taken under the same switch value, to its final destination.
+    fn thread(&mut self) -> bool {
+        let pos = self.positions();
+        let mut changed = false;
+        for i in 0..self.instrs.len() {
+            let Some(instr) = self.instr(i) else {
+                continue;
+            };
+            let Some(mut l) = target(instr).map(String::from) else {
+                continue;
+            };
+            let mut seen = HashSet::new();
+            while let Some(next) = self.instr(pos[&l]) {
+                let follow = match (instr, next) {
+                    (_, MInstr::B(m, _)) => m,
+                    (MInstr::BT(_, _), MInstr::BT(m, _)) => m,
+                    (MInstr::BF(_, _), MInstr::BF(m, _)) => m,
+                    _ => break,
+                };
+                if !seen.insert(pos[&l]) {
+                    break;
+                }
+                l = follow.clone();
+            }
+            if Some(l.as_str()) != target(instr) {
+                retarget(self.instrs[i].as_mut().unwrap(), l);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Replaces switch tests with a known outcome.
+    fn fold(&mut self) -> bool {
+        let states = self.switch_states();
+        let mut changed = false;
+        for (i, state) in states.into_iter().enumerate().take(self.instrs.len()) {
+            let Some(Sw::Known(sw)) = state else {
+                continue;
+            };
+            let slot = &mut self.instrs[i];
+            match slot {
+                Some(MInstr::BT(l, _)) if sw => *slot = Some(MInstr::B(l.clone(), 0)),
+                Some(MInstr::BF(l, _)) if !sw => *slot = Some(MInstr::B(l.clone(), 0)),
+                Some(MInstr::BT(_, _)) | Some(MInstr::BF(_, _)) => *slot = None,
+                Some(MInstr::BE) | Some(MInstr::SET) if sw => *slot = None,
+                _ => continue,
+            }
+            changed = true;
+        }
+        if changed {
+            self.compact();
+        }
+        changed
+    }
+
+    fn drop_next_branches(&mut self) -> bool {
+        let pos = self.positions();
+        let mut changed = false;
+        for i in 0..self.instrs.len() {
+            if let Some(l) = self.instr(i).and_then(target) {
+                if pos[l] == i + 1 {
+                    self.instrs[i] = None;
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.compact();
+        }
+        changed
+    }
+
+    fn drop_unreachable(&mut self) -> bool {
+        let states = self.switch_states();
+        let mut changed = false;
+        // the prolog is not executed in sequence
+        for (i, state) in states.iter().enumerate().take(self.instrs.len()).skip(1) {
+            if state.is_none() && self.instrs[i].is_some() {
+                self.instrs[i] = None;
+                changed = true;
+            }
+        }
+        if changed {
+            self.compact();
+        }
+        changed
+    }
+
+    /// Drops unreferenced labels and renames the branch labels sharing an
+    /// instruction to one label, rule labels are kept.
+    fn merge_labels(&mut self) -> bool {
+        let rules: HashSet<String> = self
+            .instrs
+            .iter()
+            .flatten()
+            .filter_map(|instr| entry(instr).map(String::from))
+            .collect();
+        let branches: HashSet<String> = self
+            .instrs
+            .iter()
+            .flatten()
+            .filter_map(|instr| target(instr).map(String::from))
+            .collect();
+        let mut renames = HashMap::new();
+        let mut changed = false;
+        for labels in self
+            .labels
+            .iter_mut()
+            .chain(std::iter::once(&mut self.tail))
+        {
+            let before = labels.len();
+            labels.retain(|l| rules.contains(l) || branches.contains(l));
+            let keep = labels
+                .iter()
+                .find(|l| rules.contains(*l))
+                .or(labels.first())
+                .cloned();
+            if let Some(keep) = keep {
+                labels.retain(|l| {
+                    if *l == keep || rules.contains(l) {
+                        return true;
+                    }
+                    renames.insert(l.clone(), keep.clone());
+                    false
+                });
+            }
+            changed |= labels.len() != before;
+        }
+        for instr in self.instrs.iter_mut().flatten() {
+            if let Some(l) = target(instr).and_then(|l| renames.get(l)).cloned() {
+                retarget(instr, l);
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::M;
+
+    fn run(pgm: &MProgram<MInstr>, source: &str) -> String {
+        let mut m = M::new(source);
+        m.execute(pgm);
+        m.generated().expect("compilation")
+    }
+
+    #[test]
+    fn patterns() {
+        let p = mparse::parse::<MInstr>(
+            r#"
+        ADR S
+S
+        TST 'a'
+        BF A001
+        CL 'x'
+        OUT
+A001
+        BT A002
+A003
+        TST 'b'
+        BT A003
+        SET
+        BE
+        BF A004
+        CL 'y'
+        OUT
+A004
+A002
+        B A005
+A005
+        R
+        CL 'dead'
+        END
+"#,
+        )
+        .expect("program");
+        let q = optimize(&p);
+        assert_eq!(
+            mparse::render(&q.lines()),
+            "        ADR S
+S
+        TST 'a'
+        BF A001
+        CL 'x'
+        OUT
+A001
+        BT A005
+A003
+        TST 'b'
+        BT A003
+        SET
+        CL 'y'
+        OUT
+A005
+        R
+        END
+"
+        );
+        for source in ["a b", "a a", "b", "a", ""] {
+            let mut m = M::new(source);
+            m.execute(&p);
+            let mut n = M::new(source);
+            n.execute(&q);
+            assert_eq!(n.generated().ok(), m.generated().ok());
+        }
+    }
+
+    #[test]
+    fn bundled_grammars() {
+        let meta_mm = mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/meta.mm"))
+            .expect("meta machine program");
+        let meta_opt = optimize(&meta_mm);
+        assert!(meta_opt.instrs.len() < meta_mm.instrs.len());
+        let grammars = [
+            (
+                include_str!("../../meta.syn"),
+                vec![include_str!("../../meta.syn")],
+            ),
+            (
+                include_str!("../../va1.syn"),
+                vec![
+                    include_str!("../../valgol1m/fig3.va1"),
+                    include_str!("../../valgol1m/nested.va1"),
+                ],
+            ),
+            (
+                include_str!("../../va2.syn"),
+                vec![
+                    include_str!("../../valgol2m/primes.va2"),
+                    include_str!("../../valgol2m/fib.va2"),
+                ],
+            ),
+        ];
+        for (syn, sources) in grammars {
+            let mm = run(&meta_mm, syn);
+            assert_eq!(run(&meta_opt, syn), mm);
+            // the compiled compilers, optimized, compile as before
+            let compiler = mparse::parse::<MInstr>(&mm).expect("compiler");
+            let compiler_opt = optimize(&compiler);
+            assert!(compiler_opt.instrs.len() < compiler.instrs.len());
+            for source in sources {
+                assert_eq!(run(&compiler_opt, source), run(&compiler, source));
+            }
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::metasyn::{Alternative, Builtin, Item, Program, Sequence, Test};
+
+// dimensions in pixels
+const CHAR_WIDTH: usize = 8;
+const PADDING: usize = 10;
+const BOX_HEIGHT: usize = 22;
+const GAP: usize = 10;
+const RADIUS: usize = 10;
+const MARGIN: usize = 20;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+svg text { font: 13px monospace; }
+svg path { fill: none; stroke: #333; stroke-width: 1.5; }
+svg rect { stroke: #333; stroke-width: 1.5; }
+svg rect.literal, svg rect.token { fill: #dfd; }
+svg rect.rule { fill: #ddf; }
+svg rect.output { fill: #fff; stroke-dasharray: 4 2; }
+svg a:hover rect { fill: #bbf; }
+";
+
+/// Renders each rule of a grammar as an SVG railroad diagram on one HTML
+/// page, with rule calls linked to the rules. `.OUT` and `.LABEL` clauses
+/// are drawn as dashed boxes when `outputs` is set and left out otherwise.
+pub fn railroad_html(grammar: &Program, outputs: bool) -> String {
+    let defined: HashSet<&str> = grammar.rules.iter().map(|r| r.name.text.as_str()).collect();
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    writeln!(html, "<title>{}</title>", escape(&grammar.name.text)).unwrap();
+    writeln!(html, "<style>\n{STYLE}</style>\n</head>\n<body>").unwrap();
+    writeln!(
+        html,
+        "<h1>Syntax of <a href=\"#{}\">{}</a></h1>",
+        anchor(&grammar.name.text),
+        escape(&grammar.name.text)
+    )
+    .unwrap();
+    for rule in grammar.rules.iter() {
+        let name = &rule.name.text;
+        writeln!(html, "<section id=\"{}\">", anchor(name)).unwrap();
+        writeln!(html, "<h2>{}</h2>", escape(name)).unwrap();
+        let diagram = Diagram::alternative(&rule.body, outputs);
+        html.push_str(&diagram.svg(&defined));
+        if !rule.recover.is_empty() {
+            let sync: Vec<String> = rule
+                .recover
+                .iter()
+                .map(|lit| format!("<code>{}</code>", escape(&format!("'{}'", lit.text))))
+                .collect();
+            writeln!(html, "<p>Recovers at {}.</p>", sync.join(", ")).unwrap();
+        }
+        let users: Vec<&str> = grammar
+            .rules
+            .iter()
+            .filter(|r| calls(&r.body).contains(&name.as_str()))
+            .map(|r| r.name.text.as_str())
+            .collect();
+        if !users.is_empty() {
+            let links: Vec<String> = users
+                .iter()
+                .map(|user| format!("<a href=\"#{}\">{}</a>", anchor(user), escape(user)))
+                .collect();
+            writeln!(html, "<p>Used by {}.</p>", links.join(", ")).unwrap();
+        }
+        html.push_str("</section>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+#[derive(Debug, PartialEq)]
+enum Diagram {
+    /// A quoted literal.
+    Literal(String),
+    /// `.ID`, `.NUMBER` or `.STRING`.
+    Token(String),
+    Rule(String),
+    Output(String),
+    Seq(Vec<Diagram>),
+    /// The first branch on the line, the others below it.
+    Choice(Vec<Diagram>),
+    /// Zero or more times, with a bypass above and a way back below.
+    Loop(Box<Diagram>),
+    /// An empty stretch of line.
+    Skip,
+}
+
+/// Width, and extent above and below the line through the diagram.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Size {
+    width: usize,
+    up: usize,
+    down: usize,
+}
+
+impl Diagram {
+    fn alternative(alt: &Alternative, outputs: bool) -> Self {
+        let mut branches: Vec<Diagram> = alt
+            .seqs
+            .iter()
+            .map(|seq| Diagram::sequence(seq, outputs))
+            .collect();
+        if branches.len() == 1 {
+            return branches.remove(0);
+        }
+        Diagram::Choice(branches)
+    }
+
+    fn sequence(seq: &Sequence, outputs: bool) -> Self {
+        let mut parts: Vec<Diagram> = seq
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Test(test) => Some(Diagram::test(test, outputs)),
+                Item::Output(output) if outputs => Some(Diagram::Output(output.to_string())),
+                Item::Label(label) if outputs => {
+                    Some(Diagram::Output(format!(".LABEL {}", label.arg)))
+                }
+                _ => None,
+            })
+            .filter(|part| *part != Diagram::Skip)
+            .collect();
+        match parts.len() {
+            0 => Diagram::Skip,
+            1 => parts.remove(0),
+            _ => Diagram::Seq(parts),
+        }
+    }
+
+    fn test(test: &Test, outputs: bool) -> Self {
+        match test {
+            Test::Call(name) => Diagram::Rule(name.text.clone()),
+            Test::Literal(lit) => Diagram::Literal(format!("'{}'", lit.text)),
+            Test::Builtin(Builtin::Empty, _) => Diagram::Skip,
+            Test::Builtin(builtin, _) => Diagram::Token(builtin.to_string()),
+            Test::Group(group) => Diagram::alternative(&group.body, outputs),
+            Test::Repeat(repeat) => Diagram::Loop(Box::new(Diagram::test(&repeat.body, outputs))),
+        }
+    }
+
+    fn size(&self) -> Size {
+        match self {
+            Diagram::Literal(s) | Diagram::Token(s) | Diagram::Rule(s) | Diagram::Output(s) => {
+                Size {
+                    width: s.chars().count() * CHAR_WIDTH + 2 * PADDING,
+                    up: BOX_HEIGHT / 2,
+                    down: BOX_HEIGHT / 2,
+                }
+            }
+            Diagram::Seq(parts) => {
+                let sizes: Vec<Size> = parts.iter().map(Diagram::size).collect();
+                Size {
+                    width: sizes.iter().map(|s| s.width).sum::<usize>() + GAP * (sizes.len() - 1),
+                    up: sizes.iter().map(|s| s.up).max().unwrap_or(0),
+                    down: sizes.iter().map(|s| s.down).max().unwrap_or(0),
+                }
+            }
+            Diagram::Choice(branches) => {
+                let first = branches[0].size();
+                let mut down = first.down;
+                let mut width = first.width;
+                for branch in branches[1..].iter() {
+                    let size = branch.size();
+                    down += separation(down, size.up) + size.down;
+                    width = width.max(size.width);
+                }
+                Size {
+                    width: width + 4 * RADIUS,
+                    up: first.up,
+                    down,
+                }
+            }
+            Diagram::Loop(body) => {
+                let size = body.size();
+                Size {
+                    width: size.width + 4 * RADIUS,
+                    up: (size.up + GAP).max(2 * RADIUS),
+                    down: (size.down + GAP).max(2 * RADIUS),
+                }
+            }
+            Diagram::Skip => Size {
+                width: 0,
+                up: 0,
+                down: 0,
+            },
+        }
+    }
+
+    fn svg(&self, rules: &HashSet<&str>) -> String {
+        let size = self.size();
+        let width = size.width + 2 * MARGIN + 2 * GAP;
+        let height = size.up + size.down + 2 * MARGIN;
+        let (x, y) = (MARGIN, MARGIN + size.up);
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">"
+        )
+        .unwrap();
+        // start and end bars
+        let end = x + 2 * GAP + size.width;
+        writeln!(svg, "<path d=\"M{x} {} V{}\"/>", y - 8, y + 8).unwrap();
+        writeln!(svg, "<path d=\"M{end} {} V{}\"/>", y - 8, y + 8).unwrap();
+        writeln!(svg, "<path d=\"M{x} {y} H{}\"/>", x + GAP).unwrap();
+        self.draw(x + GAP, y, rules, &mut svg);
+        writeln!(svg, "<path d=\"M{} {y} H{end}\"/>", x + GAP + size.width).unwrap();
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    fn draw(&self, x: usize, y: usize, rules: &HashSet<&str>, svg: &mut String) {
+        let size = self.size();
+        match self {
+            Diagram::Literal(s) => draw_box(svg, x, y, size.width, s, "literal", 10),
+            Diagram::Token(s) => draw_box(svg, x, y, size.width, s, "token", 10),
+            Diagram::Output(s) => draw_box(svg, x, y, size.width, s, "output", 0),
+            Diagram::Rule(s) if rules.contains(s.as_str()) => {
+                write!(svg, "<a href=\"#{}\">", anchor(s)).unwrap();
+                draw_box(svg, x, y, size.width, s, "rule", 0);
+                svg.push_str("</a>\n");
+            }
+            Diagram::Rule(s) => draw_box(svg, x, y, size.width, s, "rule", 0),
+            Diagram::Seq(parts) => {
+                let mut x = x;
+                for (i, part) in parts.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(svg, "<path d=\"M{x} {y} h{GAP}\"/>").unwrap();
+                        x += GAP;
+                    }
+                    part.draw(x, y, rules, svg);
+                    x += part.size().width;
+                }
+            }
+            Diagram::Choice(branches) => {
+                let (left, right) = (x + 2 * RADIUS, x + size.width - 2 * RADIUS);
+                let mut bottom = y;
+                for (i, branch) in branches.iter().enumerate() {
+                    let bsize = branch.size();
+                    let by = match i {
+                        0 => y,
+                        _ => bottom + separation(bottom - y, bsize.up),
+                    };
+                    writeln!(svg, "<path d=\"M{x} {y} {}\"/>", bend(x, y, by)).unwrap();
+                    branch.draw(left, by, rules, svg);
+                    writeln!(
+                        svg,
+                        "<path d=\"M{} {by} H{right} {}\"/>",
+                        left + bsize.width,
+                        bend(right, by, y)
+                    )
+                    .unwrap();
+                    bottom = by + bsize.down;
+                }
+            }
+            Diagram::Loop(body) => {
+                let bsize = body.size();
+                let (left, right) = (x + 2 * RADIUS, x + 2 * RADIUS + bsize.width);
+                let (over, under) = (y - size.up, y + size.down);
+                let end = x + size.width;
+                let r = RADIUS;
+                writeln!(svg, "<path d=\"M{x} {y} H{left}\"/>").unwrap();
+                body.draw(left, y, rules, svg);
+                writeln!(svg, "<path d=\"M{right} {y} H{end}\"/>").unwrap();
+                // bypass
+                writeln!(
+                    svg,
+                    "<path d=\"M{x} {y} {} H{} {}\"/>",
+                    bend(x, y, over),
+                    end - 2 * r,
+                    bend(end - 2 * r, over, y)
+                )
+                .unwrap();
+                // way back, turning around on both sides
+                writeln!(
+                    svg,
+                    "<path d=\"M{right} {y} Q{} {y} {0} {} V{} Q{0} {under} {right} {under} \
+                     H{left} Q{} {under} {3} {} V{} Q{3} {y} {left} {y}\"/>",
+                    right + r,
+                    y + r,
+                    under - r,
+                    left - r,
+                    under - r,
+                    y + r,
+                )
+                .unwrap();
+            }
+            Diagram::Skip => (),
+        }
+    }
+}
+
+// space between the line of a branch ending `down` below the choice line
+// and the line of the next branch, reaching `up` above its line
+fn separation(down: usize, up: usize) -> usize {
+    (down + GAP + up).max(2 * RADIUS) - down
+}
+
+// continues a path from heading right at (x, y1) to heading right at
+// (x + 2r, y2), straight on when y1 is y2
+fn bend(x: usize, y1: usize, y2: usize) -> String {
+    let r = RADIUS;
+    let (turn1, turn2) = match y2.cmp(&y1) {
+        Ordering::Equal => return format!("H{}", x + 2 * r),
+        Ordering::Greater => (y1 + r, y2 - r),
+        Ordering::Less => (y1 - r, y2 + r),
+    };
+    format!(
+        "Q{} {y1} {0} {turn1} V{turn2} Q{0} {y2} {} {y2}",
+        x + r,
+        x + 2 * r
+    )
+}
+
+fn draw_box(
+    svg: &mut String,
+    x: usize,
+    y: usize,
+    width: usize,
+    text: &str,
+    class: &str,
+    rx: usize,
+) {
+    writeln!(
+        svg,
+        "<rect class=\"{class}\" x=\"{x}\" y=\"{}\" width=\"{width}\" height=\"{BOX_HEIGHT}\" rx=\"{rx}\"/>",
+        y - BOX_HEIGHT / 2
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+        x + width / 2,
+        y + 4,
+        escape(text)
+    )
+    .unwrap();
+}
+
+fn calls(alt: &Alternative) -> Vec<&str> {
+    fn test<'a>(t: &'a Test, names: &mut Vec<&'a str>) {
+        match t {
+            Test::Call(name) => names.push(&name.text),
+            Test::Group(group) => names.extend(calls(&group.body)),
+            Test::Repeat(repeat) => test(&repeat.body, names),
+            Test::Literal(_) | Test::Builtin(_, _) => (),
+        }
+    }
+    let mut names = Vec::new();
+    for seq in alt.seqs.iter() {
+        for item in seq.items.iter() {
+            if let Item::Test(t) = item {
+                test(t, &mut names);
+            }
+        }
+    }
+    names
+}
+
+fn anchor(name: &str) -> String {
+    format!("rule-{name}")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metasyn;
+
+    fn diagram(body: &str, outputs: bool) -> Diagram {
+        let p = metasyn::parse(&format!(".SYNTAX A A = {body} ; .END")).expect("grammar");
+        Diagram::alternative(&p.rules[0].body, outputs)
+    }
+
+    #[test]
+    fn shapes() {
+        assert_eq!(
+            diagram("'X' .OUT('A') / .EMPTY", false),
+            Diagram::Choice(vec![Diagram::Literal("'X'".to_string()), Diagram::Skip])
+        );
+        assert_eq!(
+            diagram("$(.ID .LABEL *1)", true),
+            Diagram::Loop(Box::new(Diagram::Seq(vec![
+                Diagram::Token(".ID".to_string()),
+                Diagram::Output(".LABEL *1".to_string())
+            ])))
+        );
+        let size = |body| diagram(body, false).size();
+        // a box
+        assert_eq!(
+            size("B"),
+            Size {
+                width: 28,
+                up: 11,
+                down: 11
+            }
+        );
+        // two boxes and a gap
+        assert_eq!(size("B 'C'").width, 28 + GAP + 44);
+        // bends on both sides, the second branch below the first
+        assert_eq!(
+            size("B / 'C'"),
+            Size {
+                width: 44 + 4 * RADIUS,
+                up: 11,
+                down: 11 + GAP + 11 + 11
+            }
+        );
+        assert_eq!(
+            size("$B"),
+            Size {
+                width: 28 + 4 * RADIUS,
+                up: 2 * RADIUS + 1,
+                down: 2 * RADIUS + 1
+            }
+        );
+    }
+
+    #[test]
+    fn page() {
+        let p = metasyn::parse(include_str!("../../meta.syn")).expect("meta.syn");
+        let html = railroad_html(&p, false);
+        assert_eq!(html.matches("<section id=").count(), 8);
+        assert_eq!(html.matches("<svg ").count(), 8);
+        assert!(html.contains("<section id=\"rule-EX3\">"));
+        assert!(html.contains("<a href=\"#rule-EX1\"><rect class=\"rule\""));
+        assert!(html.contains(
+            "<p>Used by <a href=\"#rule-EX3\">EX3</a>, <a href=\"#rule-ST\">ST</a>.</p>"
+        ));
+        assert!(html.contains("&#39;.OUT&#39;"));
+        assert!(!html.contains("class=\"output\""));
+        let html = railroad_html(&p, true);
+        assert!(html.contains(".OUT(&#39;CLL&#39; *)</text>"));
+        let p = metasyn::parse(include_str!("../../va1.syn")).expect("va1.syn");
+        assert!(railroad_html(&p, false).contains(
+            "<p>Recovers at <code>&#39;;&#39;</code>, <code>&#39;.ELSE&#39;</code>, <code>&#39;.END&#39;</code>.</p>"
+        ));
+        // calls of undefined rules are not linked
+        let p = metasyn::parse(".SYNTAX A A = B ; .END").expect("grammar");
+        assert!(!railroad_html(&p, false).contains("href=\"#rule-B\""));
+    }
+}
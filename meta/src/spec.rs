@@ -0,0 +1,322 @@
+//! Test cases for a compiled grammar, written as
+//!
+//! ```text
+//! # comments before the first case
+//! === precedence @ EXP
+//! A + B * 2
+//! --- output
+//!         LD  A
+//!         LD  B
+//!         LDL 2
+//!         MLT
+//!         ADD
+//!
+//! === missing operand
+//! .BEGIN
+//! .REAL X;
+//! X + = X
+//! .END
+//! --- error at 3:5
+//! ```
+//!
+//! A case is a `===` line naming it and optionally the rule to start at,
+//! its input, and either the output expected, compared without trailing
+//! blanks, or `--- error`, with the line and column of the first syntax
+//! error if given, whether the grammar recovers from it or not.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::metasyn::line_col;
+use crate::{MInstr, M};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expect {
+    Output(String),
+    Failure(Option<(usize, usize)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub name: String,
+    /// The rule to start at instead of the `ADR` target.
+    pub rule: Option<String>,
+    pub input: String,
+    pub expect: Expect,
+    /// Of the `===` line in the spec.
+    pub line: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SpecError {
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for SpecError {}
+
+pub fn parse_spec(src: &str) -> Result<Vec<Case>, SpecError> {
+    let mut cases = Vec::new();
+    let mut lines = src.lines().zip(1..).peekable();
+    while let Some((line, n)) = lines.next() {
+        let Some(header) = line.strip_prefix("===") else {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            return Err(SpecError {
+                line: n,
+                message: "expected a === case",
+            });
+        };
+        let (name, rule) = match header.split_once('@') {
+            Some((name, rule)) => (name.trim(), Some(rule.trim().to_string())),
+            None => (header.trim(), None),
+        };
+        if name.is_empty() || rule.as_deref() == Some("") {
+            return Err(SpecError {
+                line: n,
+                message: "expected a case name and an optional @ rule",
+            });
+        }
+        let mut input = Vec::new();
+        let section = loop {
+            match lines.next() {
+                Some((line, _)) if line.starts_with("---") => break line,
+                Some((line, _)) => input.push(line),
+                None => {
+                    return Err(SpecError {
+                        line: n,
+                        message: "missing --- output or --- error",
+                    })
+                }
+            }
+        };
+        let mut body = Vec::new();
+        while let Some((line, _)) = lines.next_if(|(line, _)| !line.starts_with("===")) {
+            body.push(line.trim_end());
+        }
+        while body.last() == Some(&"") {
+            body.pop();
+        }
+        let expect = match section["---".len()..].trim() {
+            "output" => Expect::Output(body.join("\n")),
+            error if error.starts_with("error") => {
+                let Some(at) = position(error["error".len()..].trim()) else {
+                    return Err(SpecError {
+                        line: n,
+                        message: "expected --- error at LINE:COLUMN",
+                    });
+                };
+                if !body.is_empty() {
+                    return Err(SpecError {
+                        line: n,
+                        message: "unexpected lines after --- error",
+                    });
+                }
+                Expect::Failure(at)
+            }
+            _ => {
+                return Err(SpecError {
+                    line: n,
+                    message: "expected --- output or --- error",
+                })
+            }
+        };
+        cases.push(Case {
+            name: name.to_string(),
+            rule,
+            input: input.join("\n"),
+            expect,
+            line: n,
+        });
+    }
+    Ok(cases)
+}
+
+// `at L:C`, or nothing
+fn position(s: &str) -> Option<Option<(usize, usize)>> {
+    if s.is_empty() {
+        return Some(None);
+    }
+    let (line, col) = s.strip_prefix("at")?.trim().split_once(':')?;
+    Some(Some((line.parse().ok()?, col.parse().ok()?)))
+}
+
+/// Runs a case, explaining how it failed.
+pub fn run_case(pgm: &mparse::MProgram<MInstr>, case: &Case) -> Result<(), String> {
+    let mut m = M::new(&case.input);
+    match &case.rule {
+        Some(rule) => m.execute_rule(pgm, rule).map_err(|e| e.to_string())?,
+        None => m.execute(pgm),
+    }
+    // the first error, recovered from or not
+    let stopped = match m.diagnostics().first() {
+        Some(d) => (d.line, d.col),
+        None => line_col(&case.input, case.input.len() - m.left().len()),
+    };
+    match (&case.expect, m.generated()) {
+        (Expect::Output(expected), Ok(output)) => {
+            let output: Vec<&str> = output.lines().map(str::trim_end).collect();
+            let mut output = output.as_slice();
+            while let [rest @ .., ""] = output {
+                output = rest;
+            }
+            let expected: Vec<&str> = expected.lines().collect();
+            if output == expected.as_slice() {
+                return Ok(());
+            }
+            Err(diff(&expected, output))
+        }
+        (Expect::Output(_), Err(_)) => Err(format!(
+            "failed at {}:{}, expected output",
+            stopped.0, stopped.1
+        )),
+        (Expect::Failure(_), Ok(_)) => Err("recognized, expected failure".to_string()),
+        (Expect::Failure(Some(at)), Err(_)) if *at != stopped => Err(format!(
+            "failed at {}:{}, expected at {}:{}",
+            stopped.0, stopped.1, at.0, at.1
+        )),
+        (Expect::Failure(_), Err(_)) => Ok(()),
+    }
+}
+
+// lines only expected marked -, lines only output marked +
+fn diff(expected: &[&str], output: &[&str]) -> String {
+    // longest common subsequences of the suffixes
+    let mut lcs = vec![vec![0; output.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..output.len()).rev() {
+            lcs[i][j] = match expected[i] == output[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+    let mut out = String::from("--- expected\n+++ output\n");
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < output.len() {
+        if i < expected.len() && j < output.len() && expected[i] == output[j] {
+            out.push_str(&format!(" {}\n", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j == output.len() || (i < expected.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("-{}\n", expected[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+{}\n", output[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn va1_mm() -> mparse::MProgram<MInstr> {
+        mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/va1.mm")).expect("program")
+    }
+
+    #[test]
+    fn parse() {
+        let cases = parse_spec(
+            "# va1\n\n=== sum @ EXP\nA + 1\n--- output\n        LD A\n\n=== bad\n\n.BEGIN\n--- error at 2:7\n\n=== any\nX\n--- error\n",
+        )
+        .expect("spec");
+        assert_eq!(
+            cases,
+            vec![
+                Case {
+                    name: "sum".to_string(),
+                    rule: Some("EXP".to_string()),
+                    input: "A + 1".to_string(),
+                    expect: Expect::Output("        LD A".to_string()),
+                    line: 3,
+                },
+                Case {
+                    name: "bad".to_string(),
+                    rule: None,
+                    input: "\n.BEGIN".to_string(),
+                    expect: Expect::Failure(Some((2, 7))),
+                    line: 8,
+                },
+                Case {
+                    name: "any".to_string(),
+                    rule: None,
+                    input: "X".to_string(),
+                    expect: Expect::Failure(None),
+                    line: 13,
+                },
+            ]
+        );
+        for (spec, line, message) in [
+            ("A\n", 1, "expected a === case"),
+            (
+                "=== @ EXP\n--- error\n",
+                1,
+                "expected a case name and an optional @ rule",
+            ),
+            ("=== a\nX\n", 1, "missing --- output or --- error"),
+            (
+                "=== a\nX\n--- outptu\n",
+                1,
+                "expected --- output or --- error",
+            ),
+            (
+                "=== a\nX\n--- error at 1\n",
+                1,
+                "expected --- error at LINE:COLUMN",
+            ),
+            (
+                "=== a\nX\n--- error\nY\n",
+                1,
+                "unexpected lines after --- error",
+            ),
+        ] {
+            assert_eq!(parse_spec(spec), Err(SpecError { line, message }), "{spec}");
+        }
+    }
+
+    #[test]
+    fn bundled_spec() {
+        let pgm = va1_mm();
+        let cases = parse_spec(include_str!("../../va1.spec")).expect("spec");
+        assert!(cases.len() > 3);
+        for case in cases.iter() {
+            assert_eq!(run_case(&pgm, case), Ok(()), "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn failures() {
+        let pgm = va1_mm();
+        let run = |spec: &str| {
+            let cases = parse_spec(spec).expect("spec");
+            run_case(&pgm, &cases[0]).expect_err("failure")
+        };
+        assert_eq!(
+            run("=== a @ EXP\nA + B\n--- output\n        LD  A\n        LD  C\n        ADD\n"),
+            "--- expected\n+++ output\n         LD  A\n-        LD  C\n+        LD  B\n         ADD\n"
+        );
+        assert_eq!(
+            run("=== a @ EXP\nA +\n--- output\n"),
+            "failed at 1:4, expected output"
+        );
+        assert_eq!(
+            run("=== a @ EXP\nA +\n--- error at 1:3\n"),
+            "failed at 1:4, expected at 1:3"
+        );
+        assert_eq!(
+            run("=== a @ EXP\nA\n--- error\n"),
+            "recognized, expected failure"
+        );
+        assert_eq!(run("=== a @ EXPR\nA\n--- error\n"), "unknown label EXPR");
+    }
+}
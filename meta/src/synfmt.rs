@@ -0,0 +1,173 @@
+use crate::metasyn::{Alternative, Item, Program, Sequence, Test};
+
+const WIDTH: usize = 80;
+
+/// Prints a grammar in canonical layout: a blank line between rules, the
+/// alternatives of a rule on lines of their own with `/` under `=`,
+/// groups of alternatives that do not fit on the line broken the same way
+/// under their `(`, and sequences too long for the line continued under
+/// their first item.
+pub fn format_grammar(grammar: &Program) -> String {
+    let mut out = format!(".SYNTAX {}\n\n", grammar.name.text);
+    for rule in grammar.rules.iter() {
+        out.push_str(&rule.name.text);
+        for (i, lit) in rule.recover.iter().enumerate() {
+            out.push_str(if i == 0 { " .RECOVER(" } else { " " });
+            out.push_str(&format!("'{}'", lit.text));
+        }
+        if !rule.recover.is_empty() {
+            out.push(')');
+        }
+        out.push(' ');
+        let col = column(&out);
+        out.push_str("= ");
+        alternative(&mut out, &rule.body, col);
+        out.push_str(" ;\n\n");
+    }
+    out.push_str(".END\n");
+    out
+}
+
+fn column(out: &str) -> usize {
+    out.len() - out.rfind('\n').map_or(0, |nl| nl + 1)
+}
+
+// the first sequence goes at the current position, the others on new
+// lines starting with `/` at `col`
+fn alternative(out: &mut String, alt: &Alternative, col: usize) {
+    for (i, seq) in alt.seqs.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+            out.push_str(&" ".repeat(col));
+            out.push_str("/ ");
+        }
+        sequence(out, seq);
+    }
+}
+
+fn sequence(out: &mut String, seq: &Sequence) {
+    let col = column(out);
+    for (i, item) in seq.items.iter().enumerate() {
+        if i > 0 && column(out) + 1 + first_line(item) > WIDTH {
+            out.push('\n');
+            out.push_str(&" ".repeat(col));
+        } else if i > 0 {
+            out.push(' ');
+        }
+        match item {
+            Item::Test(t) => test(out, t),
+            _ => out.push_str(&inline(item)),
+        }
+    }
+}
+
+fn inline(item: &Item) -> String {
+    match item {
+        Item::Test(t) => t.to_string(),
+        Item::Output(output) => output.to_string(),
+        Item::Label(label) => format!(".LABEL {}", label.arg),
+    }
+}
+
+// the length up to the first line break, should `item` need breaking
+fn first_line(item: &Item) -> usize {
+    fn test(t: &Test) -> usize {
+        match t {
+            Test::Group(group) if group.body.seqs.len() > 1 => {
+                2 + group.body.seqs[0].to_string().len()
+            }
+            Test::Repeat(repeat) => 1 + test(&repeat.body),
+            _ => t.to_string().len(),
+        }
+    }
+    match item {
+        Item::Test(t) => test(t),
+        _ => inline(item).len(),
+    }
+}
+
+fn test(out: &mut String, t: &Test) {
+    match t {
+        Test::Group(group) => {
+            let inline = t.to_string();
+            if group.body.seqs.len() < 2 || column(out) + inline.len() <= WIDTH {
+                out.push_str(&inline);
+                return;
+            }
+            let col = column(out);
+            out.push_str("( ");
+            alternative(out, &group.body, col);
+            out.push_str(" )");
+        }
+        Test::Repeat(repeat) => {
+            out.push('$');
+            test(out, &repeat.body);
+        }
+        _ => out.push_str(&t.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metasyn;
+    use crate::MInstr;
+
+    fn compile(grammar: &str) -> String {
+        let meta_mm = mparse::parse::<MInstr>(include_str!("../../meta_mach_pgms/meta.mm"))
+            .expect("meta machine program");
+        let mut m = crate::M::new(grammar);
+        m.execute(&meta_mm);
+        m.generated().expect("compilation")
+    }
+
+    fn format(src: &str) -> String {
+        format_grammar(&metasyn::parse(src).expect("grammar"))
+    }
+
+    #[test]
+    fn same_code() {
+        for src in [
+            include_str!("../../meta.syn"),
+            include_str!("../../va1.syn"),
+            include_str!("../../va2.syn"),
+        ] {
+            let formatted = format(src);
+            assert_eq!(compile(&formatted), compile(src));
+            assert_eq!(format(&formatted), formatted);
+        }
+    }
+
+    #[test]
+    fn bundled_grammars_formatted() {
+        for (name, src) in [
+            ("meta.syn", include_str!("../../meta.syn")),
+            ("va1.syn", include_str!("../../va1.syn")),
+            ("va2.syn", include_str!("../../va2.syn")),
+        ] {
+            assert_eq!(format(src), src, "{name}");
+        }
+    }
+
+    #[test]
+    fn layout() {
+        let src = include_str!("../../meta.syn");
+        let formatted = format(src);
+        assert!(formatted.contains(
+            "\nOUT1 = '*1' .OUT('GN1')\n     / '*2' .OUT('GN2')\n     / '*' .OUT('CI')\n     / .STRING .OUT('CL ' *) ;\n"
+        ));
+        assert!(formatted.contains("\nEX3 = .ID .OUT('CLL' *)\n    / .STRING .OUT('TST ' *)\n"));
+        assert!(formatted.contains("\n    / '$' .LABEL *1 EX3 .OUT('BT ' *1) .OUT('SET') ;\n"));
+        assert!(formatted.contains("\nEX1 = EX2 $('/' .OUT('BT ' *1) EX2) .LABEL *1 ;\n"));
+
+        let formatted = format(include_str!("../../va1.syn"));
+        assert!(formatted.contains(
+            "\nRELATION = EXP1 ( '.=' EXP1 .OUT('EQU')\n                / '.<>' EXP1 .OUT('NEQ')\n"
+        ));
+        assert!(formatted.contains("\n                / .EMPTY ) ;\n"));
+        assert!(formatted.contains(
+            "\nST .RECOVER(';' '.ELSE' '.END') = IOST\n                                / ASSIGNST\n"
+        ));
+        assert!(formatted.lines().all(|line| line.len() <= WIDTH));
+    }
+}
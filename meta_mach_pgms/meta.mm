@@ -171,12 +171,47 @@ A033
 A030 
 A034 
         R 
+RECOVER
+        TST  '.RECOVER'
+        BF  A035 
+        TST  '('
+        BE 
+        SR 
+        BE 
+        CL  'SYN '
+        CI 
+        OUT 
+A036 
+        SR 
+        BF  A037 
+        CL  'SYN '
+        CI 
+        OUT 
+A037 
+A038 
+        BT  A036 
+        SET 
+        BE 
+        TST  ')'
+        BE 
+A035 
+A039 
+        R 
 ST
         ID 
-        BF  A035 
+        BF  A040 
         LB 
         CI 
         OUT 
+        CLL RECOVER
+        BF  A041 
+A041 
+        BT  A042 
+        SET 
+        BF  A043 
+A043 
+A042 
+        BE 
         TST  '='
         BE 
         CLL EX1
@@ -185,28 +220,28 @@ ST
         BE 
         CL  'R'
         OUT 
-A035 
-A036 
+A040 
+A044 
         R 
 PROGRAM
         TST  '.SYNTAX'
-        BF  A037 
+        BF  A045 
         ID 
         BE 
         CL  'ADR'
         CI 
         OUT 
-A038 
+A046 
         CLL ST
-        BT  A038 
+        BT  A046 
         SET 
         BE 
         TST  '.END'
         BE 
         CL  'END'
         OUT 
-A037 
-A039 
+A045 
+A047 
         R 
         END 
         
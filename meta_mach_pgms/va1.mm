@@ -21,74 +21,174 @@ A003
         TST  ')'
         BE 
 A004 
+        BT  A002 
+        TST  '-'
+        BF  A005 
+        CLL PRIMARY
+        BE 
+        CL  'NEG'
+        OUT 
+A005 
+        BT  A002 
+        TST  '.NOT'
+        BF  A006 
+        CLL PRIMARY
+        BE 
+        CL  'NOT'
+        OUT 
+A006 
 A002 
         R 
 TERM
         CLL PRIMARY
-        BF  A005 
-A006 
-        TST  '*'
         BF  A007 
+A008 
+        TST  '*'
+        BF  A009 
         CLL PRIMARY
         BE 
         CL  'MLT'
         OUT 
-A007 
-A008 
-        BT  A006 
+A009 
+        BT  A010 
+        TST  '/'
+        BF  A011 
+        CLL PRIMARY
+        BE 
+        CL  'DIV'
+        OUT 
+A011 
+A010 
+        BT  A008 
         SET 
         BE 
-A005 
-A009 
+A007 
+A012 
         R 
 EXP1
         CLL TERM
-        BF  A010 
-A011 
+        BF  A013 
+A014 
         TST  '+'
-        BF  A012 
+        BF  A015 
         CLL TERM
         BE 
         CL  'ADD'
         OUT 
-A012 
-        BT  A013 
+A015 
+        BT  A016 
         TST  '-'
-        BF  A014 
+        BF  A017 
         CLL TERM
         BE 
         CL  'SUB'
         OUT 
-A014 
-A013 
-        BT  A011 
+A017 
+A016 
+        BT  A014 
         SET 
         BE 
-A010 
-A015 
+A013 
+A018 
         R 
-EXP
+RELATION
         CLL EXP1
-        BF  A016 
+        BF  A019 
         TST  '.='
-        BF  A017 
+        BF  A020 
         CLL EXP1
         BE 
         CL  'EQU'
         OUT 
-A017 
-        BT  A018 
+A020 
+        BT  A021 
+        TST  '.<>'
+        BF  A022 
+        CLL EXP1
+        BE 
+        CL  'NEQ'
+        OUT 
+A022 
+        BT  A021 
+        TST  '.<='
+        BF  A023 
+        CLL EXP1
+        BE 
+        CL  'LEQ'
+        OUT 
+A023 
+        BT  A021 
+        TST  '.>='
+        BF  A024 
+        CLL EXP1
+        BE 
+        CL  'GEQ'
+        OUT 
+A024 
+        BT  A021 
+        TST  '.<'
+        BF  A025 
+        CLL EXP1
+        BE 
+        CL  'LSS'
+        OUT 
+A025 
+        BT  A021 
+        TST  '.>'
+        BF  A026 
+        CLL EXP1
+        BE 
+        CL  'GTR'
+        OUT 
+A026 
+        BT  A021 
         SET 
-        BF  A019 
+        BF  A027 
+A027 
+A021 
+        BE 
 A019 
-A018 
+A028 
+        R 
+CONJUNCTION
+        CLL RELATION
+        BF  A029 
+A030 
+        TST  '.AND'
+        BF  A031 
+        CLL RELATION
         BE 
-A016 
-A020 
+        CL  'AND'
+        OUT 
+A031 
+A032 
+        BT  A030 
+        SET 
+        BE 
+A029 
+A033 
+        R 
+EXP
+        CLL CONJUNCTION
+        BF  A034 
+A035 
+        TST  '.OR'
+        BF  A036 
+        CLL CONJUNCTION
+        BE 
+        CL  'OR'
+        OUT 
+A036 
+A037 
+        BT  A035 
+        SET 
+        BE 
+A034 
+A038 
         R 
 ASSIGNST
         CLL EXP
-        BF  A021 
+        BF  A039 
         TST  '='
         BE 
         ID 
@@ -96,12 +196,12 @@ ASSIGNST
         CL  'ST '
         CI 
         OUT 
-A021 
-A022 
+A039 
+A040 
         R 
 UNTILST
         TST  '.UNTIL'
-        BF  A023 
+        BF  A041 
         LB 
         GN1 
         OUT 
@@ -120,12 +220,12 @@ UNTILST
         LB 
         GN2 
         OUT 
-A023 
-A024 
+A041 
+A042 
         R 
 CONDITIONALST
         TST  '.IF'
-        BF  A025 
+        BF  A043 
         CLL EXP
         BE 
         TST  '.THEN'
@@ -148,12 +248,12 @@ CONDITIONALST
         LB 
         GN2 
         OUT 
-A025 
-A026 
+A043 
+A044 
         R 
 IOST
         TST  'EDIT'
-        BF  A027 
+        BF  A045 
         TST  '('
         BE 
         CLL EXP
@@ -161,51 +261,83 @@ IOST
         TST  ','
         BE 
         SR 
-        BE 
+        BF  A046 
         CL  'EDT'
         CI 
         OUT 
+A046 
+        BT  A047 
+        CLL EXP
+        BF  A048 
+        TST  ','
+        BE 
+        NUM 
+        BE 
+        CL  'LDL'
+        CI 
+        OUT 
+        TST  ','
+        BE 
+        NUM 
+        BE 
+        CL  'LDL'
+        CI 
+        OUT 
+        CL  'EDN'
+        OUT 
+A048 
+A047 
+        BE 
         TST  ')'
         BE 
-A027 
-        BT  A028 
+A045 
+        BT  A049 
         TST  'PRINT'
-        BF  A029 
+        BF  A050 
         CL  'PNT'
         OUT 
-A029 
-A028 
+A050 
+        BT  A049 
+        TST  'READ'
+        BF  A051 
+        ID 
+        BE 
+        CL  'RD '
+        CI 
+        OUT 
+A051 
+A049 
         R 
 IDSEQ1
         ID 
-        BF  A030 
+        BF  A052 
         LB 
         CI 
         OUT 
         CL  'BLK 1'
         OUT 
-A030 
-A031 
+A052 
+A053 
         R 
 IDSEQ
         CLL IDSEQ1
-        BF  A032 
-A033 
+        BF  A054 
+A055 
         TST  ','
-        BF  A034 
+        BF  A056 
         CLL IDSEQ1
         BE 
-A034 
-A035 
-        BT  A033 
+A056 
+A057 
+        BT  A055 
         SET 
         BE 
-A032 
-A036 
+A054 
+A058 
         R 
 DEC
         TST  '.REAL'
-        BF  A037 
+        BF  A059 
         CL  'B '
         GN1 
         OUT 
@@ -214,71 +346,74 @@ DEC
         LB 
         GN1 
         OUT 
-A037 
-A038 
+A059 
+A060 
         R 
 BLOCK
         TST  '.BEGIN'
-        BF  A039 
+        BF  A061 
         CLL DEC
-        BF  A040 
+        BF  A062 
         TST  ';'
         BE 
-A040 
-        BT  A041 
+A062 
+        BT  A063 
         SET 
-        BF  A042 
-A042 
-A041 
+        BF  A064 
+A064 
+A063 
         BE 
         CLL ST
         BE 
-A043 
+A065 
         TST  ';'
-        BF  A044 
+        BF  A066 
         CLL ST
         BE 
-A044 
-A045 
-        BT  A043 
+A066 
+A067 
+        BT  A065 
         SET 
         BE 
         TST  '.END'
         BE 
-A039 
-A046 
+A061 
+A068 
         R 
 ST
+        SYN  ';'
+        SYN  '.ELSE'
+        SYN  '.END'
         CLL IOST
-        BF  A047 
-A047 
-        BT  A048 
+        BF  A069 
+A069 
+        BT  A070 
         CLL ASSIGNST
-        BF  A049 
-A049 
-        BT  A048 
+        BF  A071 
+A071 
+        BT  A070 
         CLL UNTILST
-        BF  A050 
-A050 
-        BT  A048 
+        BF  A072 
+A072 
+        BT  A070 
         CLL CONDITIONALST
-        BF  A051 
-A051 
-        BT  A048 
+        BF  A073 
+A073 
+        BT  A070 
         CLL BLOCK
-        BF  A052 
-A052 
-A048 
+        BF  A074 
+A074 
+A070 
         R 
 PROGRAM
         CLL BLOCK
-        BF  A053 
+        BF  A075 
         CL  'HLT'
         OUT 
         CL  'END'
         OUT 
-A053 
-A054 
+A075 
+A076 
         R 
         END 
         
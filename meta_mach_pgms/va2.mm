@@ -0,0 +1,622 @@
+        ADR PROGRAM
+ARGS
+        CLL EXP
+        BF  A001 
+A002 
+        TST  ','
+        BF  A003 
+        CLL EXP
+        BE 
+A003 
+A004 
+        BT  A002 
+        SET 
+        BE 
+A001 
+A005 
+        R 
+CALL
+        ID 
+        BF  A006 
+        CL  'PRC '
+        CI 
+        OUT 
+        TST  '('
+        BE 
+        CLL ARGS
+        BF  A007 
+A007 
+        BT  A008 
+        SET 
+        BF  A009 
+A009 
+A008 
+        BE 
+        TST  ')'
+        BE 
+        CL  'CLS'
+        OUT 
+A006 
+A010 
+        R 
+VAR
+        ID 
+        BF  A011 
+        CL  'LD '
+        CI 
+        OUT 
+        TST  '['
+        BF  A012 
+        CLL EXP
+        BE 
+        TST  ']'
+        BE 
+        CL  'AIA'
+        OUT 
+A012 
+        BT  A013 
+        SET 
+        BF  A014 
+A014 
+A013 
+        BE 
+A011 
+A015 
+        R 
+PRIMARY
+        TST  '.CALL'
+        BF  A016 
+        CLL CALL
+        BE 
+A016 
+        BT  A017 
+        CLL VAR
+        BF  A018 
+        CL  'LOD'
+        OUT 
+A018 
+        BT  A017 
+        NUM 
+        BF  A019 
+        CL  'LDL'
+        CI 
+        OUT 
+A019 
+        BT  A017 
+        TST  '('
+        BF  A020 
+        CLL EXP
+        BE 
+        TST  ')'
+        BE 
+A020 
+        BT  A017 
+        TST  '-'
+        BF  A021 
+        CLL PRIMARY
+        BE 
+        CL  'NEG'
+        OUT 
+A021 
+A017 
+        R 
+TERM
+        CLL PRIMARY
+        BF  A022 
+A023 
+        TST  '*'
+        BF  A024 
+        CLL PRIMARY
+        BE 
+        CL  'MLT'
+        OUT 
+A024 
+        BT  A025 
+        TST  '/'
+        BF  A026 
+        CLL PRIMARY
+        BE 
+        CL  'DIV'
+        OUT 
+A026 
+A025 
+        BT  A023 
+        SET 
+        BE 
+A022 
+A027 
+        R 
+EXP1
+        CLL TERM
+        BF  A028 
+A029 
+        TST  '+'
+        BF  A030 
+        CLL TERM
+        BE 
+        CL  'ADD'
+        OUT 
+A030 
+        BT  A031 
+        TST  '-'
+        BF  A032 
+        CLL TERM
+        BE 
+        CL  'SUB'
+        OUT 
+A032 
+A031 
+        BT  A029 
+        SET 
+        BE 
+A028 
+A033 
+        R 
+EXP
+        CLL EXP1
+        BF  A034 
+        TST  '.='
+        BF  A035 
+        CLL EXP1
+        BE 
+        CL  'EQU'
+        OUT 
+A035 
+        BT  A036 
+        TST  '.<'
+        BF  A037 
+        CLL EXP1
+        BE 
+        CL  'LSS'
+        OUT 
+A037 
+        BT  A036 
+        TST  '.>'
+        BF  A038 
+        CLL EXP1
+        BE 
+        CL  'GTR'
+        OUT 
+A038 
+        BT  A036 
+        SET 
+        BF  A039 
+A039 
+A036 
+        BE 
+A034 
+A040 
+        R 
+ASSIGNST
+        CLL VAR
+        BF  A041 
+        TST  '='
+        BE 
+        CLL EXP
+        BE 
+        CL  'ST'
+        OUT 
+A041 
+A042 
+        R 
+UNTILST
+        TST  '.UNTIL'
+        BF  A043 
+        LB 
+        GN1 
+        OUT 
+        CLL EXP
+        BE 
+        TST  '.DO'
+        BE 
+        CL  'BTP '
+        GN2 
+        OUT 
+        CLL ST
+        BE 
+        CL  'B '
+        GN1 
+        OUT 
+        LB 
+        GN2 
+        OUT 
+A043 
+A044 
+        R 
+FORST
+        TST  '.FOR'
+        BF  A045 
+        CLL VAR
+        BE 
+        TST  '='
+        BE 
+        CLL EXP
+        BE 
+        CL  'STA'
+        OUT 
+        TST  '.STEP'
+        BE 
+        CLL EXP
+        BE 
+        LB 
+        GN1 
+        OUT 
+        TST  '.UNTIL'
+        BE 
+        CLL EXP
+        BE 
+        CL  'FTS'
+        OUT 
+        CL  'BFP '
+        GN2 
+        OUT 
+        TST  '.DO'
+        BE 
+        CLL ST
+        BE 
+        CL  'FIN'
+        OUT 
+        CL  'B '
+        GN1 
+        OUT 
+        LB 
+        GN2 
+        OUT 
+        CL  'POP'
+        OUT 
+        CL  'POP'
+        OUT 
+A045 
+A046 
+        R 
+CONDITIONALST
+        TST  '.IF'
+        BF  A047 
+        CLL EXP
+        BE 
+        TST  '.THEN'
+        BE 
+        CL  'BFP '
+        GN1 
+        OUT 
+        CLL ST
+        BE 
+        TST  '.ELSE'
+        BF  A048 
+        CL  'B '
+        GN2 
+        OUT 
+        LB 
+        GN1 
+        OUT 
+        CLL ST
+        BE 
+        LB 
+        GN2 
+        OUT 
+A048 
+        BT  A049 
+        SET 
+        BF  A050 
+        LB 
+        GN1 
+        OUT 
+A050 
+A049 
+        BE 
+A047 
+A051 
+        R 
+GOTOST
+        TST  '.GO'
+        BF  A052 
+        TST  '.TO'
+        BE 
+        ID 
+        BE 
+        CL  'B '
+        CI 
+        OUT 
+A052 
+A053 
+        R 
+LABELST
+        TST  '.LABEL'
+        BF  A054 
+        ID 
+        BE 
+        LB 
+        CI 
+        OUT 
+A054 
+A055 
+        R 
+CALLST
+        TST  '.CALL'
+        BF  A056 
+        CLL CALL
+        BE 
+        CL  'POP'
+        OUT 
+A056 
+A057 
+        R 
+RETURNST
+        TST  '.RETURN'
+        BF  A058 
+        CLL EXP
+        BE 
+        CL  'RSR'
+        OUT 
+A058 
+A059 
+        R 
+IOST
+        TST  'EDIT'
+        BF  A060 
+        TST  '('
+        BE 
+        CLL EXP
+        BE 
+        TST  ','
+        BE 
+        SR 
+        BE 
+        CL  'EDT'
+        CI 
+        OUT 
+        TST  ')'
+        BE 
+A060 
+        BT  A061 
+        TST  'PRINT'
+        BF  A062 
+        CL  'PNT'
+        OUT 
+A062 
+A061 
+        R 
+IDSEQ1
+        ID 
+        BF  A063 
+        LB 
+        CI 
+        OUT 
+        CL  'BLK 1'
+        OUT 
+A063 
+A064 
+        R 
+IDSEQ
+        CLL IDSEQ1
+        BF  A065 
+A066 
+        TST  ','
+        BF  A067 
+        CLL IDSEQ1
+        BE 
+A067 
+A068 
+        BT  A066 
+        SET 
+        BE 
+A065 
+A069 
+        R 
+ARRAYSEQ1
+        ID 
+        BF  A070 
+        LB 
+        CI 
+        OUT 
+        TST  '['
+        BE 
+        NUM 
+        BE 
+        CL  'BLK '
+        CI 
+        OUT 
+        TST  ']'
+        BE 
+A070 
+A071 
+        R 
+ARRAYSEQ
+        CLL ARRAYSEQ1
+        BF  A072 
+A073 
+        TST  ','
+        BF  A074 
+        CLL ARRAYSEQ1
+        BE 
+A074 
+A075 
+        BT  A073 
+        SET 
+        BE 
+A072 
+A076 
+        R 
+PARAM
+        ID 
+        BF  A077 
+        LB 
+        CI 
+        OUT 
+        CL  'BLK 1'
+        OUT 
+        CL  'ENT '
+        CI 
+        OUT 
+A077 
+A078 
+        R 
+PARAMS
+        CLL PARAM
+        BF  A079 
+A080 
+        TST  ','
+        BF  A081 
+        CLL PARAM
+        BE 
+A081 
+A082 
+        BT  A080 
+        SET 
+        BE 
+A079 
+A083 
+        R 
+PROCEDURE
+        TST  '.PROCEDURE'
+        BF  A084 
+        CL  'B '
+        GN1 
+        OUT 
+        ID 
+        BE 
+        LB 
+        CI 
+        OUT 
+        TST  '('
+        BE 
+        CLL PARAMS
+        BF  A085 
+A085 
+        BT  A086 
+        SET 
+        BF  A087 
+A087 
+A086 
+        BE 
+        TST  ')'
+        BE 
+        CLL ST
+        BE 
+        CL  'LDL 0'
+        OUT 
+        CL  'RSR'
+        OUT 
+        LB 
+        GN1 
+        OUT 
+A084 
+A088 
+        R 
+DEC
+        TST  '.REAL'
+        BF  A089 
+        CL  'B '
+        GN1 
+        OUT 
+        CLL IDSEQ
+        BE 
+        LB 
+        GN1 
+        OUT 
+A089 
+        BT  A090 
+        TST  '.ARRAY'
+        BF  A091 
+        CL  'B '
+        GN1 
+        OUT 
+        CLL ARRAYSEQ
+        BE 
+        LB 
+        GN1 
+        OUT 
+A091 
+        BT  A090 
+        CLL PROCEDURE
+        BF  A092 
+A092 
+A090 
+        R 
+BLOCK
+        TST  '.BEGIN'
+        BF  A093 
+A094 
+        CLL DEC
+        BF  A095 
+        TST  ';'
+        BE 
+A095 
+A096 
+        BT  A094 
+        SET 
+        BE 
+        CLL ST
+        BE 
+A097 
+        TST  ';'
+        BF  A098 
+        CLL ST
+        BE 
+A098 
+A099 
+        BT  A097 
+        SET 
+        BE 
+        TST  '.END'
+        BE 
+A093 
+A100 
+        R 
+ST
+        SYN  ';'
+        SYN  '.ELSE'
+        SYN  '.END'
+        CLL IOST
+        BF  A101 
+A101 
+        BT  A102 
+        CLL CALLST
+        BF  A103 
+A103 
+        BT  A102 
+        CLL RETURNST
+        BF  A104 
+A104 
+        BT  A102 
+        CLL GOTOST
+        BF  A105 
+A105 
+        BT  A102 
+        CLL LABELST
+        BF  A106 
+A106 
+        BT  A102 
+        CLL UNTILST
+        BF  A107 
+A107 
+        BT  A102 
+        CLL FORST
+        BF  A108 
+A108 
+        BT  A102 
+        CLL CONDITIONALST
+        BF  A109 
+A109 
+        BT  A102 
+        CLL BLOCK
+        BF  A110 
+A110 
+        BT  A102 
+        CLL ASSIGNST
+        BF  A111 
+A111 
+A102 
+        R 
+PROGRAM
+        CLL BLOCK
+        BF  A112 
+        CL  'HLT'
+        OUT 
+        CL  'END'
+        OUT 
+A112 
+A113 
+        R 
+        END 
+        
@@ -1,3 +1,5 @@
+#![cfg_attr(test, allow(clippy::assertions_on_constants))]
+
 use std::convert::From;
 use std::error::Error;
 use std::iter::Peekable;
@@ -6,6 +8,20 @@ use std::str::Chars;
 pub struct Lexer<'a> {
     it: Peekable<Chars<'a>>,
     syms: &'a [&'static str],
+    comments: CommentSyntax<'a>,
+}
+
+/// Comment delimiters recognized by a `Lexer`.
+///
+/// Line comments run from their delimiter up to the end of the line,
+/// block comments up to the matching closing delimiter. Unless `emit`
+/// is set comments are skipped and count as whitespace, otherwise they
+/// are returned as `Comment` tokens.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CommentSyntax<'a> {
+    pub line: &'a [&'static str],
+    pub block: &'a [(&'static str, &'static str)],
+    pub emit: bool,
 }
 
 #[derive(Debug, PartialEq)]
@@ -15,6 +31,7 @@ pub enum Token {
     Num(f64),
     Symbol(String),
     Str(String),
+    Comment(String),
     End,
 }
 
@@ -33,12 +50,73 @@ use ParseState::*;
 
 impl<'a> Lexer<'a> {
     pub fn new(txt: &'a str, syms: &'a [&'static str]) -> Self {
+        Self::with_comments(txt, syms, CommentSyntax::default())
+    }
+
+    pub fn with_comments(
+        txt: &'a str,
+        syms: &'a [&'static str],
+        comments: CommentSyntax<'a>,
+    ) -> Self {
         Lexer {
             it: txt.chars().peekable(),
             syms,
+            comments,
         }
     }
 
+    fn at(&self, s: &str) -> bool {
+        let mut it = self.it.clone();
+        s.chars().all(|ch| it.next() == Some(ch))
+    }
+
+    fn advance(&mut self, s: &str) {
+        for _ in s.chars() {
+            self.it.next();
+        }
+    }
+
+    fn comment_start(&self) -> Option<(&'static str, Option<&'static str>)> {
+        for open in self.comments.line {
+            if self.at(open) {
+                return Some((open, None));
+            }
+        }
+        for (open, close) in self.comments.block {
+            if self.at(open) {
+                return Some((open, Some(close)));
+            }
+        }
+        None
+    }
+
+    fn comment(
+        &mut self,
+        open: &str,
+        close: Option<&'static str>,
+    ) -> Result<String, Box<dyn Error>> {
+        self.advance(open);
+        let mut body = String::new();
+        match close {
+            None => {
+                while let Some(ch) = self.it.next_if(|ch| *ch != '\n') {
+                    body.push(ch);
+                }
+            }
+            Some(close) => loop {
+                if self.at(close) {
+                    self.advance(close);
+                    break;
+                }
+                match self.it.next() {
+                    Some(ch) => body.push(ch),
+                    None => return Err(From::from(format!("unterminated comment {open}"))),
+                }
+            },
+        }
+        Ok(body)
+    }
+
     fn is_sym_start(&self, s: &str) -> bool {
         // XXX make a shrinking list of candidates instead?
         for symb in self.syms {
@@ -54,6 +132,21 @@ impl<'a> Lexer<'a> {
         let mut st = ParseStart;
         while let Some(ch) = self.it.peek() {
             let ch = *ch;
+            if !matches!(st, ParseStr) {
+                if let Some((open, close)) = self.comment_start() {
+                    match st {
+                        ParseStart if self.comments.emit => {
+                            return Ok(Comment(self.comment(open, close)?));
+                        }
+                        ParseStart | ParseWS if !self.comments.emit => {
+                            self.comment(open, close)?;
+                            st = ParseWS;
+                            continue;
+                        }
+                        _ => break,
+                    }
+                }
+            }
             match st {
                 ParseStart => {
                     if ch.is_ascii_whitespace() {
@@ -209,6 +302,52 @@ mod tests {
         assert_eq!(lx.next_token().expect("token"), Str("".to_string()));
     }
 
+    const COMMENTS: CommentSyntax = CommentSyntax {
+        line: &["#", "--"],
+        block: &[("{", "}")],
+        emit: false,
+    };
+
+    #[test]
+    fn comments_skipped() {
+        let mut lx = Lexer::with_comments("a# one\n  {two} -b{ three\n}c--", &["-"], COMMENTS);
+        assert_eq!(lx.next_token().expect("token"), Id("a".to_string()));
+        assert_eq!(lx.next_token().expect("token"), WS);
+        assert_eq!(lx.next_token().expect("token"), Symbol("-".to_string()));
+        assert_eq!(lx.next_token().expect("token"), Id("b".to_string()));
+        assert_eq!(lx.next_token().expect("token"), WS);
+        assert_eq!(lx.next_token().expect("token"), Id("c".to_string()));
+        assert_eq!(lx.next_token().expect("token"), WS);
+        assert_eq!(lx.next_token().expect("token"), End);
+    }
+
+    #[test]
+    fn comments_emitted() {
+        let comments = CommentSyntax {
+            emit: true,
+            ..COMMENTS
+        };
+        let mut lx = Lexer::with_comments("a #one\n{two}'{x}'", &[], comments);
+        assert_eq!(lx.next_token().expect("token"), Id("a".to_string()));
+        assert_eq!(lx.next_token().expect("token"), WS);
+        assert_eq!(lx.next_token().expect("token"), Comment("one".to_string()));
+        assert_eq!(lx.next_token().expect("token"), WS);
+        assert_eq!(lx.next_token().expect("token"), Comment("two".to_string()));
+        assert_eq!(lx.next_token().expect("token"), Str("{x}".to_string()));
+        assert_eq!(lx.next_token().expect("token"), End);
+    }
+
+    #[test]
+    fn comment_error_unterminated() {
+        let mut lx = Lexer::with_comments("a {b", &[], COMMENTS);
+        assert_eq!(lx.next_token().expect("token"), Id("a".to_string()));
+        if let Err(e) = lx.next_token() {
+            assert_eq!(format!("{}", e), "unterminated comment {")
+        } else {
+            panic!("expected an error")
+        }
+    }
+
     #[test]
     fn num_error() {
         let mut lx = Lexer::new("1.2.3", &[]);
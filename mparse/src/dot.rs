@@ -0,0 +1,200 @@
+use std::fmt;
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::MProgram;
+use crate::ParseableInstr;
+
+/// How control leaves an instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    /// Continues with the next instruction.
+    Next,
+    /// Always continues at the target.
+    Jump(usize),
+    /// Continues at the target or with the next instruction.
+    Branch(usize),
+    /// Runs the rule at the target, then continues with the next instruction.
+    Call(usize),
+    /// Ends the program or the current rule.
+    Stop,
+}
+
+/// Instructions that report their branch targets.
+pub trait ControlFlow {
+    fn flow(&self) -> Flow;
+}
+
+/// Splits the program into basic blocks. Calls do not end a block.
+pub fn basic_blocks<MInstr>(pgm: &MProgram<MInstr>) -> Vec<Range<usize>>
+where
+    MInstr: ParseableInstr + ControlFlow + fmt::Debug,
+{
+    let len = pgm.instrs.len();
+    let mut leader = vec![false; len + 1];
+    leader[0] = true;
+    for (ic, instr) in pgm.instrs.iter().enumerate() {
+        match instr.flow() {
+            Flow::Next => (),
+            Flow::Call(t) => leader[t] = true,
+            Flow::Jump(t) | Flow::Branch(t) => {
+                leader[t] = true;
+                leader[ic + 1] = true;
+            }
+            Flow::Stop => leader[ic + 1] = true,
+        }
+    }
+    let starts: Vec<usize> = (0..len).filter(|ic| leader[*ic]).collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, start)| *start..starts.get(i + 1).copied().unwrap_or(len))
+        .collect()
+}
+
+/// Formats the basic blocks of the program as a Graphviz digraph.
+///
+/// Conditional branches have a solid edge to their target and a dashed
+/// edge to the next instruction. Execution past the last instruction
+/// leads to an `end` node.
+pub fn to_dot<MInstr>(pgm: &MProgram<MInstr>) -> String
+where
+    MInstr: ParseableInstr + ControlFlow + fmt::Debug + fmt::Display,
+{
+    let len = pgm.instrs.len();
+    let mut dot = String::from("digraph cfg {\n  node [shape=box fontname=\"monospace\"];\n");
+    let mut edges = String::new();
+    let mut falls_off = false;
+    let mut edge = |from: usize, to: usize, attrs: &str| {
+        falls_off |= to == len;
+        let to = if to == len {
+            "end".to_string()
+        } else {
+            format!("b{to}")
+        };
+        writeln!(edges, "  b{from} -> {to}{attrs};").unwrap();
+    };
+    for block in basic_blocks(pgm) {
+        let mut label = String::new();
+        for name in labels_at(pgm, block.start) {
+            write!(label, "{}\\l", escape(name)).unwrap();
+        }
+        for ic in block.clone() {
+            let instr = escape(&pgm.instrs[ic].to_string());
+            write!(label, "{ic:<6}{instr}\\l").unwrap();
+        }
+        writeln!(dot, "  b{} [label=\"{label}\"];", block.start).unwrap();
+        let last = block.end - 1;
+        match pgm.instrs[last].flow() {
+            Flow::Next | Flow::Call(_) => edge(block.start, block.end, ""),
+            Flow::Jump(t) => edge(block.start, t, ""),
+            Flow::Branch(t) => {
+                edge(block.start, t, "");
+                edge(block.start, block.end, " [style=dashed]");
+            }
+            Flow::Stop => (),
+        }
+    }
+    if falls_off {
+        dot.push_str("  end [shape=doublecircle label=\"\"];\n");
+    }
+    dot.push_str(&edges);
+    dot.push_str("}\n");
+    dot
+}
+
+/// Formats the rules of the program and the calls between them as a
+/// Graphviz digraph.
+///
+/// A rule is entered at a call target and takes in what is reachable from
+/// there without following calls. The root is the rule at the start of
+/// the program, after any leading jumps. Only rules reachable from the
+/// root are shown.
+pub fn call_graph<MInstr>(pgm: &MProgram<MInstr>) -> String
+where
+    MInstr: ParseableInstr + ControlFlow + fmt::Debug,
+{
+    let len = pgm.instrs.len();
+    let mut root = 0;
+    for _ in 0..len {
+        match pgm.instrs.get(root).map(ControlFlow::flow) {
+            Some(Flow::Jump(t)) => root = t,
+            _ => break,
+        }
+    }
+    let mut dot = String::from("digraph calls {\n  node [shape=box];\n");
+    let mut edges = String::new();
+    let mut rules = vec![root];
+    let mut i = 0;
+    while let Some(&entry) = rules.get(i) {
+        i += 1;
+        let name = match labels_at(pgm, entry).first() {
+            Some(name) => escape(name),
+            None => format!("ic {entry}"),
+        };
+        writeln!(dot, "  r{entry} [label=\"{name}\"];").unwrap();
+        for callee in callees(pgm, entry) {
+            writeln!(edges, "  r{entry} -> r{callee};").unwrap();
+            if !rules.contains(&callee) {
+                rules.push(callee);
+            }
+        }
+    }
+    dot.push_str(&edges);
+    dot.push_str("}\n");
+    dot
+}
+
+/// Call targets in the rule entered at `entry`, in instruction order.
+fn callees<MInstr>(pgm: &MProgram<MInstr>, entry: usize) -> Vec<usize>
+where
+    MInstr: ParseableInstr + ControlFlow + fmt::Debug,
+{
+    let mut seen = vec![false; pgm.instrs.len()];
+    let mut work = vec![entry];
+    let mut calls = Vec::new();
+    while let Some(ic) = work.pop() {
+        if ic >= seen.len() || seen[ic] {
+            continue;
+        }
+        seen[ic] = true;
+        match pgm.instrs[ic].flow() {
+            Flow::Next => work.push(ic + 1),
+            Flow::Jump(t) => work.push(t),
+            Flow::Branch(t) => work.extend([t, ic + 1]),
+            Flow::Call(t) => {
+                calls.push((ic, t));
+                work.push(ic + 1);
+            }
+            Flow::Stop => (),
+        }
+    }
+    calls.sort();
+    let mut targets: Vec<usize> = Vec::new();
+    for (_, t) in calls {
+        if !targets.contains(&t) {
+            targets.push(t);
+        }
+    }
+    targets
+}
+
+fn labels_at<MInstr>(pgm: &MProgram<MInstr>, ic: usize) -> Vec<&str>
+where
+    MInstr: ParseableInstr + fmt::Debug,
+{
+    let mut labels: Vec<(u32, &str)> = pgm
+        .labels
+        .iter()
+        .filter(|(_, addr)| pgm.ic.get(addr) == Some(&ic))
+        // data labels are not code labels
+        .filter(|(_, addr)| !pgm.blocks.iter().any(|b| b.contains(addr)))
+        .map(|(label, addr)| (*addr, label.as_str()))
+        .collect();
+    labels.sort();
+    labels.into_iter().map(|(_, label)| label).collect()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -1,18 +1,72 @@
+#![cfg_attr(test, allow(clippy::upper_case_acronyms))]
+
+use minilexer::CommentSyntax;
 use minilexer::Lexer;
 use minilexer::Token;
 use std::collections::HashMap;
 use std::convert::From;
 use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::ops::Range;
+
+pub mod dot;
+pub mod profile;
+pub mod valgol;
 
 #[derive(Debug)]
 pub struct MProgram<MInstr: ParseableInstr + std::fmt::Debug> {
     pub instrs: Vec<MInstr>,
     pub labels: Labels,
     pub ic: ICs,
+    /// Address ranges reserved by `BLK`, in increasing order.
+    pub blocks: Vec<Range<u32>>,
     addr: u32,
 }
 
+const COMMENTS: CommentSyntax = CommentSyntax {
+    line: &["#"],
+    block: &[],
+    emit: false,
+};
+
+/// One line of a program in assembler form.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line<MInstr> {
+    Label(String),
+    Instr(MInstr),
+    Blk(u32),
+}
+
+/// An instruction counter and the nearest label at or before it,
+/// shown as `ic 12 (LOOP+3)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub ic: usize,
+    pub label: Option<(String, usize)>,
+}
+
+impl Location {
+    /// The label part alone, `LOOP+3`, empty without a label.
+    pub fn label_text(&self) -> String {
+        match &self.label {
+            Some((label, 0)) => label.clone(),
+            Some((label, off)) => format!("{label}+{off}"),
+            None => String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ic {}", self.ic)?;
+        match self.label {
+            Some(_) => write!(f, " ({})", self.label_text()),
+            None => Ok(()),
+        }
+    }
+}
+
 type Labels = HashMap<String, u32>;
 type ICs = HashMap<u32, usize>;
 
@@ -25,6 +79,9 @@ pub enum AAAUse {
 pub trait ParseableInstr {
     const UNDEF: Self;
     const ACCEPT_BLK: bool;
+    /// Whether a branch may target a label after the last instruction,
+    /// leaving running into it to the machine.
+    const ACCEPT_END_LABEL: bool;
 
     fn is_undefined(&self) -> bool;
 
@@ -71,7 +128,7 @@ fn resolve_ic<MInstr: ParseableInstr>(
             "internal error: unmatched addr {addr} for ic"
         )));
     };
-    if ic >= pgm_len {
+    if ic > pgm_len || (ic == pgm_len && !MInstr::ACCEPT_END_LABEL) {
         return Err(From::from(format!(
             "instruction counter {ic} for {aaa} without instruction"
         )));
@@ -86,6 +143,7 @@ impl<MInstr: ParseableInstr + std::fmt::Debug> MProgram<MInstr> {
             instrs: Vec::new(),
             labels: Labels::new(),
             ic: ICs::new(),
+            blocks: Vec::new(),
             addr: 0,
         }
     }
@@ -96,7 +154,7 @@ impl<MInstr: ParseableInstr + std::fmt::Debug> MProgram<MInstr> {
             if line.is_empty() {
                 continue;
             };
-            let mut lx = Lexer::new(line, &["#"]);
+            let mut lx = Lexer::with_comments(line, &["-"], COMMENTS);
             let tok = lx.next_token()?;
             match tok {
                 Token::Id(id) => self.add_label(&id),
@@ -106,7 +164,6 @@ impl<MInstr: ParseableInstr + std::fmt::Debug> MProgram<MInstr> {
                         break;
                     }
                 }
-                Token::Symbol(s) if s == "#" => (),
                 _ => return Err(From::from(format!("unexpected {:?}", tok))),
             }
         }
@@ -123,8 +180,7 @@ impl<MInstr: ParseableInstr + std::fmt::Debug> MProgram<MInstr> {
 
     fn add_instr(&mut self, lx: &mut Lexer, line: &str) -> Result<bool, Box<dyn Error>> {
         let ins = match lx.next_token()? {
-            Token::End => return Ok(true),
-            Token::Symbol(s) if s == "#" => return Ok(false),
+            Token::End => return Ok(false),
             Token::Id(instr) => instr,
             unexp => return Err(From::from(format!("unexpected {:?}", unexp))),
         };
@@ -134,6 +190,12 @@ impl<MInstr: ParseableInstr + std::fmt::Debug> MProgram<MInstr> {
         if tok == Token::WS {
             tok = lx.next_token()?;
         }
+        if tok == Token::Symbol("-".to_string()) {
+            tok = match lx.next_token()? {
+                Token::Num(n) => Token::Num(-n),
+                _ => return Err(From::from(format!("invalid line {line}"))),
+            };
+        }
 
         let mut inc = 2;
         let instr = match tok {
@@ -147,16 +209,18 @@ impl<MInstr: ParseableInstr + std::fmt::Debug> MProgram<MInstr> {
                     if n.fract() != 0.0 || n < 0.0 {
                         return Err(From::from("invalid BLK: {line}"));
                     }
+                    let start = self.addr;
                     self.addr += n as u32;
+                    self.blocks.push(start..self.addr);
                     return Ok(false);
                 }
                 MInstr::with_num(ins, n)
             }
             Token::Str(s) => MInstr::with_string(ins, s),
-            Token::Symbol(s) if s != "#" => {
+            Token::Symbol(_) | Token::Comment(_) => {
                 return Err(From::from(format!("invalid line {line}")));
             }
-            Token::End | Token::Symbol(_) => {
+            Token::End => {
                 inc = 1;
                 if ins == "END" {
                     return Ok(true);
@@ -185,6 +249,72 @@ impl<MInstr: ParseableInstr + std::fmt::Debug> MProgram<MInstr> {
         Ok(())
     }
 
+    /// Returns the size of the memory needed to hold all `BLK` reserved cells.
+    pub fn mem_size(&self) -> u32 {
+        self.blocks.last().map_or(0, |r| r.end)
+    }
+
+    /// Returns the closest label at or before `ic` together with the
+    /// distance of `ic` from it.
+    pub fn nearest_label(&self, ic: usize) -> Option<(&str, usize)> {
+        self.labels
+            .iter()
+            .filter_map(|(label, addr)| {
+                let lic = *self.ic.get(addr)?;
+                (lic <= ic).then_some((label.as_str(), *addr, lic))
+            })
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+            .map(|(label, _, lic)| (label, ic - lic))
+    }
+
+    /// Returns `ic` with its nearest label.
+    pub fn location(&self, ic: usize) -> Location {
+        let label = self
+            .nearest_label(ic)
+            .map(|(label, off)| (label.to_string(), off));
+        Location { ic, label }
+    }
+
+    /// Returns the program as assembler lines, labels and blocks in
+    /// address order before the instruction that follows them.
+    pub fn lines(&self) -> Vec<Line<MInstr>>
+    where
+        MInstr: Clone,
+    {
+        // (ic, addr, labels before the block they name, line)
+        let mut marks: Vec<(usize, u32, u8, Line<MInstr>)> = self
+            .labels
+            .iter()
+            .map(|(label, addr)| (self.ic[addr], *addr, 0, Line::Label(label.clone())))
+            .collect();
+        for block in self.blocks.iter() {
+            let ic = self.ic.get(&block.start).copied().unwrap_or_else(|| {
+                // an unlabelled block precedes the next labelled address
+                self.ic
+                    .iter()
+                    .filter(|(addr, _)| **addr >= block.end)
+                    .min_by_key(|(addr, _)| **addr)
+                    .map_or(self.instrs.len(), |(_, ic)| *ic)
+            });
+            marks.push((ic, block.start, 1, Line::Blk(block.end - block.start)));
+        }
+        marks.sort_by(|a, b| {
+            (a.0, a.1, a.2)
+                .cmp(&(b.0, b.1, b.2))
+                .then(label_order(&a.3, &b.3))
+        });
+        let mut marks = marks.into_iter().peekable();
+        let mut lines = Vec::new();
+        for (ic, instr) in self.instrs.iter().enumerate() {
+            while let Some((_, _, _, line)) = marks.next_if(|m| m.0 <= ic) {
+                lines.push(line);
+            }
+            lines.push(Line::Instr(instr.clone()));
+        }
+        lines.extend(marks.map(|(_, _, _, line)| line));
+        lines
+    }
+
     pub fn debug_ics(&self) {
         for (label, addr) in self.labels.iter() {
             let ic = self.ic.get(addr).unwrap();
@@ -198,6 +328,28 @@ impl<MInstr: ParseableInstr + std::fmt::Debug> MProgram<MInstr> {
     }
 }
 
+fn label_order<MInstr>(a: &Line<MInstr>, b: &Line<MInstr>) -> std::cmp::Ordering {
+    match (a, b) {
+        (Line::Label(a), Line::Label(b)) => a.cmp(b),
+        _ => std::cmp::Ordering::Equal,
+    }
+}
+
+/// Formats `lines` as assembler source that `parse` reads back.
+pub fn render<MInstr: fmt::Display>(lines: &[Line<MInstr>]) -> String {
+    let mut src = String::new();
+    for line in lines {
+        match line {
+            Line::Label(label) => src.push_str(label),
+            Line::Instr(instr) => src.push_str(&format!("        {instr}")),
+            Line::Blk(n) => src.push_str(&format!("        BLK {n}")),
+        }
+        src.push('\n');
+    }
+    src.push_str("        END\n");
+    src
+}
+
 pub fn load<MInstr: ParseableInstr + std::fmt::Debug>(
     pgm_path: &str,
 ) -> Result<MProgram<MInstr>, Box<dyn Error>> {
@@ -219,7 +371,7 @@ pub fn parse<MInstr: ParseableInstr + std::fmt::Debug>(
 mod tests {
     use super::*;
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone, PartialEq)]
     enum MInstr {
         B(String, usize),
         LDL(f64),
@@ -233,6 +385,7 @@ mod tests {
     impl ParseableInstr for MInstr {
         const UNDEF: Self = MInstr::Undef;
         const ACCEPT_BLK: bool = true;
+        const ACCEPT_END_LABEL: bool = false;
 
         fn is_undefined(&self) -> bool {
             matches!(self, MInstr::Undef)
@@ -292,6 +445,19 @@ mod tests {
         }
     }
 
+    impl fmt::Display for MInstr {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                MInstr::B(aaa, _) => write!(f, "B {aaa}"),
+                MInstr::LDL(n) => write!(f, "LDL {n}"),
+                MInstr::ST(aaa, _) => write!(f, "ST {aaa}"),
+                MInstr::LD(aaa, _) => write!(f, "LD {aaa}"),
+                MInstr::EDT(s) => write!(f, "EDT '{s}'"),
+                other => write!(f, "{other:?}"),
+            }
+        }
+    }
+
     #[test]
     fn it_works() {
         assert!(parse::<MInstr>(
@@ -312,4 +478,84 @@ A  # label
         )
         .is_ok())
     }
+
+    #[test]
+    fn trailing_label() {
+        let e = parse::<MInstr>("   B Z\n   HLT\nZ\n").expect_err("branch past the end");
+        assert_eq!(
+            e.to_string(),
+            "instruction counter 2 for Z without instruction"
+        );
+        assert!(parse::<MInstr>("   B Z\nZ\n   HLT\n").is_ok());
+    }
+
+    #[test]
+    fn nearest_label() {
+        let p = parse::<MInstr>(
+            r#"
+ B  A
+X
+   BLK 3
+A
+   LDL  5.0
+   HLT
+B
+"#,
+        )
+        .expect("program");
+        assert_eq!(p.nearest_label(0), None);
+        assert_eq!(p.nearest_label(1), Some(("A", 0)));
+        assert_eq!(p.nearest_label(2), Some(("A", 1)));
+        assert_eq!(p.nearest_label(3), Some(("B", 0)));
+        assert_eq!(p.location(0).to_string(), "ic 0");
+        assert_eq!(p.location(2).to_string(), "ic 2 (A+1)");
+        assert_eq!(p.location(3).label_text(), "B");
+        assert_eq!(p.blocks, vec![2..5]);
+        assert_eq!(p.mem_size(), 5);
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let src = r#"
+ B  A
+X
+Y
+   BLK 3
+   BLK 2
+Z
+   BLK 1
+A
+   LDL  5.5
+   LDL -2
+   ST Y
+   EDT 'a b'
+C
+   HLT
+"#;
+        let p = parse::<MInstr>(src).expect("program");
+        let lines = p.lines();
+        assert_eq!(
+            lines,
+            vec![
+                Line::Instr(MInstr::B("A".to_string(), 1)),
+                Line::Label("X".to_string()),
+                Line::Label("Y".to_string()),
+                Line::Blk(3),
+                Line::Blk(2),
+                Line::Label("Z".to_string()),
+                Line::Blk(1),
+                Line::Label("A".to_string()),
+                Line::Instr(MInstr::LDL(5.5)),
+                Line::Instr(MInstr::LDL(-2.0)),
+                Line::Instr(MInstr::ST("Y".to_string(), 2)),
+                Line::Instr(MInstr::EDT("a b".to_string())),
+                Line::Label("C".to_string()),
+                Line::Instr(MInstr::HLT),
+            ]
+        );
+        let q = parse::<MInstr>(&render(&lines)).expect("rendered program");
+        assert_eq!(q.instrs, p.instrs);
+        assert_eq!(q.labels, p.labels);
+        assert_eq!(q.blocks, p.blocks);
+    }
 }
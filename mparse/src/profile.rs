@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use crate::MProgram;
+use crate::ParseableInstr;
+
+const HOT_INSTRS: usize = 10;
+
+/// Execution counts of rules, procedures entered by call instructions.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RuleStats {
+    pub calls: u64,
+    pub successes: u64,
+    pub failures: u64,
+    /// Instructions executed by the rule itself.
+    pub self_cost: u64,
+    /// Instructions executed by the rule and its callees, recursive
+    /// activations are counted once.
+    pub total_cost: u64,
+}
+
+/// Instruction and rule counters collected by a machine while executing
+/// a program.
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub counts: Vec<u64>,
+    /// Keyed by the ic of the rule entry.
+    pub rules: HashMap<usize, RuleStats>,
+    /// Instructions executed under each stack of rule entries.
+    pub stacks: HashMap<Vec<usize>, u64>,
+    // active rule entries, with the executed count when they were entered
+    active: Vec<usize>,
+    entered: Vec<u64>,
+    executed: u64,
+}
+
+impl Profile {
+    pub fn new(pgm_len: usize) -> Self {
+        Profile {
+            counts: vec![0; pgm_len],
+            ..Default::default()
+        }
+    }
+
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    pub fn instr(&mut self, ic: usize) {
+        self.executed += 1;
+        if let Some(cnt) = self.counts.get_mut(ic) {
+            *cnt += 1;
+        }
+        if let Some(entry) = self.active.last() {
+            self.rules.entry(*entry).or_default().self_cost += 1;
+            match self.stacks.get_mut(self.active.as_slice()) {
+                Some(cnt) => *cnt += 1,
+                None => {
+                    self.stacks.insert(self.active.clone(), 1);
+                }
+            }
+        }
+    }
+
+    pub fn call(&mut self, entry: usize) {
+        self.rules.entry(entry).or_default().calls += 1;
+        self.active.push(entry);
+        self.entered.push(self.executed);
+    }
+
+    /// Records the return from the innermost rule, `success` is `None`
+    /// when the outcome is unknown.
+    pub fn ret(&mut self, success: Option<bool>) {
+        let (Some(entry), Some(entered)) = (self.active.pop(), self.entered.pop()) else {
+            return;
+        };
+        let recursive = self.active.contains(&entry);
+        let stats = self.rules.entry(entry).or_default();
+        match success {
+            Some(true) => stats.successes += 1,
+            Some(false) => stats.failures += 1,
+            None => (),
+        }
+        if !recursive {
+            stats.total_cost += self.executed - entered;
+        }
+    }
+
+    /// Closes the rules still active when execution stopped.
+    pub fn finish(&mut self) {
+        while !self.active.is_empty() {
+            self.ret(None);
+        }
+    }
+
+    /// Writes rules sorted by total cost, then the most executed
+    /// instructions. Without rules, instruction counts are summed by
+    /// nearest label instead.
+    pub fn report<I: ParseableInstr + fmt::Debug + fmt::Display>(
+        &self,
+        pgm: &MProgram<I>,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        writeln!(out, "executed {} instructions", self.executed)?;
+        if self.rules.is_empty() {
+            writeln!(out, "{:<12} {:>10}", "label", "count")?;
+            for (label, cnt) in self.by_label(pgm) {
+                writeln!(out, "{label:<12} {cnt:>10}")?;
+            }
+        } else {
+            writeln!(
+                out,
+                "{:<12} {:>8} {:>8} {:>8} {:>10} {:>10}",
+                "rule", "calls", "success", "failure", "self", "total"
+            )?;
+            let mut rules: Vec<_> = self.rules.iter().collect();
+            rules.sort_by_key(|(entry, stats)| (std::cmp::Reverse(stats.total_cost), **entry));
+            for (entry, stats) in rules {
+                writeln!(
+                    out,
+                    "{:<12} {:>8} {:>8} {:>8} {:>10} {:>10}",
+                    rule_name(pgm, *entry),
+                    stats.calls,
+                    stats.successes,
+                    stats.failures,
+                    stats.self_cost,
+                    stats.total_cost
+                )?;
+            }
+        }
+        writeln!(out, "{:<5} {:<12} {:>10}  instruction", "ic", "at", "count")?;
+        let mut hot: Vec<(usize, u64)> = self
+            .counts
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, cnt)| *cnt > 0)
+            .collect();
+        hot.sort_by_key(|(ic, cnt)| (std::cmp::Reverse(*cnt), *ic));
+        for (ic, cnt) in hot.into_iter().take(HOT_INSTRS) {
+            let at = pgm.location(ic).label_text();
+            writeln!(out, "{ic:<5} {at:<12} {cnt:>10}  {}", pgm.instrs[ic])?;
+        }
+        Ok(())
+    }
+
+    /// Writes the counts in the folded stack format read by flame graph
+    /// tools, one `frame;frame count` line per stack.
+    pub fn write_folded<I: ParseableInstr + fmt::Debug>(
+        &self,
+        pgm: &MProgram<I>,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        if self.rules.is_empty() {
+            for (label, cnt) in self.by_label(pgm) {
+                writeln!(out, "{label} {cnt}")?;
+            }
+            return Ok(());
+        }
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, cnt)| {
+                let frames: Vec<String> = stack.iter().map(|e| rule_name(pgm, *e)).collect();
+                format!("{} {cnt}", frames.join(";"))
+            })
+            .collect();
+        lines.sort();
+        for line in lines {
+            writeln!(out, "{line}")?;
+        }
+        Ok(())
+    }
+
+    fn by_label<I: ParseableInstr + fmt::Debug>(&self, pgm: &MProgram<I>) -> Vec<(String, u64)> {
+        let mut sums: HashMap<String, u64> = HashMap::new();
+        for (ic, cnt) in self.counts.iter().enumerate().filter(|(_, cnt)| **cnt > 0) {
+            let label = pgm.nearest_label(ic).map_or("(start)", |(label, _)| label);
+            *sums.entry(label.to_string()).or_default() += cnt;
+        }
+        let mut sums: Vec<(String, u64)> = sums.into_iter().collect();
+        sums.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        sums
+    }
+}
+
+fn rule_name<I: ParseableInstr + fmt::Debug>(pgm: &MProgram<I>, entry: usize) -> String {
+    match pgm.nearest_label(entry) {
+        Some((label, 0)) => label.to_string(),
+        _ => format!("ic{entry}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_costs() {
+        let mut p = Profile::new(4);
+        p.call(0);
+        p.instr(0);
+        p.call(2);
+        p.instr(2);
+        p.call(2);
+        p.instr(2);
+        p.instr(3);
+        p.ret(Some(false));
+        p.ret(Some(true));
+        p.instr(1);
+        p.finish();
+        assert_eq!(p.executed(), 5);
+        assert_eq!(p.counts, vec![1, 1, 2, 1]);
+        assert_eq!(
+            p.rules[&2],
+            RuleStats {
+                calls: 2,
+                successes: 1,
+                failures: 1,
+                self_cost: 3,
+                total_cost: 3,
+            }
+        );
+        assert_eq!(p.rules[&0].self_cost, 2);
+        assert_eq!(p.rules[&0].total_cost, 5);
+        assert_eq!(p.stacks[&vec![0, 2, 2]], 2);
+    }
+}
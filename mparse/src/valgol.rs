@@ -0,0 +1,51 @@
+//! What the VALGOL I and VALGOL II machines share.
+use std::error::Error;
+use std::fmt;
+
+use crate::{Location, MProgram, ParseableInstr};
+
+pub const PRINT_AREA_SIZE: usize = 100;
+/// Numbers closer than this compare equal.
+pub const EPS: f64 = 0.000001;
+
+/// Places `s` in the print area from column `n`, leaving the area as it
+/// is when `s` does not fit or is not ASCII. An empty area is blank.
+pub fn place(area: &mut String, n: f64, s: &str) {
+    if n < 0.0 || !s.is_ascii() {
+        return;
+    }
+    let start = n as usize;
+    let sz = s.len();
+    if start + sz > PRINT_AREA_SIZE {
+        return;
+    }
+    if area.is_empty() {
+        area.push_str(&" ".repeat(PRINT_AREA_SIZE));
+    }
+    area.replace_range(start..start + sz, s);
+}
+
+/// A machine's runtime error located at the failing instruction counter,
+/// with the nearest preceding label when there is one.
+#[derive(Debug, PartialEq)]
+pub struct ExecError<E> {
+    pub error: E,
+    pub at: Location,
+}
+
+impl<E> ExecError<E> {
+    pub fn new<I: ParseableInstr + fmt::Debug>(error: E, ic: usize, pgm: &MProgram<I>) -> Self {
+        ExecError {
+            error,
+            at: pgm.location(ic),
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for ExecError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.error, self.at)
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> Error for ExecError<E> {}
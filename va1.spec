@@ -0,0 +1,69 @@
+# Cases for va1.mm, the VALGOL I compiler, run with
+#   meta test meta_mach_pgms/va1.mm va1.spec
+
+=== precedence @ EXP
+A + B * 2
+--- output
+        LD  A
+        LD  B
+        LDL 2
+        MLT
+        ADD
+
+=== unary operators @ EXP
+- A .OR .NOT B
+--- output
+        LD  A
+        NEG
+        LD  B
+        NOT
+        OR
+
+=== comparison @ RELATION
+A .<= 10
+--- output
+        LD  A
+        LDL 10
+        LEQ
+
+=== until loop @ ST
+.UNTIL I .= 10 .DO I + 1 = I
+--- output
+A001
+        LD  I
+        LDL 10
+        EQU
+        BTP  B001
+        LD  I
+        LDL 1
+        ADD
+        ST  I
+        B  A001
+B001
+
+=== print only
+.BEGIN PRINT .END
+--- output
+        PNT
+        HLT
+        END
+
+=== missing operand
+.BEGIN
+.REAL X;
+X + = X
+.END
+--- error at 3:5
+
+=== unterminated block
+.BEGIN PRINT
+--- error
+
+=== statements recovered
+.BEGIN
+.REAL X;
+X + = X;
+.IF X .THEN PRINT .ELSE 1 = ;
+PRINT
+.END
+--- error at 3:5
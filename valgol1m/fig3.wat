@@ -0,0 +1,101 @@
+(module
+  (import "valgol" "edt" (func $edt (param f64 i32 i32)))
+  (import "valgol" "pnt" (func $pnt))
+  (memory (export "memory") 1)
+  (data (i32.const 24) "*")
+  (func (export "main")
+    (local $s0 f64) (local $s1 f64)
+    ;; B A01
+    ;; A01
+    ;; LDL 0
+    f64.const 0.0
+    local.set $s0
+    ;; ST X
+    i32.const 16
+    local.get $s0
+    f64.store
+    loop $L3
+      ;; A02
+      ;; LD X
+      i32.const 16
+      f64.load
+      local.set $s0
+      ;; LDL 3
+      f64.const 3.0
+      local.set $s1
+      ;; EQU
+      local.get $s1
+      local.get $s0
+      f64.sub
+      f64.abs
+      f64.const 1e-6
+      f64.lt
+      f64.convert_i32_u
+      local.set $s0
+      ;; BTP A03
+      local.get $s0
+      f64.const 0
+      f64.ne
+      if
+        ;; A03
+        ;; HLT
+        return
+      else
+        ;; LD X
+        i32.const 16
+        f64.load
+        local.set $s0
+        ;; LD X
+        i32.const 16
+        f64.load
+        local.set $s1
+        ;; MLT
+        local.get $s1
+        local.get $s0
+        f64.mul
+        local.set $s0
+        ;; LDL 10
+        f64.const 10.0
+        local.set $s1
+        ;; MLT
+        local.get $s1
+        local.get $s0
+        f64.mul
+        local.set $s0
+        ;; LDL 1
+        f64.const 1.0
+        local.set $s1
+        ;; ADD
+        local.get $s1
+        local.get $s0
+        f64.add
+        local.set $s0
+        ;; EDT '*'
+        local.get $s0
+        i32.const 24
+        i32.const 1
+        call $edt
+        ;; PNT
+        call $pnt
+        ;; LD X
+        i32.const 16
+        f64.load
+        local.set $s0
+        ;; LDL 0.1
+        f64.const 0.1
+        local.set $s1
+        ;; ADD
+        local.get $s1
+        local.get $s0
+        f64.add
+        local.set $s0
+        ;; ST X
+        i32.const 16
+        local.get $s0
+        f64.store
+        ;; B A02
+        br $L3
+      end
+    end
+  )
+)
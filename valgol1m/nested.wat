@@ -0,0 +1,243 @@
+(module
+  (import "valgol" "edt" (func $edt (param f64 i32 i32)))
+  (import "valgol" "edn" (func $edn (param f64 f64 f64 f64)))
+  (import "valgol" "pnt" (func $pnt))
+  (import "valgol" "rd" (func $rd (result f64)))
+  (import "valgol" "fail" (func $fail (param i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 48) "BIG")
+  (data (i32.const 51) "SMALL")
+  (func (export "main")
+    (local $s0 f64) (local $s1 f64) (local $s2 f64) (local $s3 f64)
+    ;; B A001
+    ;; A001
+    ;; RD N
+    i32.const 16
+    call $rd
+    f64.store
+    ;; LDL 0
+    f64.const 0.0
+    local.set $s0
+    ;; ST I
+    i32.const 24
+    local.get $s0
+    f64.store
+    loop $L4
+      ;; A002
+      ;; LD I
+      i32.const 24
+      f64.load
+      local.set $s0
+      ;; LD N
+      i32.const 16
+      f64.load
+      local.set $s1
+      ;; EQU
+      local.get $s1
+      local.get $s0
+      f64.sub
+      f64.abs
+      f64.const 1e-6
+      f64.lt
+      f64.convert_i32_u
+      local.set $s0
+      ;; BTP B001
+      local.get $s0
+      f64.const 0
+      f64.ne
+      if
+        ;; B001
+        ;; HLT
+        return
+      else
+        ;; LDL 0
+        f64.const 0.0
+        local.set $s0
+        ;; ST S
+        i32.const 40
+        local.get $s0
+        f64.store
+        ;; LDL 0
+        f64.const 0.0
+        local.set $s0
+        ;; ST J
+        i32.const 32
+        local.get $s0
+        f64.store
+        loop $L12
+          ;; A003
+          ;; LD J
+          i32.const 32
+          f64.load
+          local.set $s0
+          ;; LD I
+          i32.const 24
+          f64.load
+          local.set $s1
+          ;; GTR
+          local.get $s0
+          local.get $s1
+          f64.gt
+          local.get $s0
+          local.get $s1
+          f64.sub
+          f64.abs
+          f64.const 1e-6
+          f64.ge
+          i32.and
+          f64.convert_i32_u
+          local.set $s0
+          ;; BTP B002
+          local.get $s0
+          f64.const 0
+          f64.ne
+          if
+            block $B36
+              ;; B002
+              ;; LD S
+              i32.const 40
+              f64.load
+              local.set $s0
+              ;; LDL 2
+              f64.const 2.0
+              local.set $s1
+              ;; DIV
+              local.get $s1
+              f64.const 0
+              f64.eq
+              if
+                i32.const 2
+                i32.const 27
+                call $fail
+                unreachable
+              end
+              local.get $s0
+              local.get $s1
+              f64.div
+              local.set $s0
+              ;; LDL 3
+              f64.const 3.0
+              local.set $s1
+              ;; GEQ
+              local.get $s0
+              local.get $s1
+              f64.gt
+              local.get $s0
+              local.get $s1
+              f64.sub
+              f64.abs
+              f64.const 1e-6
+              f64.lt
+              i32.or
+              f64.convert_i32_u
+              local.set $s0
+              ;; BFP A004
+              local.get $s0
+              f64.const 0
+              f64.eq
+              if
+                ;; A004
+                ;; LDL 0
+                f64.const 0.0
+                local.set $s0
+                ;; EDT 'SMALL'
+                local.get $s0
+                i32.const 51
+                i32.const 5
+                call $edt
+                br $B36
+              else
+                ;; LDL 0
+                f64.const 0.0
+                local.set $s0
+                ;; EDT 'BIG'
+                local.get $s0
+                i32.const 48
+                i32.const 3
+                call $edt
+                ;; B B003
+                br $B36
+              end
+            end
+            ;; B003
+            ;; LDL 6
+            f64.const 6.0
+            local.set $s0
+            ;; LD S
+            i32.const 40
+            f64.load
+            local.set $s1
+            ;; LDL 6
+            f64.const 6.0
+            local.set $s2
+            ;; LDL 1
+            f64.const 1.0
+            local.set $s3
+            ;; EDN
+            local.get $s0
+            local.get $s1
+            local.get $s2
+            local.get $s3
+            call $edn
+            ;; PNT
+            call $pnt
+            ;; LD I
+            i32.const 24
+            f64.load
+            local.set $s0
+            ;; LDL 1
+            f64.const 1.0
+            local.set $s1
+            ;; ADD
+            local.get $s1
+            local.get $s0
+            f64.add
+            local.set $s0
+            ;; ST I
+            i32.const 24
+            local.get $s0
+            f64.store
+            ;; B A002
+            br $L4
+          else
+            ;; LD S
+            i32.const 40
+            f64.load
+            local.set $s0
+            ;; LD J
+            i32.const 32
+            f64.load
+            local.set $s1
+            ;; ADD
+            local.get $s1
+            local.get $s0
+            f64.add
+            local.set $s0
+            ;; ST S
+            i32.const 40
+            local.get $s0
+            f64.store
+            ;; LD J
+            i32.const 32
+            f64.load
+            local.set $s0
+            ;; LDL 1
+            f64.const 1.0
+            local.set $s1
+            ;; ADD
+            local.get $s1
+            local.get $s0
+            f64.add
+            local.set $s0
+            ;; ST J
+            i32.const 32
+            local.get $s0
+            f64.store
+            ;; B A003
+            br $L12
+          end
+        end
+      end
+    end
+  )
+)
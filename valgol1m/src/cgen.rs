@@ -0,0 +1,444 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use mparse::MProgram;
+
+use crate::{MInstr, EPS, PRINT_AREA_SIZE};
+
+const STACK_SIZE: usize = 1024;
+
+const PRELUDE: &str = r#"#include <math.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+"#;
+
+const HELPERS: &str = r#"
+static void fail(int ic, const char *err, const char *arg)
+{
+    fflush(stdout);
+    fprintf(stderr, "error: %s%s at %s\n", err, arg, at[ic]);
+    exit(1);
+}
+
+static double underflow(int ic)
+{
+    fail(ic, "machine stack underflow", "");
+    return 0.0;
+}
+
+#define POP(ic) (sp > 0 ? stack[--sp] : underflow(ic))
+#define PUSH(ic, v)                                  \
+    do {                                             \
+        if (sp == STACK_SIZE)                        \
+            fail(ic, "machine stack overflow", "");  \
+        stack[sp++] = (v);                           \
+    } while (0)
+"#;
+
+const PLACE: &str = r#"
+static void place(double n, const char *s)
+{
+    size_t sz = strlen(s);
+    if (n < 0.0 || n + sz > PRINT_AREA_SIZE)
+        return;
+    memcpy(area + (size_t)n, s, sz);
+}
+"#;
+
+const EDN: &str = r#"
+static void edn(double n, double v, double width, double decimals)
+{
+    int w = width > 0.0 ? (int)round(fmin(width, PRINT_AREA_SIZE)) : 0;
+    int d = decimals > 0.0 ? (int)round(fmin(decimals, PRINT_AREA_SIZE)) : 0;
+    int len = snprintf(NULL, 0, "%*.*f", w, d, v);
+    char *s = malloc((len > w ? len : w) + 1);
+    if (s == NULL)
+        return;
+    if (len > w) {
+        memset(s, '*', w);
+        s[w] = '\0';
+    } else {
+        snprintf(s, len + 1, "%*.*f", w, d, v);
+    }
+    place(round(n), s);
+    free(s);
+}
+"#;
+
+const PNT: &str = r#"
+static void pnt(void)
+{
+    int len = PRINT_AREA_SIZE;
+    while (len > 0 && (area[len - 1] == ' ' || area[len - 1] == '\t'))
+        len--;
+    printf("%.*s\n", len, area);
+    memset(area, ' ', PRINT_AREA_SIZE);
+}
+"#;
+
+const RD: &str = r#"
+static double rd(int ic)
+{
+    char tok[256];
+    char *end;
+    double v;
+    if (scanf("%255s", tok) != 1)
+        fail(ic, "end of input", "");
+    v = strtod(tok, &end);
+    if (*end != '\0')
+        fail(ic, "invalid input ", tok);
+    return v;
+}
+"#;
+
+/// Translates `pgm` to a self-contained C program printing what the
+/// interpreter would print. Memory cells become a `double` array, the
+/// machine stack a local array of `STACK_SIZE` cells and branches
+/// `goto` statements. Uninitialized cells read as zero.
+pub fn to_c(pgm: &MProgram<MInstr>) -> String {
+    let has = |f: fn(&MInstr) -> bool| pgm.instrs.iter().any(f);
+    let uses_edn = has(|i| matches!(i, MInstr::EDN));
+    let uses_place = uses_edn || has(|i| matches!(i, MInstr::EDT(_)));
+    let uses_area = uses_place || has(|i| matches!(i, MInstr::PNT));
+    let uses_rd = has(|i| matches!(i, MInstr::RD(_, _)));
+
+    let mut c = String::new();
+    c.push_str("/* generated by valgol1m */\n");
+    c.push_str(PRELUDE);
+    c.push('\n');
+    writeln!(c, "#define MEM_SIZE {}", pgm.mem_size().max(1)).unwrap();
+    writeln!(c, "#define STACK_SIZE {STACK_SIZE}").unwrap();
+    writeln!(c, "#define PRINT_AREA_SIZE {PRINT_AREA_SIZE}").unwrap();
+    writeln!(c, "#define EPS {EPS:?}").unwrap();
+    c.push('\n');
+
+    let mut vars: Vec<(&u32, &String)> = pgm
+        .labels
+        .iter()
+        .filter(|(_, addr)| reserved(pgm, **addr))
+        .map(|(var, addr)| (addr, var))
+        .collect();
+    vars.sort();
+    for (addr, var) in vars {
+        writeln!(c, "/* {var}: mem[{addr}] */").unwrap();
+    }
+    c.push_str("static double mem[MEM_SIZE];\n");
+    if uses_area {
+        c.push_str("static char area[PRINT_AREA_SIZE];\n");
+    }
+    c.push_str("static const char *const at[] = {\n");
+    for ic in 0..=pgm.instrs.len() {
+        writeln!(c, "    \"{}\",", pgm.location(ic)).unwrap();
+    }
+    c.push_str("};\n");
+
+    c.push_str(HELPERS);
+    if uses_place {
+        c.push_str(PLACE);
+    }
+    if uses_edn {
+        c.push_str(EDN);
+    }
+    if uses_area {
+        c.push_str(PNT);
+    }
+    if uses_rd {
+        c.push_str(RD);
+    }
+
+    let targets: HashSet<usize> = pgm
+        .instrs
+        .iter()
+        .filter_map(|instr| match instr {
+            MInstr::B(_, t) | MInstr::BFP(_, t) | MInstr::BTP(_, t) => Some(*t),
+            _ => None,
+        })
+        .collect();
+    let temps = pgm.instrs.iter().map(temps_of).max().unwrap_or(0);
+
+    c.push_str("\nint main(void)\n{\n");
+    c.push_str("    double stack[STACK_SIZE];\n");
+    c.push_str("    int sp = 0;\n");
+    if temps > 0 {
+        writeln!(c, "    double {};", TEMPS[..temps].join(", ")).unwrap();
+    }
+    if uses_area {
+        c.push_str("\n    memset(area, ' ', PRINT_AREA_SIZE);\n");
+    }
+    for (ic, instr) in pgm.instrs.iter().enumerate() {
+        emit_labels(&mut c, pgm, ic, &targets);
+        writeln!(c, "    {} /* {instr} */", statement(pgm, ic, instr)).unwrap();
+    }
+    emit_labels(&mut c, pgm, pgm.instrs.len(), &targets);
+    writeln!(
+        c,
+        "    fail({}, \"execution past the end of the program\", \"\");",
+        pgm.instrs.len()
+    )
+    .unwrap();
+    c.push_str("    return 1;\n}\n");
+    c
+}
+
+const TEMPS: [&str; 4] = ["a", "b", "c", "d"];
+
+fn temps_of(instr: &MInstr) -> usize {
+    match instr {
+        MInstr::EDN => 4,
+        MInstr::ADD
+        | MInstr::SUB
+        | MInstr::MLT
+        | MInstr::DIV
+        | MInstr::EQU
+        | MInstr::NEQ
+        | MInstr::LSS
+        | MInstr::GTR
+        | MInstr::LEQ
+        | MInstr::GEQ
+        | MInstr::AND
+        | MInstr::OR => 2,
+        MInstr::NEG | MInstr::NOT | MInstr::DUP => 1,
+        _ => 0,
+    }
+}
+
+fn reserved(pgm: &MProgram<MInstr>, addr: u32) -> bool {
+    pgm.blocks.iter().any(|block| block.contains(&addr))
+}
+
+fn emit_labels(c: &mut String, pgm: &MProgram<MInstr>, ic: usize, targets: &HashSet<usize>) {
+    let mut labels: Vec<&str> = pgm
+        .labels
+        .iter()
+        .filter(|(_, addr)| !reserved(pgm, **addr) && pgm.ic.get(addr) == Some(&ic))
+        .map(|(label, _)| label.as_str())
+        .collect();
+    labels.sort();
+    for label in labels {
+        writeln!(c, "    /* {label} */").unwrap();
+    }
+    if targets.contains(&ic) {
+        writeln!(c, "L{ic}:").unwrap();
+    }
+}
+
+fn statement(pgm: &MProgram<MInstr>, ic: usize, instr: &MInstr) -> String {
+    let binary = |op: &str| format!("a = POP({ic}); b = POP({ic}); PUSH({ic}, {op});");
+    let checked = |loc: u32, stmt: String| {
+        if reserved(pgm, loc) {
+            stmt
+        } else {
+            format!("fail({ic}, \"address {loc} out of memory range\", \"\");")
+        }
+    };
+    match instr {
+        MInstr::B(_, t) => format!("goto L{t};"),
+        MInstr::BFP(_, t) => format!("if (POP({ic}) == 0.0) goto L{t};"),
+        MInstr::BTP(_, t) => format!("if (POP({ic}) != 0.0) goto L{t};"),
+        MInstr::LDL(v) => format!("PUSH({ic}, {});", literal(*v)),
+        MInstr::LD(_, loc) => checked(*loc, format!("PUSH({ic}, mem[{loc}]);")),
+        MInstr::ST(_, loc) => checked(*loc, format!("mem[{loc}] = POP({ic});")),
+        MInstr::RD(_, loc) => checked(*loc, format!("mem[{loc}] = rd({ic});")),
+        // operands are popped top first, as the interpreter does
+        MInstr::ADD => binary("a + b"),
+        MInstr::SUB => binary("b - a"),
+        MInstr::MLT => binary("a * b"),
+        MInstr::EQU => binary("fabs(a - b) < EPS"),
+        MInstr::DIV => format!(
+            "a = POP({ic}); b = POP({ic}); \
+             if (a == 0.0) fail({ic}, \"division by zero\", \"\"); PUSH({ic}, b / a);"
+        ),
+        MInstr::NEQ => binary("fabs(b - a) >= EPS"),
+        MInstr::LSS => binary("b < a && fabs(b - a) >= EPS"),
+        MInstr::GTR => binary("b > a && fabs(b - a) >= EPS"),
+        MInstr::LEQ => binary("b < a || fabs(b - a) < EPS"),
+        MInstr::GEQ => binary("b > a || fabs(b - a) < EPS"),
+        MInstr::AND => binary("b != 0.0 && a != 0.0"),
+        MInstr::OR => binary("b != 0.0 || a != 0.0"),
+        MInstr::NEG => format!("a = POP({ic}); PUSH({ic}, -a);"),
+        MInstr::DUP => format!("a = POP({ic}); PUSH({ic}, a); PUSH({ic}, a);"),
+        MInstr::NOT => format!("a = POP({ic}); PUSH({ic}, a == 0.0);"),
+        MInstr::EDT(s) if !s.is_ascii() => {
+            format!(
+                "(void)POP({ic}); fail({ic}, \"non-ASCII text \", {});",
+                string(s)
+            )
+        }
+        MInstr::EDT(s) => format!("place(round(POP({ic})), {});", string(s)),
+        MInstr::EDN => {
+            format!("a = POP({ic}); b = POP({ic}); c = POP({ic}); d = POP({ic}); edn(d, c, b, a);")
+        }
+        MInstr::PNT => "pnt();".to_string(),
+        MInstr::HLT => "return 0;".to_string(),
+        MInstr::Undef => format!("fail({ic}, \"invalid instruction\", \"\");"),
+    }
+}
+
+fn literal(v: f64) -> String {
+    if v.is_nan() {
+        "NAN".to_string()
+    } else if v.is_infinite() {
+        format!("{}INFINITY", if v < 0.0 { "-" } else { "" })
+    } else {
+        format!("{v:?}")
+    }
+}
+
+fn string(s: &str) -> String {
+    let mut lit = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' => {
+                lit.push('\\');
+                lit.push(b as char);
+            }
+            b' '..=b'~' if b != b'?' => lit.push(b as char),
+            // octal escapes also keep `?` out of trigraphs
+            _ => write!(lit, "\\{b:03o}").unwrap(),
+        }
+    }
+    lit.push('"');
+    lit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{output_lines, M};
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+    use std::process::{Command, Output};
+
+    fn compile_and_run(name: &str, pgm: &MProgram<MInstr>, input: &str) -> Output {
+        let dir = std::env::temp_dir().join(format!("valgol1m-cgen-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("temp dir");
+        let src = dir.join(format!("{name}.c"));
+        let exe: PathBuf = dir.join(name);
+        fs::write(&src, to_c(pgm)).expect("write C source");
+        let cc = Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Werror", "-o"])
+            .arg(&exe)
+            .arg(&src)
+            .arg("-lm")
+            .output()
+            .expect("run cc");
+        assert!(
+            cc.status.success(),
+            "cc failed: {}",
+            String::from_utf8_lossy(&cc.stderr)
+        );
+        let mut child = Command::new(&exe)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .expect("run compiled program");
+        io::Write::write_all(&mut child.stdin.take().unwrap(), input.as_bytes())
+            .expect("write input");
+        child.wait_with_output().expect("compiled program output")
+    }
+
+    #[test]
+    fn fig3_matches_interpreter() {
+        let p = mparse::parse::<MInstr>(include_str!("../fig3.va1m")).expect("program");
+        let expected = output_lines(&p, io::empty()).expect("interpreter");
+        let optimized = crate::optimize(&p);
+        for (name, pgm) in [("fig3", &p), ("fig3_opt", &optimized)] {
+            let out = compile_and_run(name, pgm, "");
+            assert!(out.status.success());
+            let lines: Vec<String> = String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .map(String::from)
+                .collect();
+            assert_eq!(lines, expected);
+        }
+    }
+
+    #[test]
+    fn io_and_errors() {
+        let p = mparse::parse::<MInstr>(
+            r#"
+X
+        BLK     1
+A
+        RD      X
+        LDL     2
+        LD      X
+        LDL     8
+        LDL     2
+        EDN
+        LDL     0
+        EDT     'x'
+        PNT
+        LD      X
+        LDL     0
+        LSS
+        BFP     A
+        LD      X
+        LDL     -2
+        EQU
+        BFP     B
+        LDL     0
+        EDT     'é'
+B
+        LDL     1
+        LDL     0
+        DIV
+        HLT
+"#,
+        )
+        .expect("program");
+        for (name, input) in [("div", "3.14159 -1\n"), ("eof", "2"), ("text", "-2\n")] {
+            let mut m = M::with_io(input.as_bytes(), Vec::new());
+            let e = m.execute(&p).expect_err("runtime error");
+            let out = compile_and_run(name, &p, input);
+            assert!(!out.status.success());
+            assert_eq!(out.stdout, m.into_output());
+            assert_eq!(
+                String::from_utf8_lossy(&out.stderr),
+                format!("error: {e}\n")
+            );
+        }
+    }
+
+    #[test]
+    fn edn_width_limited() {
+        let p = mparse::parse::<MInstr>(
+            r#"
+X
+        BLK     1
+        LDL     0
+        ST      X
+        LD      X
+        LDL     1
+        LDL     1000000000000000000
+        LDL     1000000000000000000
+        EDN
+        PNT
+        LDL     0
+        LDL     1
+        LDL     1000000000000000000
+        LDL     2
+        EDN
+        PNT
+        HLT
+"#,
+        )
+        .expect("program");
+        let expected = output_lines(&p, io::empty()).expect("interpreter");
+        let out = compile_and_run("edn", &p, "");
+        assert!(out.status.success());
+        let lines: Vec<String> = String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn string_literals() {
+        assert_eq!(string("a\"?\\é"), r#""a\"\077\\\303\251""#);
+    }
+}
@@ -0,0 +1,333 @@
+use std::error::Error;
+use std::io::BufRead;
+use std::io::Write;
+
+use mparse::MProgram;
+
+use crate::{Cell, ExecError, MInstr, M};
+
+const HELP: &str = "\
+commands:
+  break LABEL|IC    stop before executing the instruction
+  delete LABEL|IC   remove a breakpoint
+  watch VAR         stop when the variable changes
+  unwatch VAR       remove a watchpoint
+  step [N]          execute N instructions (default 1)
+  continue          run until a breakpoint, watchpoint or the end
+  where             show the next instruction
+  stack             show the operand stack, top last
+  vars [VAR...]     show variables
+  area              show the print area
+  quit              end the session";
+
+enum Stop {
+    Breakpoint,
+    Watch(String, Option<f64>, Option<f64>),
+    Steps,
+    Halted,
+    Failed(ExecError),
+}
+
+/// An interactive session over a machine executing `pgm`, reading
+/// commands from `cmds` and reporting to `out`.
+pub struct Debugger<'p, C: BufRead, O: Write> {
+    pgm: &'p MProgram<MInstr>,
+    cmds: C,
+    out: O,
+    ic: usize,
+    finished: bool,
+    breakpoints: Vec<usize>,
+    watches: Vec<(String, u32)>,
+}
+
+impl<'p, C: BufRead, O: Write> Debugger<'p, C, O> {
+    pub fn new(pgm: &'p MProgram<MInstr>, cmds: C, out: O) -> Self {
+        Debugger {
+            pgm,
+            cmds,
+            out,
+            ic: 0,
+            finished: false,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+        }
+    }
+
+    pub fn into_output(self) -> O {
+        self.out
+    }
+
+    /// Runs the session until `quit` or the end of the commands.
+    pub fn run<R: BufRead, W: Write>(&mut self, m: &mut M<R, W>) -> Result<(), Box<dyn Error>> {
+        m.layout(self.pgm);
+        self.show_where()?;
+        loop {
+            write!(self.out, "(vdb) ")?;
+            self.out.flush()?;
+            let mut line = String::new();
+            if self.cmds.read_line(&mut line)? == 0 {
+                writeln!(self.out)?;
+                return Ok(());
+            }
+            let mut words = line.split_ascii_whitespace();
+            let cmd = words.next().unwrap_or("step");
+            let args: Vec<&str> = words.collect();
+            match cmd {
+                "b" | "break" => self.set_breakpoint(&args, true)?,
+                "d" | "delete" => self.set_breakpoint(&args, false)?,
+                "w" | "watch" => self.set_watch(&args, true)?,
+                "unwatch" => self.set_watch(&args, false)?,
+                "s" | "step" => {
+                    let n = match args.first() {
+                        Some(n) => match n.parse::<usize>() {
+                            Ok(n) => n,
+                            Err(_) => {
+                                writeln!(self.out, "invalid count {n}")?;
+                                continue;
+                            }
+                        },
+                        None => 1,
+                    };
+                    self.resume(m, Some(n))?;
+                }
+                "c" | "continue" => self.resume(m, None)?,
+                "where" => self.show_where()?,
+                "stack" => writeln!(self.out, "{:?}", m.stack)?,
+                "v" | "vars" => self.show_vars(m, &args)?,
+                "area" => writeln!(self.out, "|{}|", m.print_area.trim_end())?,
+                "q" | "quit" => return Ok(()),
+                "h" | "help" => writeln!(self.out, "{HELP}")?,
+                _ => writeln!(self.out, "unknown command {cmd}, try help")?,
+            }
+        }
+    }
+
+    fn resolve_ic(&self, arg: &str) -> Option<usize> {
+        if let Ok(ic) = arg.parse::<usize>() {
+            return (ic < self.pgm.instrs.len()).then_some(ic);
+        }
+        let addr = self.pgm.labels.get(arg)?;
+        self.pgm
+            .ic
+            .get(addr)
+            .copied()
+            .filter(|ic| *ic < self.pgm.instrs.len())
+    }
+
+    fn resolve_var(&self, var: &str) -> Option<u32> {
+        let addr = *self.pgm.labels.get(var)?;
+        self.pgm
+            .blocks
+            .iter()
+            .any(|block| block.contains(&addr))
+            .then_some(addr)
+    }
+
+    fn set_breakpoint(&mut self, args: &[&str], on: bool) -> Result<(), Box<dyn Error>> {
+        let Some(arg) = args.first() else {
+            writeln!(self.out, "missing label or ic")?;
+            return Ok(());
+        };
+        let Some(ic) = self.resolve_ic(arg) else {
+            writeln!(self.out, "no instruction at {arg}")?;
+            return Ok(());
+        };
+        self.breakpoints.retain(|b| *b != ic);
+        if on {
+            self.breakpoints.push(ic);
+            writeln!(self.out, "breakpoint at ic {ic}")?;
+        }
+        Ok(())
+    }
+
+    fn set_watch(&mut self, args: &[&str], on: bool) -> Result<(), Box<dyn Error>> {
+        let Some(var) = args.first() else {
+            writeln!(self.out, "missing variable")?;
+            return Ok(());
+        };
+        let Some(addr) = self.resolve_var(var) else {
+            writeln!(self.out, "unknown variable {var}")?;
+            return Ok(());
+        };
+        self.watches.retain(|(_, a)| *a != addr);
+        if on {
+            self.watches.push((var.to_string(), addr));
+            writeln!(self.out, "watching {var}")?;
+        }
+        Ok(())
+    }
+
+    fn resume<R: BufRead, W: Write>(
+        &mut self,
+        m: &mut M<R, W>,
+        mut steps: Option<usize>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.finished {
+            writeln!(self.out, "program finished")?;
+            return Ok(());
+        }
+        // the instruction resumed from may itself be a breakpoint
+        let mut resuming = true;
+        let stop = loop {
+            if !resuming && self.breakpoints.contains(&self.ic) {
+                break Stop::Breakpoint;
+            }
+            resuming = false;
+            if steps == Some(0) {
+                break Stop::Steps;
+            }
+            let before: Vec<Option<f64>> = self.watches.iter().map(|(_, a)| m.value(*a)).collect();
+            match m.step(self.pgm, self.ic) {
+                Ok(Some(next)) => self.ic = next,
+                Ok(None) => break Stop::Halted,
+                Err(e) => break Stop::Failed(ExecError::new(e, self.ic, self.pgm)),
+            }
+            steps = steps.map(|n| n - 1);
+            let changed = self
+                .watches
+                .iter()
+                .zip(before)
+                .find(|((_, a), old)| m.value(*a) != *old);
+            if let Some(((var, a), old)) = changed {
+                break Stop::Watch(var.clone(), old, m.value(*a));
+            }
+        };
+        match stop {
+            Stop::Halted => {
+                self.finished = true;
+                writeln!(self.out, "program halted")?;
+                return Ok(());
+            }
+            Stop::Failed(e) => {
+                self.finished = true;
+                writeln!(self.out, "error: {e}")?;
+                return Ok(());
+            }
+            Stop::Breakpoint => writeln!(self.out, "breakpoint")?,
+            Stop::Watch(var, old, new) => {
+                writeln!(self.out, "{var}: {} -> {}", show(old), show(new))?
+            }
+            Stop::Steps => (),
+        }
+        self.show_where()
+    }
+
+    fn show_where(&mut self) -> Result<(), Box<dyn Error>> {
+        let label = self.pgm.location(self.ic).label_text();
+        match self.pgm.instrs.get(self.ic) {
+            Some(instr) => writeln!(self.out, "{:>4} {label:<10} {instr}", self.ic)?,
+            None => writeln!(self.out, "{:>4} {label:<10} <end>", self.ic)?,
+        }
+        Ok(())
+    }
+
+    fn show_vars<R: BufRead, W: Write>(
+        &mut self,
+        m: &M<R, W>,
+        args: &[&str],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut vars: Vec<(&str, u32)> = self
+            .pgm
+            .labels
+            .keys()
+            .filter(|var| args.is_empty() || args.contains(&var.as_str()))
+            .filter_map(|var| Some((var.as_str(), self.resolve_var(var)?)))
+            .collect();
+        vars.sort_by_key(|(var, addr)| (*addr, *var));
+        for (var, addr) in vars {
+            let v = match m.mem.get(addr as usize) {
+                Some(Cell::Set(v)) => Some(*v),
+                _ => None,
+            };
+            writeln!(self.out, "{var} = {}", show(v))?;
+        }
+        Ok(())
+    }
+}
+
+fn show(v: Option<f64>) -> String {
+    match v {
+        Some(v) => v.to_string(),
+        None => "unset".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn session(cmds: &str) -> String {
+        let p = mparse::parse::<MInstr>(include_str!("../fig3.va1m")).expect("program");
+        let mut m = M::with_io(io::empty(), Vec::new());
+        let mut d = Debugger::new(&p, cmds.as_bytes(), Vec::new());
+        d.run(&mut m).expect("session");
+        String::from_utf8(d.into_output()).expect("utf8 output")
+    }
+
+    #[test]
+    fn breakpoints_and_steps() {
+        let out = session("b A03\nb A02\nc\nvars\nstack\nstep 3\nstack\nd A02\nc\nc\n");
+        let expected = "   0            B A01
+(vdb) breakpoint at ic 21
+(vdb) breakpoint at ic 3
+(vdb) breakpoint
+   3 A02        LD X
+(vdb) X = 0
+(vdb) []
+(vdb)    6 A02+3      BTP A03
+(vdb) [0.0]
+(vdb) (vdb) breakpoint
+  21 A03        HLT
+(vdb) program halted
+(vdb) 
+";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn step_stops_at_breakpoints() {
+        let out = session("b A02\nstep 10\nstep 5\nstep 20\n");
+        let expected = "   0            B A01
+(vdb) breakpoint at ic 3
+(vdb) breakpoint
+   3 A02        LD X
+(vdb)    8 A02+5      LD X
+(vdb) breakpoint
+   3 A02        LD X
+(vdb) 
+";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn continue_leaves_the_breakpoint_it_stopped_at() {
+        let out = session("b A02\nc\nc\nvars X\n");
+        let expected = "   0            B A01
+(vdb) breakpoint at ic 3
+(vdb) breakpoint
+   3 A02        LD X
+(vdb) breakpoint
+   3 A02        LD X
+(vdb) X = 0.1
+(vdb) 
+";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn watch_and_area() {
+        let out = session("watch X\nc\nc\narea\nstep 13\narea\nunwatch X\nquit\n");
+        let expected = "   0            B A01
+(vdb) watching X
+(vdb) X: unset -> 0
+   3 A02        LD X
+(vdb) X: 0 -> 0.1
+  20 A02+17     B A02
+(vdb) ||
+(vdb)   15 A02+12     PNT
+(vdb) | *|
+(vdb) (vdb) ";
+        assert_eq!(out, expected);
+    }
+}
@@ -1,23 +1,153 @@
 use mparse::ParseableInstr;
-use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
 
-const PRINT_AREA_SIZE: usize = 100;
-const EPS: f64 = 0.000001;
+use mparse::dot::{ControlFlow, Flow};
+use mparse::profile::Profile;
+
+mod cgen;
+mod debug;
+mod opt;
+mod verify;
+mod wat;
+
+pub use cgen::to_c;
+pub use debug::Debugger;
+pub use opt::optimize;
+pub use verify::{verify, StackFault, VerifyError};
+pub use wat::{to_wat, WatError};
+
+use mparse::valgol::{self, EPS, PRINT_AREA_SIZE};
+
+#[derive(Debug, PartialEq)]
+pub enum RuntimeError {
+    StackUnderflow,
+    FellOffProgram,
+    InvalidInstruction,
+    DivisionByZero,
+    EndOfInput,
+    InvalidInput(String),
+    OutputFailed(String),
+    AddressOutOfRange(u32),
+    UninitializedRead(u32),
+    NonAsciiText(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            RuntimeError::StackUnderflow => "machine stack underflow",
+            RuntimeError::FellOffProgram => "execution past the end of the program",
+            RuntimeError::InvalidInstruction => "invalid instruction",
+            RuntimeError::DivisionByZero => "division by zero",
+            RuntimeError::EndOfInput => "end of input",
+            RuntimeError::InvalidInput(inp) => return write!(f, "invalid input {inp}"),
+            RuntimeError::OutputFailed(e) => return write!(f, "output failed: {e}"),
+            RuntimeError::AddressOutOfRange(loc) => {
+                return write!(f, "address {loc} out of memory range")
+            }
+            RuntimeError::UninitializedRead(loc) => {
+                return write!(f, "read of uninitialized address {loc}")
+            }
+            RuntimeError::NonAsciiText(s) => return write!(f, "non-ASCII text {s}"),
+        };
+        f.write_str(msg)
+    }
+}
+
+impl Error for RuntimeError {}
+
+pub type ExecError = valgol::ExecError<RuntimeError>;
+
+pub type Stdin = io::BufReader<io::Stdin>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cell {
+    Unreserved,
+    Unset,
+    Set(f64),
+}
 
 #[derive(Debug)]
-pub struct M {
-    mem: HashMap<u32, f64>,
+pub struct M<R: BufRead = Stdin, W: Write = io::Stdout> {
+    mem: Vec<Cell>,
+    check_uninit: bool,
     stack: Vec<f64>,
     print_area: String,
+    input: R,
+    // numbers of the current input line not yet read
+    pending: VecDeque<String>,
+    out: W,
+    profile: Option<Profile>,
 }
 
 impl M {
     pub fn new() -> Self {
+        M::with_input(io::BufReader::new(io::stdin()))
+    }
+}
+
+impl<R: BufRead> M<R> {
+    /// Creates a machine whose `RD` instructions read whitespace
+    /// separated numbers from `input`.
+    pub fn with_input(input: R) -> Self {
+        M::with_io(input, io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> M<R, W> {
+    /// Creates a machine reading from `input` whose `PNT` instructions
+    /// write lines to `out`.
+    pub fn with_io(input: R, out: W) -> Self {
         M {
-            mem: HashMap::new(),
+            mem: Vec::new(),
+            check_uninit: false,
             stack: Vec::new(),
             print_area: String::with_capacity(PRINT_AREA_SIZE),
+            input,
+            pending: VecDeque::new(),
+            out,
+            profile: None,
+        }
+    }
+
+    /// Makes `execute` collect a `Profile` of the run.
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    pub fn into_output(self) -> W {
+        self.out
+    }
+
+    /// Makes reads of cells never written since the start of `execute`
+    /// fail instead of producing 0.
+    pub fn check_uninitialized(&mut self, check: bool) {
+        self.check_uninit = check;
+    }
+
+    /// Returns the value of the memory cell at `loc` if it was written.
+    pub fn value(&self, loc: u32) -> Option<f64> {
+        match self.mem.get(loc as usize) {
+            Some(Cell::Set(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn layout(&mut self, pgm: &mparse::MProgram<MInstr>) {
+        self.mem.clear();
+        self.mem.resize(pgm.mem_size() as usize, Cell::Unreserved);
+        for block in pgm.blocks.iter() {
+            self.mem[block.start as usize..block.end as usize].fill(Cell::Unset);
         }
     }
 
@@ -25,108 +155,237 @@ impl M {
         self.stack.push(u);
     }
 
-    fn pop(&mut self) -> f64 {
-        self.stack.pop().expect("machine stack underflow")
+    fn pop(&mut self) -> Result<f64, RuntimeError> {
+        self.stack.pop().ok_or(RuntimeError::StackUnderflow)
     }
 
-    fn ld(&mut self, loc: u32) {
-        if let Some(v) = self.mem.get(&loc) {
-            self.push(*v)
-        } else {
-            self.push(0.0)
+    fn ld(&mut self, loc: u32) -> Result<(), RuntimeError> {
+        let v = match self.mem.get(loc as usize) {
+            Some(Cell::Set(v)) => *v,
+            Some(Cell::Unset) if self.check_uninit => {
+                return Err(RuntimeError::UninitializedRead(loc))
+            }
+            Some(Cell::Unset) => 0.0,
+            _ => return Err(RuntimeError::AddressOutOfRange(loc)),
+        };
+        self.push(v);
+        Ok(())
+    }
+
+    fn store(&mut self, loc: u32, v: f64) -> Result<(), RuntimeError> {
+        match self.mem.get_mut(loc as usize) {
+            Some(Cell::Unreserved) | None => Err(RuntimeError::AddressOutOfRange(loc)),
+            Some(cell) => {
+                *cell = Cell::Set(v);
+                Ok(())
+            }
         }
     }
 
-    fn st(&mut self, loc: u32) {
-        let v = self.pop();
-        self.mem.insert(loc, v);
+    fn st(&mut self, loc: u32) -> Result<(), RuntimeError> {
+        let v = self.pop()?;
+        self.store(loc, v)
     }
 
-    fn add(&mut self) {
-        let a = self.pop();
-        let b = self.pop();
+    fn add(&mut self) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        let b = self.pop()?;
         self.push(a + b);
+        Ok(())
     }
 
-    fn mlt(&mut self) {
-        let a = self.pop();
-        let b = self.pop();
+    fn mlt(&mut self) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        let b = self.pop()?;
         self.push(a * b);
+        Ok(())
     }
 
-    fn equ(&mut self) {
-        let a = self.pop();
-        let b = self.pop();
+    fn equ(&mut self) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        let b = self.pop()?;
         let mut fl = 0.0;
         if (a - b).abs() < EPS {
             fl = 1.0
         }
         self.push(fl);
+        Ok(())
     }
 
-    fn sub(&mut self) {
-        let a = self.pop();
-        let b = self.pop();
-        self.push(a - b);
+    fn sub(&mut self) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        let b = self.pop()?;
+        self.push(b - a);
+        Ok(())
     }
 
-    fn edt(&mut self, s: &str) {
-        let n = self.pop().round();
-        if n < 0.0 {
-            return;
+    fn div(&mut self) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        let b = self.pop()?;
+        if a == 0.0 {
+            return Err(RuntimeError::DivisionByZero);
         }
-        let start = n as usize;
-        let sz = s.len();
-        if start + sz > PRINT_AREA_SIZE {
-            return;
+        self.push(b / a);
+        Ok(())
+    }
+
+    fn neg(&mut self) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        self.push(-a);
+        Ok(())
+    }
+
+    fn dup(&mut self) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        self.push(a);
+        self.push(a);
+        Ok(())
+    }
+
+    fn cmp(&mut self, f: fn(f64, f64) -> bool) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        let b = self.pop()?;
+        self.push(if f(b, a) { 1.0 } else { 0.0 });
+        Ok(())
+    }
+
+    fn logic(&mut self, f: fn(bool, bool) -> bool) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        let b = self.pop()?;
+        self.push(if f(b != 0.0, a != 0.0) { 1.0 } else { 0.0 });
+        Ok(())
+    }
+
+    fn not(&mut self) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        self.push(if a == 0.0 { 1.0 } else { 0.0 });
+        Ok(())
+    }
+
+    fn edt(&mut self, s: &str) -> Result<(), RuntimeError> {
+        let n = self.pop()?.round();
+        // the print area is counted in bytes, as in the translations
+        if !s.is_ascii() {
+            return Err(RuntimeError::NonAsciiText(s.to_string()));
         }
-        if self.print_area.is_empty() {
-            self.print_area.push_str(&" ".repeat(PRINT_AREA_SIZE));
+        self.place(n, s);
+        Ok(())
+    }
+
+    fn place(&mut self, n: f64, s: &str) {
+        valgol::place(&mut self.print_area, n, s);
+    }
+
+    fn edn(&mut self) -> Result<(), RuntimeError> {
+        // no wider than the print area, whatever the program asks for
+        let limit = |x: f64| x.round().clamp(0.0, PRINT_AREA_SIZE as f64) as usize;
+        let decimals = limit(self.pop()?);
+        let width = limit(self.pop()?);
+        let v = self.pop()?;
+        let n = self.pop()?.round();
+        let mut s = format!("{v:>width$.decimals$}");
+        if s.len() > width {
+            s = "*".repeat(width);
         }
-        self.print_area.replace_range(start..start + sz, s);
+        self.place(n, &s);
+        Ok(())
     }
 
-    fn pnt(&mut self) {
-        println!("{}", self.print_area.trim_end());
+    fn rd(&mut self, loc: u32) -> Result<(), RuntimeError> {
+        while self.pending.is_empty() {
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) => return Err(RuntimeError::EndOfInput),
+                Ok(_) => self
+                    .pending
+                    .extend(line.split_ascii_whitespace().map(String::from)),
+                Err(e) => return Err(RuntimeError::InvalidInput(e.to_string())),
+            }
+        }
+        let inp = self.pending.pop_front().unwrap();
+        let v = inp
+            .parse::<f64>()
+            .map_err(|_| RuntimeError::InvalidInput(inp))?;
+        self.store(loc, v)
+    }
+
+    fn pnt(&mut self) -> Result<(), RuntimeError> {
+        writeln!(self.out, "{}", self.print_area.trim_end())
+            .map_err(|e| RuntimeError::OutputFailed(e.to_string()))?;
         self.print_area.truncate(0);
+        Ok(())
     }
 
-    pub fn execute(&mut self, pgm: &mparse::MProgram<MInstr>) {
+    pub fn execute(&mut self, pgm: &mparse::MProgram<MInstr>) -> Result<(), ExecError> {
+        self.layout(pgm);
+        if let Some(prof) = &mut self.profile {
+            *prof = Profile::new(pgm.instrs.len());
+        }
         let mut ic: usize = 0;
         loop {
-            match &pgm.instrs[ic] {
-                MInstr::Undef => panic!("Undef unexpected in program"),
-                MInstr::LDL(v) => {
-                    self.push(*v);
-                }
-                MInstr::LD(_, loc) => self.ld(*loc),
-                MInstr::ST(_, loc) => self.st(*loc),
-                MInstr::B(_, jic) => {
-                    ic = *jic;
-                    continue;
-                }
-                MInstr::BFP(_, jic) => {
-                    if self.pop() == 0.0 {
-                        ic = *jic;
-                        continue;
-                    }
+            if let Some(prof) = &mut self.profile {
+                prof.instr(ic);
+            }
+            match self.step(pgm, ic) {
+                Ok(Some(next)) => ic = next,
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(ExecError::new(e, ic, pgm)),
+            }
+        }
+    }
+
+    fn step(
+        &mut self,
+        pgm: &mparse::MProgram<MInstr>,
+        ic: usize,
+    ) -> Result<Option<usize>, RuntimeError> {
+        let instr = pgm.instrs.get(ic).ok_or(RuntimeError::FellOffProgram)?;
+        self.exec(instr, ic)
+    }
+
+    /// Executes `instr` found at `ic`, returning the next ic or `None`
+    /// when the machine halts.
+    fn exec(&mut self, instr: &MInstr, ic: usize) -> Result<Option<usize>, RuntimeError> {
+        match instr {
+            MInstr::Undef => return Err(RuntimeError::InvalidInstruction),
+            MInstr::LDL(v) => {
+                self.push(*v);
+            }
+            MInstr::LD(_, loc) => self.ld(*loc)?,
+            MInstr::ST(_, loc) => self.st(*loc)?,
+            MInstr::B(_, jic) => return Ok(Some(*jic)),
+            MInstr::BFP(_, jic) => {
+                if self.pop()? == 0.0 {
+                    return Ok(Some(*jic));
                 }
-                MInstr::BTP(_, jic) => {
-                    if self.pop() != 0.0 {
-                        ic = *jic;
-                        continue;
-                    }
+            }
+            MInstr::BTP(_, jic) => {
+                if self.pop()? != 0.0 {
+                    return Ok(Some(*jic));
                 }
-                MInstr::ADD => self.add(),
-                MInstr::SUB => self.sub(),
-                MInstr::MLT => self.mlt(),
-                MInstr::EQU => self.equ(),
-                MInstr::HLT => break,
-                MInstr::EDT(s) => self.edt(s),
-                MInstr::PNT => self.pnt(),
             }
-            ic += 1;
+            MInstr::ADD => self.add()?,
+            MInstr::SUB => self.sub()?,
+            MInstr::MLT => self.mlt()?,
+            MInstr::EQU => self.equ()?,
+            MInstr::DIV => self.div()?,
+            MInstr::NEG => self.neg()?,
+            MInstr::DUP => self.dup()?,
+            MInstr::NEQ => self.cmp(|b, a| (b - a).abs() >= EPS)?,
+            MInstr::LSS => self.cmp(|b, a| b < a && (b - a).abs() >= EPS)?,
+            MInstr::GTR => self.cmp(|b, a| b > a && (b - a).abs() >= EPS)?,
+            MInstr::LEQ => self.cmp(|b, a| b < a || (b - a).abs() < EPS)?,
+            MInstr::GEQ => self.cmp(|b, a| b > a || (b - a).abs() < EPS)?,
+            MInstr::AND => self.logic(|b, a| b && a)?,
+            MInstr::OR => self.logic(|b, a| b || a)?,
+            MInstr::NOT => self.not()?,
+            MInstr::HLT => return Ok(None),
+            MInstr::EDT(s) => self.edt(s)?,
+            MInstr::EDN => self.edn()?,
+            MInstr::RD(_, loc) => self.rd(*loc)?,
+            MInstr::PNT => self.pnt()?,
         }
+        Ok(Some(ic + 1))
     }
 }
 
@@ -136,7 +395,7 @@ impl Default for M {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MInstr {
     // branch
     B(String, usize),
@@ -147,12 +406,27 @@ pub enum MInstr {
     // memory
     ST(String, u32),
     LD(String, u32),
+    RD(String, u32),
     // operations
     EQU,
     ADD,
     MLT,
     SUB,
+    DIV,
+    NEG,
+    DUP,
+    // comparisons, operands in evaluation order
+    NEQ,
+    LSS,
+    GTR,
+    LEQ,
+    GEQ,
+    // boolean
+    AND,
+    OR,
+    NOT,
     EDT(String),
+    EDN,
     PNT,
     HLT,
     Undef,
@@ -161,6 +435,7 @@ pub enum MInstr {
 impl ParseableInstr for MInstr {
     const UNDEF: Self = MInstr::Undef;
     const ACCEPT_BLK: bool = true;
+    const ACCEPT_END_LABEL: bool = true;
 
     fn is_undefined(&self) -> bool {
         matches!(self, MInstr::Undef)
@@ -171,6 +446,7 @@ impl ParseableInstr for MInstr {
             "B" => MInstr::B(label, 0),
             "ST" => MInstr::ST(label, 0),
             "LD" => MInstr::LD(label, 0),
+            "RD" => MInstr::RD(label, 0),
             "BTP" => MInstr::BTP(label, 0),
             "BFP" => MInstr::BFP(label, 0),
             _ => MInstr::Undef,
@@ -197,6 +473,18 @@ impl ParseableInstr for MInstr {
             "ADD" => MInstr::ADD,
             "SUB" => MInstr::SUB,
             "MLT" => MInstr::MLT,
+            "DIV" => MInstr::DIV,
+            "NEG" => MInstr::NEG,
+            "DUP" => MInstr::DUP,
+            "NEQ" => MInstr::NEQ,
+            "LSS" => MInstr::LSS,
+            "GTR" => MInstr::GTR,
+            "LEQ" => MInstr::LEQ,
+            "GEQ" => MInstr::GEQ,
+            "AND" => MInstr::AND,
+            "OR" => MInstr::OR,
+            "NOT" => MInstr::NOT,
+            "EDN" => MInstr::EDN,
             "PNT" => MInstr::PNT,
             "HLT" => MInstr::HLT,
             _ => MInstr::Undef,
@@ -205,7 +493,9 @@ impl ParseableInstr for MInstr {
 
     fn aaa_of(&self) -> mparse::AAAUse {
         match self {
-            MInstr::ST(aaa, _) | MInstr::LD(aaa, _) => mparse::AAAUse::Mem(aaa.to_string()),
+            MInstr::ST(aaa, _) | MInstr::LD(aaa, _) | MInstr::RD(aaa, _) => {
+                mparse::AAAUse::Mem(aaa.to_string())
+            }
             MInstr::B(aaa, _) | MInstr::BFP(aaa, _) | MInstr::BTP(aaa, _) => {
                 mparse::AAAUse::IC(aaa.to_string())
             }
@@ -217,6 +507,7 @@ impl ParseableInstr for MInstr {
         *self = match self {
             MInstr::ST(_, _) => MInstr::ST(aaa, addr),
             MInstr::LD(_, _) => MInstr::LD(aaa, addr),
+            MInstr::RD(_, _) => MInstr::RD(aaa, addr),
             _ => panic!("internal error: unknown aaa instruction"),
         }
     }
@@ -231,26 +522,204 @@ impl ParseableInstr for MInstr {
     }
 }
 
+/// Executes `pgm` reading from `input` and returns the printed lines.
+pub fn output_lines<R: BufRead>(
+    pgm: &mparse::MProgram<MInstr>,
+    input: R,
+) -> Result<Vec<String>, ExecError> {
+    let mut m = M::with_io(input, Vec::new());
+    m.execute(pgm)?;
+    let out = String::from_utf8_lossy(&m.into_output()).into_owned();
+    Ok(out.lines().map(String::from).collect())
+}
+
+impl ControlFlow for MInstr {
+    fn flow(&self) -> Flow {
+        match self {
+            MInstr::B(_, ic) => Flow::Jump(*ic),
+            MInstr::BTP(_, ic) | MInstr::BFP(_, ic) => Flow::Branch(*ic),
+            MInstr::HLT | MInstr::Undef => Flow::Stop,
+            _ => Flow::Next,
+        }
+    }
+}
+
+impl fmt::Display for MInstr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MInstr::B(aaa, _) => write!(f, "B {aaa}"),
+            MInstr::BFP(aaa, _) => write!(f, "BFP {aaa}"),
+            MInstr::BTP(aaa, _) => write!(f, "BTP {aaa}"),
+            MInstr::LDL(n) => write!(f, "LDL {n}"),
+            MInstr::ST(aaa, _) => write!(f, "ST {aaa}"),
+            MInstr::LD(aaa, _) => write!(f, "LD {aaa}"),
+            MInstr::RD(aaa, _) => write!(f, "RD {aaa}"),
+            MInstr::EDT(s) => write!(f, "EDT '{s}'"),
+            MInstr::Undef => f.write_str("???"),
+            noarg => write!(f, "{noarg:?}"),
+        }
+    }
+}
+
 pub fn run(opts: Options) -> Result<(), Box<dyn Error>> {
-    let p = mparse::load::<MInstr>(&opts.pgm_path)?;
-    println!("{p:#?}");
-    let mut m = M::new();
-    m.execute(&p);
+    let mut p = mparse::load::<MInstr>(&opts.pgm_path)?;
+    if opts.optimize {
+        let optimized = optimize(&p);
+        eprintln!(
+            "optimized {} instructions to {}",
+            p.instrs.len(),
+            optimized.instrs.len()
+        );
+        p = optimized;
+    }
+    if opts.dump {
+        println!("{p:#?}");
+    }
+    match opts.command {
+        Command::C => {
+            print!("{}", to_c(&p));
+            Ok(())
+        }
+        Command::Wat => {
+            print!("{}", to_wat(&p)?);
+            Ok(())
+        }
+        Command::Dot => {
+            print!("{}", mparse::dot::to_dot(&p));
+            Ok(())
+        }
+        Command::Run | Command::Debug => run_machine(&p, &opts),
+    }
+}
+
+fn run_machine(p: &mparse::MProgram<MInstr>, opts: &Options) -> Result<(), Box<dyn Error>> {
+    let debug = opts.command == Command::Debug;
+    if opts.verify {
+        verify(p)?;
+    }
+    // stdin carries the debugger commands, so RD needs its own file
+    if debug && opts.input_path.is_none() && p.instrs.iter().any(|i| matches!(i, MInstr::RD(..))) {
+        return Err("debugging a program with RD needs an input file".into());
+    }
+    let input: Box<dyn BufRead> = match &opts.input_path {
+        Some(path) => Box::new(io::BufReader::new(fs::File::open(path)?)),
+        None if debug => Box::new(io::empty()),
+        None => Box::new(io::BufReader::new(io::stdin())),
+    };
+    let mut m = M::with_input(input);
+    m.check_uninitialized(opts.check_uninit);
+    if debug {
+        let mut d = Debugger::new(p, io::stdin().lock(), io::stdout());
+        return d.run(&mut m);
+    }
+    if opts.profile || opts.folded_path.is_some() {
+        m.enable_profile();
+    }
+    let res = m.execute(p);
+    if let Some(prof) = m.take_profile() {
+        if opts.profile {
+            prof.report(p, &mut io::stderr())?;
+        }
+        if let Some(path) = &opts.folded_path {
+            prof.write_folded(p, &mut fs::File::create(path)?)?;
+        }
+    }
+    res?;
     Ok(())
 }
 
+/// What `valgol1m` does, named by the first argument unless running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// `valgol1m PGM [INPUT]` runs the program.
+    Run,
+    /// `valgol1m debug PGM [INPUT]` runs the program under the debugger,
+    /// which reads its commands from stdin. Programs with `RD` need INPUT.
+    Debug,
+    /// `valgol1m c PGM` prints the program translated to C.
+    C,
+    /// `valgol1m wat PGM` prints the program translated to WebAssembly text.
+    Wat,
+    /// `valgol1m dot PGM` prints the basic block graph of the program.
+    Dot,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "debug" => Some(Command::Debug),
+            "c" => Some(Command::C),
+            "wat" => Some(Command::Wat),
+            "dot" => Some(Command::Dot),
+            _ => None,
+        }
+    }
+}
+
 pub struct Options {
+    pub command: Command,
     pub pgm_path: String,
+    pub input_path: Option<String>,
+    pub dump: bool,
+    pub optimize: bool,
+    /// Checks machine stack use before running, on unless `--no-verify`.
+    pub verify: bool,
+    pub check_uninit: bool,
+    pub profile: bool,
+    pub folded_path: Option<String>,
 }
 
 impl Options {
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Self, &'static str> {
-        args.next();
-        let pgm_path = match args.next() {
+    pub fn build(args: impl Iterator<Item = String>) -> Result<Self, &'static str> {
+        let mut dump = false;
+        let mut optimize = false;
+        let mut verify = true;
+        let mut check_uninit = false;
+        let mut profile = false;
+        let mut folded_path = None;
+        let mut positional = Vec::new();
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--dump" => dump = true,
+                "--optimize" => optimize = true,
+                "--no-verify" => verify = false,
+                "--check-uninit" => check_uninit = true,
+                "--profile" => profile = true,
+                "--folded" => match args.next() {
+                    Some(path) => folded_path = Some(path),
+                    None => return Err("missing folded stacks file argument"),
+                },
+                _ => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter().peekable();
+        let command = match positional.peek().and_then(|arg| Command::from_name(arg)) {
+            Some(command) => {
+                positional.next();
+                command
+            }
+            None => Command::Run,
+        };
+        let pgm_path = match positional.next() {
             Some(arg) => arg,
             None => return Err("missing program path argument"),
         };
-        Ok(Options { pgm_path })
+        let input_path = positional.next();
+        if positional.next().is_some() {
+            return Err("too many arguments");
+        }
+        Ok(Options {
+            command,
+            pgm_path,
+            input_path,
+            dump,
+            optimize,
+            verify,
+            check_uninit,
+            profile,
+            folded_path,
+        })
     }
 }
 
@@ -258,6 +727,172 @@ impl Options {
 mod tests {
     use super::*;
 
+    fn compile(mpgm: &str, source: &str) -> String {
+        let p = mparse::parse::<meta::MInstr>(mpgm).expect("meta machine program");
+        let mut m = meta::M::new(source);
+        m.execute(&p);
+        let mut out = m.generated().expect("compilation");
+        out.push('\n');
+        out
+    }
+
+    #[test]
+    fn va1_grammar_compiles() {
+        assert_eq!(
+            compile(
+                include_str!("../../meta_mach_pgms/meta.mm"),
+                include_str!("../../va1.syn")
+            ),
+            include_str!("../../meta_mach_pgms/va1.mm")
+        );
+        assert_eq!(
+            compile(
+                include_str!("../../meta_mach_pgms/va1.mm"),
+                include_str!("../fig3.va1")
+            ),
+            include_str!("../comp_fig3.va1m")
+        );
+    }
+
+    #[test]
+    fn fig3_plot() -> Result<(), ExecError> {
+        let p = mparse::parse::<MInstr>(include_str!("../fig3.va1m")).expect("program");
+        let lines = output_lines(&p, io::empty())?;
+        assert_eq!(lines.len(), 30);
+        assert_eq!(lines[0], " *");
+        assert_eq!(
+            lines,
+            include_str!("../fig3.out").lines().collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn fig3_dot() {
+        let p = mparse::parse::<MInstr>(include_str!("../fig3.va1m")).expect("program");
+        let dot = mparse::dot::to_dot(&p);
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("  b21 [label=\"A03\\l21    HLT\\l\"];\n"));
+        assert!(dot.ends_with(
+            "  b0 -> b1;\n  b1 -> b3;\n  b3 -> b21;\n  b3 -> b7 [style=dashed];\n  b7 -> b3;\n}\n"
+        ));
+        let blocks = mparse::dot::basic_blocks(&p);
+        assert_eq!(blocks, vec![0..1, 1..3, 3..7, 7..21, 21..22]);
+    }
+
+    #[test]
+    fn fig3_profile() -> Result<(), ExecError> {
+        let p = mparse::parse::<MInstr>(include_str!("../fig3.va1m")).expect("program");
+        let mut m = M::with_io(io::empty(), Vec::new());
+        m.enable_profile();
+        m.execute(&p)?;
+        let prof = m.take_profile().expect("profile");
+        assert_eq!(prof.executed(), 3 + 30 * 18 + 5);
+        assert_eq!(prof.counts[3], 31);
+        let mut folded = Vec::new();
+        prof.write_folded(&p, &mut folded).expect("folded");
+        assert_eq!(
+            String::from_utf8_lossy(&folded),
+            "A02 544\nA01 2\n(start) 1\nA03 1\n"
+        );
+        let mut report = Vec::new();
+        prof.report(&p, &mut report).expect("report");
+        let report = String::from_utf8_lossy(&report);
+        assert!(report.starts_with("executed 548 instructions\nlabel"));
+        assert!(report.contains("\n3     A02                  31  LD X\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn options() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let opts = Options::build(args(&["valgol1m", "p.va1m"]).into_iter()).expect("options");
+        assert_eq!(opts.pgm_path, "p.va1m");
+        assert_eq!(opts.command, Command::Run);
+        assert_eq!(opts.input_path, None);
+        assert!(!opts.dump);
+        assert!(opts.verify);
+        let opts = Options::build(args(&["valgol1m", "p.va1m", "in", "--dump"]).into_iter())
+            .expect("options");
+        assert_eq!(opts.input_path.as_deref(), Some("in"));
+        assert!(opts.dump);
+        assert!(Options::build(args(&["valgol1m", "--dump"]).into_iter()).is_err());
+        let opts =
+            Options::build(args(&["valgol1m", "debug", "p.va1m"]).into_iter()).expect("options");
+        assert_eq!(opts.pgm_path, "p.va1m");
+        assert_eq!(opts.command, Command::Debug);
+        let opts = Options::build(args(&["valgol1m", "c", "p.va1m"]).into_iter()).expect("options");
+        assert_eq!(opts.command, Command::C);
+        let opts =
+            Options::build(args(&["valgol1m", "wat", "p.va1m"]).into_iter()).expect("options");
+        assert_eq!(opts.command, Command::Wat);
+        assert!(!opts.optimize);
+        let opts = Options::build(args(&["valgol1m", "wat", "--optimize", "p.va1m"]).into_iter())
+            .expect("options");
+        assert_eq!(opts.command, Command::Wat);
+        assert!(opts.optimize);
+        let opts = Options::build(args(&["valgol1m", "--no-verify", "p.va1m"]).into_iter())
+            .expect("options");
+        assert!(!opts.verify);
+        let opts = Options::build(
+            args(&["valgol1m", "--folded", "out.folded", "p.va1m", "--profile"]).into_iter(),
+        )
+        .expect("options");
+        assert_eq!(opts.pgm_path, "p.va1m");
+        assert_eq!(opts.folded_path.as_deref(), Some("out.folded"));
+        assert!(opts.profile);
+        assert!(Options::build(args(&["valgol1m", "p.va1m", "--folded"]).into_iter()).is_err());
+    }
+
+    #[test]
+    fn debug_needs_input_for_rd() {
+        let path = std::env::temp_dir().join(format!("valgol1m-rd-{}.va1m", std::process::id()));
+        fs::write(
+            &path,
+            "        RD X\n        HLT\nX\n        BLK 1\n        END\n",
+        )
+        .expect("write program");
+        let args = ["valgol1m", "debug", path.to_str().expect("path")];
+        let opts = Options::build(args.iter().map(|s| s.to_string())).expect("options");
+        let err = run(opts).expect_err("debug without input");
+        fs::remove_file(&path).expect("remove program");
+        assert_eq!(
+            err.to_string(),
+            "debugging a program with RD needs an input file"
+        );
+    }
+
+    #[test]
+    fn va1_expressions() -> Result<(), ExecError> {
+        let va1m = compile(
+            include_str!("../../meta_mach_pgms/va1.mm"),
+            r#"
+.BEGIN
+.REAL A, B, C, D, E, F, G;
+7 / 2 = A;
+5 - 2 = F;
+10 - 4 - 3 = G;
+-A * 2 + 1 = B;
+A .> 3 .AND B .< 0 .OR .NOT 1 = C;
+A .<> 3.5 .OR A .>= 4 = D;
+.IF B .<= -6 .THEN 1 = E .ELSE 2 = E
+.END
+"#,
+        );
+        let p = mparse::parse::<MInstr>(&va1m).expect("program");
+        let mut m = M::new();
+        m.execute(&p)?;
+        let val = |v: &str| m.value(p.labels[v]);
+        assert_eq!(val("A"), Some(3.5));
+        assert_eq!(val("B"), Some(-6.0));
+        assert_eq!(val("C"), Some(1.0));
+        assert_eq!(val("D"), Some(0.0));
+        assert_eq!(val("E"), Some(1.0));
+        assert_eq!(val("F"), Some(3.0));
+        assert_eq!(val("G"), Some(3.0));
+        Ok(())
+    }
+
     #[test]
     fn parse_vs_lexing() {
         assert!(mparse::parse::<MInstr>(
@@ -279,94 +914,335 @@ A  # label
     }
 
     #[test]
-    fn m() {
+    fn execute_errors() {
+        let p = mparse::parse::<MInstr>(include_str!("../outofpgm.va1m")).expect("program");
+        let e = M::new().execute(&p).expect_err("fell off");
+        assert_eq!(e.error, RuntimeError::FellOffProgram);
+        assert_eq!(e.at.ic, 1);
+        assert_eq!(
+            format!("{e}"),
+            "execution past the end of the program at ic 1 (E)"
+        );
+
+        let p = mparse::parse::<MInstr>(
+            r#"
+        LDL 1
+A
+        PNT
+        ADD
+        HLT
+"#,
+        )
+        .expect("program");
+        let e = M::new().execute(&p).expect_err("underflow");
+        assert_eq!(e.error, RuntimeError::StackUnderflow);
+        assert_eq!(e.at.label, Some(("A".to_string(), 1)));
+        assert_eq!(format!("{e}"), "machine stack underflow at ic 2 (A+1)");
+
+        let p = mparse::parse::<MInstr>("        LDL 0\n        EDT 'é'\n        HLT\n")
+            .expect("program");
+        let e = M::new().execute(&p).expect_err("non-ASCII");
+        assert_eq!(e.error, RuntimeError::NonAsciiText("é".to_string()));
+        assert_eq!(format!("{e}"), "non-ASCII text é at ic 1");
+    }
+
+    #[test]
+    fn m() -> Result<(), RuntimeError> {
         let mut m = M::new();
         m.push(1.0);
-        assert_eq!(m.pop(), 1.0);
+        assert_eq!(m.pop()?, 1.0);
+        Ok(())
     }
 
     #[test]
-    fn m_add() {
+    fn m_add() -> Result<(), RuntimeError> {
         let mut m = M::new();
         m.push(2.0);
         m.push(3.0);
-        m.add();
-        assert_eq!(m.pop(), 5.0);
+        m.add()?;
+        assert_eq!(m.pop()?, 5.0);
+        Ok(())
     }
 
     #[test]
-    fn m_mlt() {
+    fn m_mlt() -> Result<(), RuntimeError> {
         let mut m = M::new();
         m.push(3.0);
         m.push(-4.0);
-        m.mlt();
-        assert_eq!(m.pop(), -12.0);
+        m.mlt()?;
+        assert_eq!(m.pop()?, -12.0);
+        Ok(())
     }
 
     #[test]
-    fn m_equ() {
+    fn m_equ() -> Result<(), RuntimeError> {
         let mut m = M::new();
         m.push(3.0);
         m.push(-4.0);
-        m.mlt();
+        m.mlt()?;
         m.push(-12.0);
-        m.equ();
-        assert_eq!(m.pop(), 1.0);
+        m.equ()?;
+        assert_eq!(m.pop()?, 1.0);
+        Ok(())
     }
 
     #[test]
-    fn m_edt_simple() {
+    fn m_div_neg() -> Result<(), RuntimeError> {
         let mut m = M::new();
         m.push(3.0);
-        m.edt("abc");
+        m.push(-4.0);
+        m.div()?;
+        m.neg()?;
+        assert_eq!(m.pop()?, 0.75);
+        m.push(1.0);
+        m.push(0.0);
+        assert_eq!(m.div(), Err(RuntimeError::DivisionByZero));
+        Ok(())
+    }
+
+    #[test]
+    fn m_dup() -> Result<(), RuntimeError> {
+        let mut m = M::new();
+        m.push(2.5);
+        m.dup()?;
+        assert_eq!(m.stack, vec![2.5, 2.5]);
+        m.stack.clear();
+        assert_eq!(m.dup(), Err(RuntimeError::StackUnderflow));
+        Ok(())
+    }
+
+    #[test]
+    fn m_cmp_logic() -> Result<(), RuntimeError> {
+        let mut m = M::new();
+        m.push(1.0);
+        m.push(2.0);
+        m.cmp(|b, a| b < a)?;
+        assert_eq!(m.pop()?, 1.0);
+        m.push(1.0);
+        m.push(0.0);
+        m.logic(|b, a| b && a)?;
+        assert_eq!(m.pop()?, 0.0);
+        m.push(0.0);
+        m.not()?;
+        assert_eq!(m.pop()?, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn m_edt_simple() -> Result<(), RuntimeError> {
+        let mut m = M::new();
+        m.push(3.0);
+        m.edt("abc")?;
         assert_eq!(m.print_area.len(), PRINT_AREA_SIZE);
         assert_eq!(m.print_area.trim_end(), "   abc");
         m.push(99.0);
-        m.edt("z");
+        m.edt("z")?;
         assert_eq!(m.print_area.len(), PRINT_AREA_SIZE);
         assert_eq!(m.print_area.trim_end(), "   abc                                                                                             z");
         m.push(100.0);
-        m.edt("x");
+        m.edt("x")?;
         assert_eq!(m.print_area.trim_end(), "   abc                                                                                             z");
         m.push(98.0);
-        m.edt("xy");
+        m.edt("xy")?;
         assert_eq!(m.print_area.trim_end(), "   abc                                                                                            xy");
         m.push(98.0);
-        m.edt("zzz");
+        m.edt("zzz")?;
         assert_eq!(m.print_area.trim_end(), "   abc                                                                                            xy");
         m.push(4.0);
-        m.edt("x");
+        m.edt("x")?;
         m.push(6.0);
-        m.edt("y");
+        m.edt("y")?;
         assert_eq!(m.print_area.trim_end(), "   axcy                                                                                           xy");
         m.push(-1.0);
-        m.edt("aa");
+        m.edt("aa")?;
         assert_eq!(m.print_area.trim_end(), "   axcy                                                                                           xy");
         m.push(0.0);
-        m.edt("aa");
+        m.edt("aa")?;
         assert_eq!(m.print_area.trim_end(), "aa axcy                                                                                           xy");
         // printing
-        m.pnt();
+        m.pnt()?;
         assert_eq!(m.print_area, "");
         // further
         m.push(0.0);
-        m.edt("aa");
+        m.edt("aa")?;
         assert_eq!(m.print_area.trim_end(), "aa");
+        Ok(())
+    }
+
+    #[test]
+    fn m_edn() -> Result<(), RuntimeError> {
+        let mut m = M::new();
+        for v in [2.0, -1.23456, 7.0, 2.0] {
+            m.push(v);
+        }
+        m.edn()?;
+        assert_eq!(m.print_area.trim_end(), "    -1.23");
+        for v in [10.0, 12345.0, 3.0, 0.0] {
+            m.push(v);
+        }
+        m.edn()?;
+        assert_eq!(m.print_area.trim_end(), "    -1.23 ***");
+        // huge widths and decimals are limited to the print area
+        for v in [0.0, 1.0, 1e18, 1e18] {
+            m.push(v);
+        }
+        m.edn()?;
+        assert_eq!(m.print_area, "*".repeat(PRINT_AREA_SIZE));
+        for v in [0.0, 1.0, 1e18, 2.0] {
+            m.push(v);
+        }
+        m.edn()?;
+        assert_eq!(m.print_area.trim_start(), "1.00");
+        Ok(())
+    }
+
+    #[test]
+    fn m_rd() -> Result<(), RuntimeError> {
+        let mut m = M::with_input(io::Cursor::new("1.5  2\n\n  -3 x"));
+        m.mem = vec![Cell::Unset; 4];
+        m.rd(0)?;
+        m.rd(1)?;
+        m.rd(2)?;
+        assert_eq!(m.value(0), Some(1.5));
+        assert_eq!(m.value(1), Some(2.0));
+        assert_eq!(m.value(2), Some(-3.0));
+        assert_eq!(m.rd(3), Err(RuntimeError::InvalidInput("x".to_string())));
+        assert_eq!(m.rd(3), Err(RuntimeError::EndOfInput));
+        Ok(())
     }
 
     #[test]
-    fn m_st_ld_sub() {
+    fn va1_read() -> Result<(), ExecError> {
+        let va1m = compile(
+            include_str!("../../meta_mach_pgms/va1.mm"),
+            r#"
+.BEGIN
+.REAL N, S, X;
+0 = S;
+READ N;
+.UNTIL N .= 0 .DO .BEGIN
+  READ X; S + X = S; N - 1 = N
+.END
+.END
+"#,
+        );
+        let p = mparse::parse::<MInstr>(&va1m).expect("program");
+        let mut m = M::with_input(io::Cursor::new("3\n1.5 2\n4\n"));
+        m.execute(&p)?;
+        assert_eq!(m.value(p.labels["S"]), Some(7.5));
+        Ok(())
+    }
+
+    // like `execute`, the output and result unless it gives up after
+    // `steps` instructions
+    fn execute_bounded(
+        pgm: &mparse::MProgram<MInstr>,
+        input: &str,
+        steps: usize,
+    ) -> Option<(String, Result<(), RuntimeError>)> {
+        let mut m = M::with_io(io::Cursor::new(input), Vec::new());
+        m.layout(pgm);
+        let mut ic = 0;
+        let mut res = None;
+        for _ in 0..steps {
+            match m.step(pgm, ic) {
+                Ok(Some(next)) => ic = next,
+                Ok(None) => {
+                    res = Some(Ok(()));
+                    break;
+                }
+                Err(e) => {
+                    res = Some(Err(e));
+                    break;
+                }
+            }
+        }
+        let out = String::from_utf8(m.into_output()).expect("utf8 output");
+        res.map(|res| (out, res))
+    }
+
+    #[test]
+    fn fuzz_generated_programs() {
+        let va1 = meta::metasyn::parse(include_str!("../../va1.syn")).expect("grammar");
+        let va1_mm = mparse::parse::<meta::MInstr>(include_str!("../../meta_mach_pgms/va1.mm"))
+            .expect("meta machine program");
+        let mut gen = meta::Generator::new(&va1, 1)
+            .expect("generator")
+            .ids(&["X", "Y", "Z"])
+            .max_depth(9);
+        let input = "1 0 -2.5 7 ".repeat(8);
+        for _ in 0..200 {
+            // the generated block in one setting all the identifiers
+            let source = format!(
+                ".BEGIN .REAL X, Y, Z; 3 = X; -1.5 = Y; 7 = Z; {} .END",
+                gen.sentence()
+            );
+            let mut m = meta::M::new(&source);
+            m.execute(&va1_mm);
+            let code = m
+                .generated()
+                .unwrap_or_else(|_| panic!("not compiled: {source}"));
+            let p = mparse::parse::<MInstr>(&code).expect("program");
+            verify(&p).unwrap_or_else(|e| panic!("{e}: {source}"));
+            let optimized = optimize(&p);
+            verify(&optimized).unwrap_or_else(|e| panic!("{e} when optimized: {source}"));
+            to_wat(&p).unwrap_or_else(|e| panic!("{e:?}: {source}"));
+            let original = execute_bounded(&p, &input, 10_000);
+            let optimized = execute_bounded(&optimized, &input, 10_000);
+            if let (Some(original), Some(optimized)) = (original, optimized) {
+                assert_eq!(original, optimized, "optimized: {source}");
+            }
+        }
+    }
+
+    #[test]
+    fn m_st_ld_sub() -> Result<(), RuntimeError> {
         let mut m = M::new();
-        m.ld(0);
-        let v = m.pop();
+        m.mem = vec![Cell::Unset; 2];
+        m.ld(0)?;
+        let v = m.pop()?;
         assert_eq!(v, 0.0);
         m.push(2.0);
-        m.st(0);
+        m.st(0)?;
         m.push(3.0);
-        m.st(1);
-        m.ld(1);
-        m.ld(0);
-        m.sub();
-        assert_eq!(m.pop(), -1.0);
+        m.st(1)?;
+        m.ld(1)?;
+        m.ld(0)?;
+        m.sub()?;
+        assert_eq!(m.pop()?, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn m_mem_checks() {
+        let p = mparse::parse::<MInstr>(
+            r#"
+        B  A
+X
+        BLK 2
+A
+        LD  X
+        ST  A
+        HLT
+"#,
+        )
+        .expect("program");
+        let mut m = M::new();
+        m.layout(&p);
+        assert_eq!(m.mem.len(), 4);
+        assert_eq!(m.ld(1), Err(RuntimeError::AddressOutOfRange(1)));
+        assert_eq!(m.ld(4), Err(RuntimeError::AddressOutOfRange(4)));
+        m.check_uninitialized(true);
+        assert_eq!(m.ld(3), Err(RuntimeError::UninitializedRead(3)));
+        m.push(1.0);
+        assert_eq!(m.st(3), Ok(()));
+        assert_eq!(m.ld(3), Ok(()));
+
+        let e = m.execute(&p).expect_err("uninitialized");
+        assert_eq!(e.error, RuntimeError::UninitializedRead(2));
+        m.check_uninitialized(false);
+        let e = m.execute(&p).expect_err("out of range");
+        assert_eq!(e.error, RuntimeError::AddressOutOfRange(4));
+        assert_eq!(e.at.label, Some(("A".to_string(), 1)));
     }
 }
@@ -0,0 +1,442 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+use mparse::{Line, MProgram};
+
+use crate::{MInstr, M};
+
+/// Instructions with the labels and blocks placed before each of them.
+struct Code {
+    prefix: Vec<Vec<Line<MInstr>>>,
+    instrs: Vec<Option<MInstr>>,
+    tail: Vec<Line<MInstr>>,
+}
+
+/// Returns an equivalent program with constant expressions and tests
+/// folded, branches to branches threaded, unreachable code removed and
+/// reloads of a variable just loaded or stored replaced by `DUP`.
+pub fn optimize(pgm: &MProgram<MInstr>) -> MProgram<MInstr> {
+    let mut code = Code::new(pgm);
+    loop {
+        let mut changed = code.fold();
+        changed |= code.thread();
+        changed |= code.invert();
+        changed |= code.drop_next_branches();
+        changed |= code.drop_unreachable();
+        changed |= code.reuse_loads();
+        if !changed {
+            break;
+        }
+    }
+    mparse::parse(&mparse::render(&code.lines()))
+        .expect("internal error: optimized program does not assemble")
+}
+
+fn target(instr: &MInstr) -> Option<&str> {
+    match instr {
+        MInstr::B(l, _) | MInstr::BTP(l, _) | MInstr::BFP(l, _) => Some(l),
+        _ => None,
+    }
+}
+
+fn retarget(instr: &mut MInstr, label: String) {
+    match instr {
+        MInstr::B(l, _) | MInstr::BTP(l, _) | MInstr::BFP(l, _) => *l = label,
+        _ => panic!("internal error: retarget of a non branch"),
+    }
+}
+
+fn is_binary(instr: &MInstr) -> bool {
+    matches!(
+        instr,
+        MInstr::ADD
+            | MInstr::SUB
+            | MInstr::MLT
+            | MInstr::DIV
+            | MInstr::EQU
+            | MInstr::NEQ
+            | MInstr::LSS
+            | MInstr::GTR
+            | MInstr::LEQ
+            | MInstr::GEQ
+            | MInstr::AND
+            | MInstr::OR
+    )
+}
+
+/// Runs `instr` on a machine holding `args`, `None` when it fails or the
+/// result cannot be written back as a literal.
+fn eval(instr: &MInstr, args: &[f64]) -> Option<f64> {
+    let mut m = M::with_io(io::empty(), io::sink());
+    for arg in args {
+        m.push(*arg);
+    }
+    m.exec(instr, 0).ok()?;
+    let v = m.pop().ok()?;
+    (m.stack.is_empty() && v.is_finite()).then_some(v)
+}
+
+impl Code {
+    fn new(pgm: &MProgram<MInstr>) -> Self {
+        let mut code = Code {
+            prefix: Vec::new(),
+            instrs: Vec::new(),
+            tail: Vec::new(),
+        };
+        for line in pgm.lines() {
+            match line {
+                Line::Instr(instr) => {
+                    code.prefix.push(std::mem::take(&mut code.tail));
+                    code.instrs.push(Some(instr));
+                }
+                other => code.tail.push(other),
+            }
+        }
+        code
+    }
+
+    fn lines(&self) -> Vec<Line<MInstr>> {
+        let mut lines = Vec::new();
+        for (prefix, instr) in self.prefix.iter().zip(self.instrs.iter()) {
+            lines.extend(prefix.iter().cloned());
+            lines.extend(instr.clone().map(Line::Instr));
+        }
+        lines.extend(self.tail.iter().cloned());
+        lines
+    }
+
+    /// Removes deleted instructions, their labels and blocks move to the
+    /// next one.
+    fn compact(&mut self) {
+        let mut prefixes = Vec::new();
+        let mut instrs = Vec::new();
+        let mut pending = Vec::new();
+        for (prefix, instr) in self.prefix.drain(..).zip(self.instrs.drain(..)) {
+            pending.extend(prefix);
+            if instr.is_some() {
+                prefixes.push(std::mem::take(&mut pending));
+                instrs.push(instr);
+            }
+        }
+        pending.append(&mut self.tail);
+        self.prefix = prefixes;
+        self.instrs = instrs;
+        self.tail = pending;
+    }
+
+    fn positions(&self) -> HashMap<String, usize> {
+        let mut pos = HashMap::new();
+        let prefixes = self.prefix.iter().chain(std::iter::once(&self.tail));
+        for (i, prefix) in prefixes.enumerate() {
+            for line in prefix {
+                if let Line::Label(label) = line {
+                    pos.insert(label.clone(), i);
+                }
+            }
+        }
+        pos
+    }
+
+    /// Instructions some branch goes to.
+    fn targets(&self) -> HashSet<usize> {
+        let pos = self.positions();
+        self.instrs
+            .iter()
+            .flatten()
+            .filter_map(|instr| target(instr).map(|l| pos[l]))
+            .collect()
+    }
+
+    fn instr(&self, i: usize) -> Option<&MInstr> {
+        self.instrs.get(i).and_then(|instr| instr.as_ref())
+    }
+
+    fn finish(&mut self, changed: bool) -> bool {
+        if changed {
+            self.compact();
+        }
+        changed
+    }
+
+    /// Folds operations on literals and tests of a literal.
+    fn fold(&mut self) -> bool {
+        let targets = self.targets();
+        let mut changed = false;
+        let mut i = 0;
+        while i < self.instrs.len() {
+            let Some(MInstr::LDL(x)) = self.instr(i) else {
+                i += 1;
+                continue;
+            };
+            let x = *x;
+            let next = (!targets.contains(&(i + 1)))
+                .then(|| self.instr(i + 1))
+                .flatten();
+            let after = (!targets.contains(&(i + 2)))
+                .then(|| self.instr(i + 2))
+                .flatten();
+            let folded = match (next, after) {
+                (Some(MInstr::LDL(y)), Some(op)) if is_binary(op) => {
+                    eval(op, &[x, *y]).map(|v| (3, Some(MInstr::LDL(v))))
+                }
+                (Some(op @ (MInstr::NEG | MInstr::NOT)), _) => {
+                    eval(op, &[x]).map(|v| (2, Some(MInstr::LDL(v))))
+                }
+                (Some(MInstr::BTP(l, _)), _) => {
+                    Some((2, (x != 0.0).then(|| MInstr::B(l.clone(), 0))))
+                }
+                (Some(MInstr::BFP(l, _)), _) => {
+                    Some((2, (x == 0.0).then(|| MInstr::B(l.clone(), 0))))
+                }
+                _ => None,
+            };
+            match folded {
+                Some((len, instr)) => {
+                    self.instrs[i] = instr;
+                    for slot in self.instrs[i + 1..i + len].iter_mut() {
+                        *slot = None;
+                    }
+                    changed = true;
+                    i += len;
+                }
+                None => i += 1,
+            }
+        }
+        self.finish(changed)
+    }
+
+    /// Retargets branches to an unconditional branch to its destination.
+    fn thread(&mut self) -> bool {
+        let pos = self.positions();
+        let mut changed = false;
+        for i in 0..self.instrs.len() {
+            let Some(mut l) = self.instr(i).and_then(target).map(String::from) else {
+                continue;
+            };
+            let mut seen = HashSet::new();
+            while let Some(MInstr::B(m, _)) = self.instr(pos[&l]) {
+                if !seen.insert(pos[&l]) {
+                    break;
+                }
+                l = m.clone();
+            }
+            if Some(l.as_str()) != self.instr(i).and_then(target) {
+                retarget(self.instrs[i].as_mut().unwrap(), l);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Turns a conditional branch over an unconditional one into the
+    /// opposite conditional branch.
+    fn invert(&mut self) -> bool {
+        let targets = self.targets();
+        let pos = self.positions();
+        let mut changed = false;
+        let mut i = 0;
+        while i + 1 < self.instrs.len() {
+            let inverted = match (self.instr(i), self.instr(i + 1)) {
+                (Some(MInstr::BTP(over, _)), Some(MInstr::B(to, _)))
+                    if pos[over] == i + 2 && !targets.contains(&(i + 1)) =>
+                {
+                    Some(MInstr::BFP(to.clone(), 0))
+                }
+                (Some(MInstr::BFP(over, _)), Some(MInstr::B(to, _)))
+                    if pos[over] == i + 2 && !targets.contains(&(i + 1)) =>
+                {
+                    Some(MInstr::BTP(to.clone(), 0))
+                }
+                _ => None,
+            };
+            if let Some(instr) = inverted {
+                self.instrs[i] = Some(instr);
+                self.instrs[i + 1] = None;
+                changed = true;
+                i += 1;
+            }
+            i += 1;
+        }
+        self.finish(changed)
+    }
+
+    fn drop_next_branches(&mut self) -> bool {
+        let pos = self.positions();
+        let next: Vec<usize> = (0..self.instrs.len())
+            .filter(|i| matches!(self.instr(*i), Some(MInstr::B(l, _)) if pos[l] == i + 1))
+            .collect();
+        for i in next.iter() {
+            self.instrs[*i] = None;
+        }
+        self.finish(!next.is_empty())
+    }
+
+    fn drop_unreachable(&mut self) -> bool {
+        let pos = self.positions();
+        let mut reached = vec![false; self.instrs.len() + 1];
+        let mut work = vec![0];
+        while let Some(i) = work.pop() {
+            if reached[i] {
+                continue;
+            }
+            reached[i] = true;
+            match self.instr(i) {
+                Some(MInstr::B(l, _)) => work.push(pos[l]),
+                Some(MInstr::BTP(l, _) | MInstr::BFP(l, _)) => {
+                    work.push(pos[l]);
+                    work.push(i + 1);
+                }
+                Some(MInstr::HLT | MInstr::Undef) | None => (),
+                Some(_) => work.push(i + 1),
+            }
+        }
+        let mut changed = false;
+        for (i, reached) in reached.into_iter().enumerate().take(self.instrs.len()) {
+            if !reached {
+                self.instrs[i] = None;
+                changed = true;
+            }
+        }
+        self.finish(changed)
+    }
+
+    /// Replaces `LD X; LD X` with `LD X; DUP` and `ST X; LD X` with
+    /// `DUP; ST X`.
+    fn reuse_loads(&mut self) -> bool {
+        let targets = self.targets();
+        let mut changed = false;
+        for i in 0..self.instrs.len().saturating_sub(1) {
+            if targets.contains(&(i + 1)) {
+                continue;
+            }
+            match (self.instr(i), self.instr(i + 1)) {
+                (Some(MInstr::LD(x, _)), Some(MInstr::LD(y, _))) if x == y => {
+                    self.instrs[i + 1] = Some(MInstr::DUP);
+                }
+                (Some(MInstr::ST(x, _)), Some(MInstr::LD(y, _))) if x == y => {
+                    self.instrs[i + 1] = self.instrs[i].take();
+                    self.instrs[i] = Some(MInstr::DUP);
+                }
+                _ => continue,
+            }
+            changed = true;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output_lines;
+
+    fn compile(source: &str) -> MProgram<MInstr> {
+        let mpgm = mparse::parse::<meta::MInstr>(include_str!("../../meta_mach_pgms/va1.mm"))
+            .expect("meta machine program");
+        let mut m = meta::M::new(source);
+        m.execute(&mpgm);
+        mparse::parse(&m.generated().expect("compilation")).expect("program")
+    }
+
+    #[test]
+    fn patterns() {
+        let p = mparse::parse::<MInstr>(
+            r#"
+        B       A
+X
+        BLK     1
+A
+        LDL     0
+        BTP     C
+        LDL     2
+        LDL     3
+        SUB
+        NEG
+        ST      X
+        LD      X
+        LD      X
+        MLT
+        LDL     1
+        LDL     0
+        DIV
+        BTP     D
+        B       C
+D
+        B       E
+E
+        EDT     'x'
+        PNT
+C
+        HLT
+        PNT
+"#,
+        )
+        .expect("program");
+        let q = optimize(&p);
+        assert_eq!(
+            mparse::render(&q.lines()),
+            "X
+        BLK 1
+A
+        LDL 1
+        DUP
+        DUP
+        ST X
+        MLT
+        LDL 1
+        LDL 0
+        DIV
+        BFP C
+D
+E
+        EDT 'x'
+        PNT
+C
+        HLT
+        END
+"
+        );
+    }
+
+    #[test]
+    fn examples() {
+        let fig3 = mparse::parse::<MInstr>(include_str!("../fig3.va1m")).expect("program");
+        let fig3_opt = optimize(&fig3);
+        assert!(fig3_opt.instrs.len() < fig3.instrs.len());
+        assert_eq!(
+            output_lines(&fig3_opt, io::empty()).expect("optimized"),
+            output_lines(&fig3, io::empty()).expect("original")
+        );
+
+        let nested = compile(include_str!("../nested.va1"));
+        let nested_opt = optimize(&nested);
+        assert!(nested_opt.instrs.len() < nested.instrs.len());
+        for input in ["0", "3", "6"] {
+            assert_eq!(
+                output_lines(&nested_opt, input.as_bytes()),
+                output_lines(&nested, input.as_bytes())
+            );
+        }
+
+        let exprs = compile(
+            r#"
+.BEGIN
+.REAL A, B, C;
+7 / 2 * (1 + 1) = A;
+-A * 2 + 1 .> 3 .OR .NOT 0 = B;
+.IF 2 .< 1 .THEN 1 = C .ELSE 2 = C;
+.UNTIL A .<= 0 .DO A - 1 = A
+.END
+"#,
+        );
+        let exprs_opt = optimize(&exprs);
+        assert!(exprs_opt.instrs.len() < exprs.instrs.len());
+        let mut m = M::new();
+        m.execute(&exprs_opt).expect("optimized");
+        let mut n = M::new();
+        n.execute(&exprs).expect("original");
+        for var in ["A", "B", "C"] {
+            assert_eq!(m.value(exprs_opt.labels[var]), n.value(exprs.labels[var]));
+        }
+    }
+}
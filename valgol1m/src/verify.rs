@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fmt;
+
+use mparse::{Location, MProgram};
+
+use crate::MInstr;
+
+/// Ways the machine stack can go wrong on some path through a program.
+#[derive(Debug, PartialEq)]
+pub enum StackFault {
+    /// An instruction pops more values than the stack holds.
+    Underflow,
+    /// Paths joining at an instruction arrive with different heights.
+    Mismatch(usize, usize),
+    /// `HLT` is reached with values still on the stack.
+    LeftOnHalt(usize),
+}
+
+impl fmt::Display for StackFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackFault::Underflow => f.write_str("machine stack underflow"),
+            StackFault::Mismatch(a, b) => write!(f, "stack heights {a} and {b} meet"),
+            StackFault::LeftOnHalt(1) => f.write_str("1 value left on the stack"),
+            StackFault::LeftOnHalt(n) => write!(f, "{n} values left on the stack"),
+        }
+    }
+}
+
+/// A `StackFault` located at an instruction counter, with the nearest
+/// preceding label when there is one.
+#[derive(Debug, PartialEq)]
+pub struct VerifyError {
+    pub fault: StackFault,
+    pub at: Location,
+}
+
+impl VerifyError {
+    fn new(fault: StackFault, ic: usize, pgm: &MProgram<MInstr>) -> Self {
+        VerifyError {
+            fault,
+            at: pgm.location(ic),
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.fault, self.at)
+    }
+}
+
+impl Error for VerifyError {}
+
+/// Checks that every reachable instruction sees the same stack height on
+/// all paths to it, that no instruction underflows the stack and that the
+/// stack is empty at each reachable `HLT`.
+pub fn verify(pgm: &MProgram<MInstr>) -> Result<(), VerifyError> {
+    let heights = stack_heights(pgm)?;
+    for (ic, instr) in pgm.instrs.iter().enumerate() {
+        if let (MInstr::HLT, Some(h @ 1..)) = (instr, heights[ic]) {
+            return Err(VerifyError::new(StackFault::LeftOnHalt(h), ic, pgm));
+        }
+    }
+    Ok(())
+}
+
+/// Stack height before each reachable instruction, the entry at
+/// `instrs.len()` is for execution past the end.
+pub(crate) fn stack_heights(pgm: &MProgram<MInstr>) -> Result<Vec<Option<usize>>, VerifyError> {
+    let mut heights = vec![None; pgm.instrs.len() + 1];
+    heights[0] = Some(0);
+    let mut work = vec![0];
+    while let Some(ic) = work.pop() {
+        let Some(instr) = pgm.instrs.get(ic) else {
+            continue;
+        };
+        let h = heights[ic].unwrap();
+        let (pops, pushes) = stack_use(instr);
+        if h < pops {
+            return Err(VerifyError::new(StackFault::Underflow, ic, pgm));
+        }
+        let after = h - pops + pushes;
+        for next in successors(pgm, ic) {
+            match heights[next] {
+                None => {
+                    heights[next] = Some(after);
+                    work.push(next);
+                }
+                Some(seen) if seen != after => {
+                    let fault = StackFault::Mismatch(seen, after);
+                    return Err(VerifyError::new(fault, next, pgm));
+                }
+                Some(_) => (),
+            }
+        }
+    }
+    Ok(heights)
+}
+
+/// Number of values an instruction pops and pushes.
+fn stack_use(instr: &MInstr) -> (usize, usize) {
+    match instr {
+        MInstr::LDL(_) | MInstr::LD(_, _) => (0, 1),
+        MInstr::ST(_, _) | MInstr::EDT(_) | MInstr::BFP(_, _) | MInstr::BTP(_, _) => (1, 0),
+        MInstr::NEG | MInstr::NOT => (1, 1),
+        MInstr::DUP => (1, 2),
+        MInstr::EDN => (4, 0),
+        MInstr::RD(_, _) | MInstr::PNT | MInstr::B(_, _) | MInstr::HLT | MInstr::Undef => (0, 0),
+        MInstr::EQU
+        | MInstr::ADD
+        | MInstr::MLT
+        | MInstr::SUB
+        | MInstr::DIV
+        | MInstr::NEQ
+        | MInstr::LSS
+        | MInstr::GTR
+        | MInstr::LEQ
+        | MInstr::GEQ
+        | MInstr::AND
+        | MInstr::OR => (2, 1),
+    }
+}
+
+fn successors(pgm: &MProgram<MInstr>, ic: usize) -> Vec<usize> {
+    match pgm.instrs.get(ic) {
+        Some(MInstr::B(_, t)) => vec![*t],
+        Some(MInstr::BFP(_, t) | MInstr::BTP(_, t)) => vec![*t, ic + 1],
+        Some(MInstr::HLT | MInstr::Undef) | None => vec![],
+        Some(_) => vec![ic + 1],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> MProgram<MInstr> {
+        mparse::parse::<MInstr>(s).expect("program")
+    }
+
+    fn compile(source: &str) -> MProgram<MInstr> {
+        let mp = mparse::parse::<meta::MInstr>(include_str!("../../meta_mach_pgms/va1.mm"))
+            .expect("meta machine program");
+        let mut m = meta::M::new(source);
+        m.execute(&mp);
+        parse(&m.generated().expect("compilation"))
+    }
+
+    #[test]
+    fn accepted() {
+        for p in [
+            parse(include_str!("../fig3.va1m")),
+            compile(include_str!("../fig3.va1")),
+            compile(include_str!("../nested.va1")),
+        ] {
+            assert_eq!(verify(&p), Ok(()));
+            assert_eq!(verify(&crate::optimize(&p)), Ok(()));
+        }
+    }
+
+    #[test]
+    fn rejected() {
+        let fault = |s: &str| verify(&parse(s)).map_err(|e| (e.fault, e.at.ic));
+        assert_eq!(
+            fault("        LDL 1\n        ADD\n        HLT\n"),
+            Err((StackFault::Underflow, 1))
+        );
+        assert_eq!(
+            fault("        LDL 1\n        BTP A\n        LDL 2\nA\n        HLT\n"),
+            Err((StackFault::Mismatch(0, 1), 3))
+        );
+        assert_eq!(
+            fault("        LDL 1\n        DUP\n        ST X\n        HLT\nX\n        BLK 1\n"),
+            Err((StackFault::LeftOnHalt(1), 3))
+        );
+        // unreachable code is not checked
+        assert_eq!(fault("        HLT\n        ADD\n"), Ok(()));
+    }
+
+    #[test]
+    fn located() {
+        let p = parse("A\n        LDL 1\n        NOT\n        HLT\n");
+        let e = verify(&p).unwrap_err();
+        assert_eq!(e.to_string(), "1 value left on the stack at ic 2 (A+2)");
+    }
+}
@@ -0,0 +1,715 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+
+use mparse::MProgram;
+
+use crate::verify::{stack_heights, VerifyError};
+use crate::{MInstr, EPS};
+
+const PAGE_SIZE: u32 = 65536;
+
+// error codes passed to the host `fail` function
+const FELL_OFF: u32 = 0;
+const INVALID_INSTRUCTION: u32 = 1;
+const DIVISION_BY_ZERO: u32 = 2;
+const OUT_OF_RANGE: u32 = 3;
+const NON_ASCII: u32 = 4;
+
+/// Reasons a program cannot be given a structured translation.
+#[derive(Debug, PartialEq)]
+pub enum WatError {
+    Stack(VerifyError),
+    Irreducible(usize),
+}
+
+impl fmt::Display for WatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatError::Stack(e) => e.fmt(f),
+            WatError::Irreducible(ic) => write!(f, "irreducible control flow at ic {ic}"),
+        }
+    }
+}
+
+impl Error for WatError {}
+
+impl From<VerifyError> for WatError {
+    fn from(e: VerifyError) -> Self {
+        WatError::Stack(e)
+    }
+}
+
+/// Translates `pgm` to a WebAssembly text module exporting its linear
+/// memory and a `main` function.
+///
+/// Memory cell `n` is the `f64` at byte offset `8 * n`, `EDT` strings
+/// follow the cells. Stack slots become locals, so the stack depth must be
+/// the same on every path to an instruction. The host provides the
+/// functions the program uses, from module `valgol`:
+///
+/// - `edt (f64 position, i32 offset, i32 length)`
+/// - `edn (f64 position, f64 value, f64 width, f64 decimals)`
+/// - `pnt ()`
+/// - `rd () -> f64`
+/// - `fail (i32 code, i32 ic)`, never returning, with codes 0 for
+///   execution past the end of the program, 1 for an invalid instruction,
+///   2 for a division by zero, 3 for an address out of memory range and
+///   4 for non-ASCII `EDT` text.
+pub fn to_wat(pgm: &MProgram<MInstr>) -> Result<String, WatError> {
+    let depths = stack_heights(pgm)?;
+    let cfg = Cfg::new(pgm);
+    cfg.check_reducible()?;
+
+    let mut strings: Vec<(&str, u32)> = Vec::new();
+    let mut offset = pgm.mem_size() * 8;
+    for instr in pgm.instrs.iter() {
+        if let MInstr::EDT(s) = instr {
+            if s.is_ascii() && !strings.iter().any(|(t, _)| t == s) {
+                strings.push((s, offset));
+                offset += s.len() as u32;
+            }
+        }
+    }
+    let mut gen = Gen {
+        pgm,
+        cfg: &cfg,
+        depths: &depths,
+        strings: strings.iter().copied().collect(),
+        body: String::new(),
+        indent: 2,
+        imports: Vec::new(),
+    };
+    gen.do_tree(0);
+
+    let mut wat = String::from("(module\n");
+    for (name, params) in IMPORTS {
+        if gen.imports.contains(&name) {
+            writeln!(
+                wat,
+                "  (import \"valgol\" \"{name}\" (func ${name}{params}))"
+            )
+            .unwrap();
+        }
+    }
+    let pages = offset.div_ceil(PAGE_SIZE).max(1);
+    writeln!(wat, "  (memory (export \"memory\") {pages})").unwrap();
+    for (s, offset) in strings.iter() {
+        writeln!(wat, "  (data (i32.const {offset}) {})", string(s)).unwrap();
+    }
+    wat.push_str("  (func (export \"main\")\n");
+    let max_depth = depths.iter().flatten().max().copied().unwrap_or(0);
+    if max_depth > 0 {
+        let locals: Vec<String> = (0..max_depth)
+            .map(|s| format!("(local $s{s} f64)"))
+            .collect();
+        writeln!(wat, "    {}", locals.join(" ")).unwrap();
+    }
+    wat.push_str(&gen.body);
+    wat.push_str("  )\n)\n");
+    Ok(wat)
+}
+
+const IMPORTS: [(&str, &str); 5] = [
+    ("edt", " (param f64 i32 i32)"),
+    ("edn", " (param f64 f64 f64 f64)"),
+    ("pnt", ""),
+    ("rd", " (result f64)"),
+    ("fail", " (param i32 i32)"),
+];
+
+enum Exit {
+    Goto(usize),
+    Branch {
+        ic: usize,
+        on_zero: bool,
+        target: usize,
+        next: usize,
+    },
+    Return,
+    Fail(u32, usize),
+}
+
+/// A basic block, its body excludes the branching instruction.
+struct Node {
+    start: usize,
+    body_end: usize,
+    end: usize,
+    exit: Exit,
+}
+
+struct Cfg {
+    nodes: Vec<Node>,
+    succs: Vec<Vec<usize>>,
+    rpo: Vec<Option<usize>>,
+    idom: Vec<Option<usize>>,
+    merge: Vec<bool>,
+    loop_header: Vec<bool>,
+}
+
+impl Cfg {
+    fn new(pgm: &MProgram<MInstr>) -> Self {
+        let len = pgm.instrs.len();
+        let mut leader = vec![false; len + 1];
+        leader[0] = true;
+        leader[len] = true;
+        for (ic, instr) in pgm.instrs.iter().enumerate() {
+            match instr {
+                MInstr::B(_, t) | MInstr::BFP(_, t) | MInstr::BTP(_, t) => {
+                    leader[*t] = true;
+                    leader[ic + 1] = true;
+                }
+                MInstr::HLT | MInstr::Undef => leader[ic + 1] = true,
+                _ => (),
+            }
+        }
+        let starts: Vec<usize> = (0..=len).filter(|ic| leader[*ic]).collect();
+        let node_of: HashMap<usize, usize> =
+            starts.iter().enumerate().map(|(n, ic)| (*ic, n)).collect();
+        let mut nodes = Vec::new();
+        for (n, start) in starts.iter().copied().enumerate() {
+            let end = starts.get(n + 1).copied().unwrap_or(len);
+            let (body_end, exit) = match pgm.instrs.get(end.wrapping_sub(1)) {
+                _ if start == len => (len, Exit::Fail(FELL_OFF, len)),
+                Some(MInstr::B(_, t)) => (end - 1, Exit::Goto(node_of[t])),
+                Some(MInstr::BFP(_, t) | MInstr::BTP(_, t)) => (
+                    end - 1,
+                    Exit::Branch {
+                        ic: end - 1,
+                        on_zero: matches!(pgm.instrs[end - 1], MInstr::BFP(_, _)),
+                        target: node_of[t],
+                        next: node_of[&end],
+                    },
+                ),
+                Some(MInstr::HLT) => (end - 1, Exit::Return),
+                Some(MInstr::Undef) => (end - 1, Exit::Fail(INVALID_INSTRUCTION, end - 1)),
+                _ => (end, Exit::Goto(node_of[&end])),
+            };
+            nodes.push(Node {
+                start,
+                body_end,
+                end,
+                exit,
+            });
+        }
+        let succs: Vec<Vec<usize>> = nodes
+            .iter()
+            .map(|node| match node.exit {
+                Exit::Goto(t) => vec![t],
+                Exit::Branch { target, next, .. } => vec![target, next],
+                Exit::Return | Exit::Fail(_, _) => vec![],
+            })
+            .collect();
+
+        // reverse postorder of the nodes reachable from the entry
+        let mut post = Vec::new();
+        let mut seen = vec![false; nodes.len()];
+        let mut stack = vec![(0, 0)];
+        seen[0] = true;
+        while let Some((n, i)) = stack.pop() {
+            match succs[n].get(i) {
+                Some(s) => {
+                    stack.push((n, i + 1));
+                    if !seen[*s] {
+                        seen[*s] = true;
+                        stack.push((*s, 0));
+                    }
+                }
+                None => post.push(n),
+            }
+        }
+        let mut rpo = vec![None; nodes.len()];
+        for (i, n) in post.iter().rev().enumerate() {
+            rpo[*n] = Some(i);
+        }
+        let mut preds = vec![Vec::new(); nodes.len()];
+        for (n, ss) in succs.iter().enumerate().filter(|(n, _)| rpo[*n].is_some()) {
+            for s in ss {
+                preds[*s].push(n);
+            }
+        }
+        // dominators, as in Cooper, Harvey and Kennedy's iterative algorithm
+        let mut idom: Vec<Option<usize>> = vec![None; nodes.len()];
+        idom[0] = Some(0);
+        let order: Vec<usize> = post.iter().rev().copied().collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for n in order.iter().skip(1).copied() {
+                let mut new: Option<usize> = None;
+                for p in preds[n].iter().copied().filter(|p| idom[*p].is_some()) {
+                    new = Some(match new {
+                        None => p,
+                        Some(mut a) => {
+                            let mut b = p;
+                            while a != b {
+                                while rpo[a] > rpo[b] {
+                                    a = idom[a].unwrap();
+                                }
+                                while rpo[b] > rpo[a] {
+                                    b = idom[b].unwrap();
+                                }
+                            }
+                            a
+                        }
+                    });
+                }
+                if new != idom[n] {
+                    idom[n] = new;
+                    changed = true;
+                }
+            }
+        }
+        let mut merge = vec![false; nodes.len()];
+        let mut loop_header = vec![false; nodes.len()];
+        for (n, ps) in preds.iter().enumerate() {
+            let forward = ps.iter().filter(|p| rpo[**p] < rpo[n]).count();
+            merge[n] = forward > 1;
+            loop_header[n] = forward < ps.len();
+        }
+        Cfg {
+            nodes,
+            succs,
+            rpo,
+            idom,
+            merge,
+            loop_header,
+        }
+    }
+
+    fn dominates(&self, a: usize, mut b: usize) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom[b] {
+                Some(d) if d != b => b = d,
+                _ => return false,
+            }
+        }
+    }
+
+    /// Every back edge must go to a node dominating its source.
+    fn check_reducible(&self) -> Result<(), WatError> {
+        for (n, ss) in self.succs.iter().enumerate() {
+            if self.rpo[n].is_none() {
+                continue;
+            }
+            for s in ss {
+                if self.rpo[*s] <= self.rpo[n] && !self.dominates(*s, n) {
+                    return Err(WatError::Irreducible(self.nodes[*s].start));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_backward(&self, from: usize, to: usize) -> bool {
+        self.rpo[to] <= self.rpo[from]
+    }
+}
+
+struct Gen<'a> {
+    pgm: &'a MProgram<MInstr>,
+    cfg: &'a Cfg,
+    depths: &'a [Option<usize>],
+    strings: HashMap<&'a str, u32>,
+    body: String,
+    indent: usize,
+    imports: Vec<&'static str>,
+}
+
+// The translation follows Ramsey's "Beyond Relooper": each node is
+// emitted under its immediate dominator, wrapped in a loop when it is the
+// target of a back edge and preceded by a block for every dominated node
+// with several forward predecessors, which is placed after the block.
+impl Gen<'_> {
+    fn line(&mut self, s: &str) {
+        writeln!(self.body, "{:1$}{s}", "", self.indent * 2).unwrap();
+    }
+
+    fn call(&mut self, f: &'static str) {
+        if !self.imports.contains(&f) {
+            self.imports.push(f);
+        }
+        self.line(&format!("call ${f}"));
+    }
+
+    fn do_tree(&mut self, n: usize) {
+        let mut merges: Vec<usize> = (0..self.cfg.nodes.len())
+            .filter(|c| *c != n && self.cfg.idom[*c] == Some(n) && self.cfg.merge[*c])
+            .collect();
+        merges.sort_by_key(|c| std::cmp::Reverse(self.cfg.rpo[*c]));
+        if self.cfg.loop_header[n] {
+            self.line(&format!("loop $L{}", self.cfg.nodes[n].start));
+            self.indent += 1;
+            self.node_within(n, &merges);
+            self.indent -= 1;
+            self.line("end");
+        } else {
+            self.node_within(n, &merges);
+        }
+    }
+
+    fn node_within(&mut self, n: usize, merges: &[usize]) {
+        if let Some((follow, inner)) = merges.split_first() {
+            self.line(&format!("block $B{}", self.cfg.nodes[*follow].start));
+            self.indent += 1;
+            self.node_within(n, inner);
+            self.indent -= 1;
+            self.line("end");
+            self.do_tree(*follow);
+            return;
+        }
+        let node = &self.cfg.nodes[n];
+        for ic in node.start..node.body_end {
+            self.comment_labels(ic);
+            self.line(&format!(";; {}", self.pgm.instrs[ic]));
+            self.instr(ic);
+        }
+        match node.exit {
+            Exit::Goto(t) => {
+                if node.body_end < node.end {
+                    self.comment_labels(node.body_end);
+                    self.line(&format!(";; {}", self.pgm.instrs[node.body_end]));
+                }
+                self.branch(n, t);
+            }
+            Exit::Branch {
+                ic,
+                on_zero,
+                target,
+                next,
+            } => {
+                self.comment_labels(ic);
+                self.line(&format!(";; {}", self.pgm.instrs[ic]));
+                let d = self.depths[ic].unwrap();
+                self.line(&format!("local.get $s{}", d - 1));
+                self.line("f64.const 0");
+                self.line(if on_zero { "f64.eq" } else { "f64.ne" });
+                self.line("if");
+                self.indent += 1;
+                self.branch(n, target);
+                self.indent -= 1;
+                self.line("else");
+                self.indent += 1;
+                self.branch(n, next);
+                self.indent -= 1;
+                self.line("end");
+            }
+            Exit::Return => {
+                self.comment_labels(node.body_end);
+                self.line(";; HLT");
+                self.line("return");
+            }
+            Exit::Fail(code, ic) => {
+                self.comment_labels(ic);
+                self.fail(code, ic);
+            }
+        }
+    }
+
+    fn branch(&mut self, from: usize, to: usize) {
+        let start = self.cfg.nodes[to].start;
+        if self.cfg.is_backward(from, to) {
+            self.line(&format!("br $L{start}"));
+        } else if self.cfg.merge[to] {
+            self.line(&format!("br $B{start}"));
+        } else {
+            self.do_tree(to);
+        }
+    }
+
+    fn comment_labels(&mut self, ic: usize) {
+        let mut labels: Vec<&str> = self
+            .pgm
+            .labels
+            .iter()
+            .filter(|(_, addr)| !reserved(self.pgm, **addr) && self.pgm.ic.get(addr) == Some(&ic))
+            .map(|(label, _)| label.as_str())
+            .collect();
+        labels.sort();
+        for label in labels {
+            self.line(&format!(";; {label}"));
+        }
+    }
+
+    fn fail(&mut self, code: u32, ic: usize) {
+        self.line(&format!("i32.const {code}"));
+        self.line(&format!("i32.const {ic}"));
+        self.call("fail");
+        self.line("unreachable");
+    }
+
+    fn get(&mut self, slot: usize) {
+        self.line(&format!("local.get $s{slot}"));
+    }
+
+    fn set(&mut self, slot: usize) {
+        self.line(&format!("local.set $s{slot}"));
+    }
+
+    fn flag(&mut self, slot: usize) {
+        self.line("f64.convert_i32_u");
+        self.set(slot);
+    }
+
+    // |x - y| compared to EPS with `cmp`
+    fn near(&mut self, x: usize, y: usize, cmp: &str) {
+        self.get(x);
+        self.get(y);
+        self.line("f64.sub");
+        self.line("f64.abs");
+        self.line(&format!("f64.const {}", literal(EPS)));
+        self.line(cmp);
+    }
+
+    /// Emits instruction `ic`, with `a` the top of the stack and `b` the
+    /// value below, as the interpreter names them.
+    fn instr(&mut self, ic: usize) {
+        let d = self.depths[ic].unwrap();
+        let (a, b) = (d.wrapping_sub(1), d.wrapping_sub(2));
+        let checked = |gen: &mut Self, loc: u32| {
+            if reserved(gen.pgm, loc) {
+                gen.line(&format!("i32.const {}", loc * 8));
+                true
+            } else {
+                gen.fail(OUT_OF_RANGE, ic);
+                false
+            }
+        };
+        match &self.pgm.instrs[ic] {
+            MInstr::LDL(v) => {
+                self.line(&format!("f64.const {}", literal(*v)));
+                self.set(d);
+            }
+            MInstr::LD(_, loc) => {
+                if checked(self, *loc) {
+                    self.line("f64.load");
+                    self.set(d);
+                }
+            }
+            MInstr::ST(_, loc) => {
+                if checked(self, *loc) {
+                    self.get(a);
+                    self.line("f64.store");
+                }
+            }
+            MInstr::RD(_, loc) => {
+                if checked(self, *loc) {
+                    self.call("rd");
+                    self.line("f64.store");
+                }
+            }
+            MInstr::ADD | MInstr::MLT => {
+                self.get(a);
+                self.get(b);
+                self.line(match &self.pgm.instrs[ic] {
+                    MInstr::ADD => "f64.add",
+                    _ => "f64.mul",
+                });
+                self.set(b);
+            }
+            MInstr::SUB => {
+                self.get(b);
+                self.get(a);
+                self.line("f64.sub");
+                self.set(b);
+            }
+            MInstr::DIV => {
+                self.get(a);
+                self.line("f64.const 0");
+                self.line("f64.eq");
+                self.line("if");
+                self.indent += 1;
+                self.fail(DIVISION_BY_ZERO, ic);
+                self.indent -= 1;
+                self.line("end");
+                self.get(b);
+                self.get(a);
+                self.line("f64.div");
+                self.set(b);
+            }
+            MInstr::EQU => {
+                self.near(a, b, "f64.lt");
+                self.flag(b);
+            }
+            MInstr::NEQ => {
+                self.near(b, a, "f64.ge");
+                self.flag(b);
+            }
+            MInstr::LSS | MInstr::GTR | MInstr::LEQ | MInstr::GEQ => {
+                let (cmp, near, join) = match &self.pgm.instrs[ic] {
+                    MInstr::LSS => ("f64.lt", "f64.ge", "i32.and"),
+                    MInstr::GTR => ("f64.gt", "f64.ge", "i32.and"),
+                    MInstr::LEQ => ("f64.lt", "f64.lt", "i32.or"),
+                    _ => ("f64.gt", "f64.lt", "i32.or"),
+                };
+                self.get(b);
+                self.get(a);
+                self.line(cmp);
+                self.near(b, a, near);
+                self.line(join);
+                self.flag(b);
+            }
+            MInstr::AND | MInstr::OR => {
+                for slot in [b, a] {
+                    self.get(slot);
+                    self.line("f64.const 0");
+                    self.line("f64.ne");
+                }
+                self.line(if matches!(self.pgm.instrs[ic], MInstr::AND) {
+                    "i32.and"
+                } else {
+                    "i32.or"
+                });
+                self.flag(b);
+            }
+            MInstr::NEG => {
+                self.get(a);
+                self.line("f64.neg");
+                self.set(a);
+            }
+            MInstr::DUP => {
+                self.get(a);
+                self.set(d);
+            }
+            MInstr::NOT => {
+                self.get(a);
+                self.line("f64.const 0");
+                self.line("f64.eq");
+                self.flag(a);
+            }
+            MInstr::EDT(s) if !s.is_ascii() => self.fail(NON_ASCII, ic),
+            MInstr::EDT(s) => {
+                let offset = self.strings[s.as_str()];
+                self.get(a);
+                self.line(&format!("i32.const {offset}"));
+                self.line(&format!("i32.const {}", s.len()));
+                self.call("edt");
+            }
+            MInstr::EDN => {
+                for slot in d - 4..d {
+                    self.get(slot);
+                }
+                self.call("edn");
+            }
+            MInstr::PNT => self.call("pnt"),
+            MInstr::B(_, _)
+            | MInstr::BFP(_, _)
+            | MInstr::BTP(_, _)
+            | MInstr::HLT
+            | MInstr::Undef => unreachable!("branches end nodes"),
+        }
+    }
+}
+
+fn reserved(pgm: &MProgram<MInstr>, addr: u32) -> bool {
+    pgm.blocks.iter().any(|block| block.contains(&addr))
+}
+
+fn literal(v: f64) -> String {
+    if v.is_nan() {
+        "nan".to_string()
+    } else {
+        format!("{v:?}")
+    }
+}
+
+fn string(s: &str) -> String {
+    let mut lit = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b' '..=b'~' if b != b'"' && b != b'\\' => lit.push(b as char),
+            _ => write!(lit, "\\{b:02x}").unwrap(),
+        }
+    }
+    lit.push('"');
+    lit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::StackFault;
+    use mparse::Location;
+
+    fn compile(mpgm: &str, source: &str) -> String {
+        let p = mparse::parse::<meta::MInstr>(mpgm).expect("meta machine program");
+        let mut m = meta::M::new(source);
+        m.execute(&p);
+        m.generated().expect("compilation")
+    }
+
+    #[test]
+    fn fig3_wat() {
+        let p = mparse::parse::<MInstr>(include_str!("../fig3.va1m")).expect("program");
+        assert_eq!(to_wat(&p), Ok(include_str!("../fig3.wat").to_string()));
+    }
+
+    #[test]
+    fn nested_wat() {
+        let va1m = compile(
+            include_str!("../../meta_mach_pgms/va1.mm"),
+            include_str!("../nested.va1"),
+        );
+        let p = mparse::parse::<MInstr>(&va1m).expect("program");
+        assert_eq!(to_wat(&p), Ok(include_str!("../nested.wat").to_string()));
+    }
+
+    #[test]
+    fn rejected() {
+        let parse = |s: &str| mparse::parse::<MInstr>(s).expect("program");
+        let p = parse("        ADD\n        HLT\n");
+        assert!(matches!(
+            to_wat(&p),
+            Err(WatError::Stack(VerifyError {
+                fault: StackFault::Underflow,
+                at: Location { ic: 0, .. },
+            }))
+        ));
+        let p = parse(
+            "
+        LDL 1
+        BTP A
+        LDL 2
+A
+        HLT
+",
+        );
+        assert!(matches!(
+            to_wat(&p),
+            Err(WatError::Stack(VerifyError {
+                fault: StackFault::Mismatch(0, 1),
+                at: Location { ic: 3, .. },
+            }))
+        ));
+        // two loop entries, neither dominating the other
+        let p = parse(
+            "
+        LDL 1
+        BTP B
+A
+        B B
+B
+        B A
+",
+        );
+        assert!(matches!(to_wat(&p), Err(WatError::Irreducible(_))));
+    }
+
+    #[test]
+    fn non_ascii_text() {
+        let p = mparse::parse::<MInstr>("        LDL 0\n        EDT 'é'\n        HLT\n")
+            .expect("program");
+        let wat = to_wat(&p).expect("translation");
+        assert!(wat.contains("i32.const 4\n    i32.const 1\n    call $fail\n"));
+        assert!(!wat.contains("(data"));
+    }
+
+    #[test]
+    fn string_literals() {
+        assert_eq!(string("a\"\\é"), r#""a\22\5c\c3\a9""#);
+    }
+}
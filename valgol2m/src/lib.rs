@@ -0,0 +1,680 @@
+use mparse::ParseableInstr;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+
+use mparse::valgol::{self, EPS, PRINT_AREA_SIZE};
+
+#[derive(Debug, PartialEq)]
+pub enum RuntimeError {
+    StackUnderflow,
+    FellOffProgram,
+    InvalidInstruction,
+    InvalidAddress(f64),
+    NegativeIndex(f64),
+    CallWithoutProcedure,
+    OutsideProcedure,
+    MissingArgument,
+    OutputFailed(String),
+    NonAsciiText(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            RuntimeError::StackUnderflow => "machine stack underflow",
+            RuntimeError::FellOffProgram => "execution past the end of the program",
+            RuntimeError::InvalidInstruction => "invalid instruction",
+            RuntimeError::InvalidAddress(a) => return write!(f, "invalid address {a}"),
+            RuntimeError::NegativeIndex(i) => return write!(f, "negative array index {i}"),
+            RuntimeError::CallWithoutProcedure => "CLS without PRC",
+            RuntimeError::OutsideProcedure => "ENT or RSR outside of procedure",
+            RuntimeError::MissingArgument => "missing procedure argument",
+            RuntimeError::OutputFailed(e) => return write!(f, "output failed: {e}"),
+            RuntimeError::NonAsciiText(s) => return write!(f, "non-ASCII text {s}"),
+        };
+        f.write_str(msg)
+    }
+}
+
+impl Error for RuntimeError {}
+
+pub type ExecError = valgol::ExecError<RuntimeError>;
+
+#[derive(Debug)]
+struct Frame {
+    ret: usize,
+    base: usize,
+    /// Cells of the procedure and their values before the call.
+    saved: Vec<(u32, f64)>,
+}
+
+#[derive(Debug)]
+pub struct M<W: Write> {
+    mem: HashMap<u32, f64>,
+    stack: Vec<f64>,
+    // procedure targets and stack heights between PRC and CLS
+    pending: Vec<(usize, usize)>,
+    frames: Vec<Frame>,
+    print_area: String,
+    out: W,
+}
+
+impl<W: Write> M<W> {
+    pub fn new(out: W) -> Self {
+        M {
+            mem: HashMap::new(),
+            stack: Vec::new(),
+            pending: Vec::new(),
+            frames: Vec::new(),
+            print_area: String::with_capacity(PRINT_AREA_SIZE),
+            out,
+        }
+    }
+
+    pub fn into_output(self) -> W {
+        self.out
+    }
+
+    fn push(&mut self, u: f64) {
+        self.stack.push(u);
+    }
+
+    fn pop(&mut self) -> Result<f64, RuntimeError> {
+        self.stack.pop().ok_or(RuntimeError::StackUnderflow)
+    }
+
+    fn pop_addr(&mut self) -> Result<u32, RuntimeError> {
+        let a = self.pop()?;
+        if a < 0.0 || a.fract() != 0.0 || a > u32::MAX as f64 {
+            return Err(RuntimeError::InvalidAddress(a));
+        }
+        Ok(a as u32)
+    }
+
+    // the address and step of the innermost for loop
+    fn for_loop(&self) -> Result<(u32, f64), RuntimeError> {
+        let n = self.stack.len();
+        if n < 2 {
+            return Err(RuntimeError::StackUnderflow);
+        }
+        let a = self.stack[n - 2];
+        if a < 0.0 || a.fract() != 0.0 || a > u32::MAX as f64 {
+            return Err(RuntimeError::InvalidAddress(a));
+        }
+        Ok((a as u32, self.stack[n - 1]))
+    }
+
+    fn load(&self, loc: u32) -> f64 {
+        *self.mem.get(&loc).unwrap_or(&0.0)
+    }
+
+    fn lod(&mut self) -> Result<(), RuntimeError> {
+        let loc = self.pop_addr()?;
+        self.push(self.load(loc));
+        Ok(())
+    }
+
+    fn aia(&mut self) -> Result<(), RuntimeError> {
+        let i = self.pop()?.round();
+        let a = self.pop()?;
+        if i < 0.0 {
+            return Err(RuntimeError::NegativeIndex(i));
+        }
+        self.push(a + i);
+        Ok(())
+    }
+
+    fn st(&mut self) -> Result<(), RuntimeError> {
+        let v = self.pop()?;
+        let loc = self.pop_addr()?;
+        self.mem.insert(loc, v);
+        Ok(())
+    }
+
+    fn sta(&mut self) -> Result<(), RuntimeError> {
+        let v = self.pop()?;
+        let loc = self.pop_addr()?;
+        self.mem.insert(loc, v);
+        self.push(loc as f64);
+        Ok(())
+    }
+
+    fn binop(&mut self, f: fn(f64, f64) -> f64) -> Result<(), RuntimeError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        self.push(f(a, b));
+        Ok(())
+    }
+
+    fn neg(&mut self) -> Result<(), RuntimeError> {
+        let a = self.pop()?;
+        self.push(-a);
+        Ok(())
+    }
+
+    fn fts(&mut self) -> Result<(), RuntimeError> {
+        let limit = self.pop()?;
+        let (a, step) = self.for_loop()?;
+        let v = self.load(a);
+        let within = if step < 0.0 { v >= limit } else { v <= limit };
+        self.push(if within { 1.0 } else { 0.0 });
+        Ok(())
+    }
+
+    fn fin(&mut self) -> Result<(), RuntimeError> {
+        let (a, step) = self.for_loop()?;
+        let v = self.load(a);
+        self.mem.insert(a, v + step);
+        Ok(())
+    }
+
+    fn prc(&mut self, pic: usize) {
+        self.pending.push((pic, self.stack.len()));
+    }
+
+    /// Calls the procedure of the last `PRC`, saving the cells it declares
+    /// in `memory` so recursive calls do not overwrite the caller's.
+    fn cls(&mut self, ret: usize, memory: &[Range<u32>]) -> Result<usize, RuntimeError> {
+        let (pic, base) = self
+            .pending
+            .pop()
+            .ok_or(RuntimeError::CallWithoutProcedure)?;
+        let saved = memory
+            .iter()
+            .flat_map(|r| r.clone())
+            .map(|loc| (loc, self.load(loc)))
+            .collect();
+        self.frames.push(Frame { ret, base, saved });
+        Ok(pic)
+    }
+
+    fn ent(&mut self, loc: u32) -> Result<(), RuntimeError> {
+        let old = self.load(loc);
+        let frame = self
+            .frames
+            .last_mut()
+            .ok_or(RuntimeError::OutsideProcedure)?;
+        if frame.base >= self.stack.len() {
+            return Err(RuntimeError::MissingArgument);
+        }
+        let v = self.stack.remove(frame.base);
+        frame.saved.push((loc, old));
+        self.mem.insert(loc, v);
+        Ok(())
+    }
+
+    fn rsr(&mut self) -> Result<usize, RuntimeError> {
+        let v = self.pop()?;
+        let frame = self.frames.pop().ok_or(RuntimeError::OutsideProcedure)?;
+        for (loc, old) in frame.saved.into_iter().rev() {
+            self.mem.insert(loc, old);
+        }
+        self.stack.truncate(frame.base);
+        self.push(v);
+        Ok(frame.ret)
+    }
+
+    fn edt(&mut self, s: &str) -> Result<(), RuntimeError> {
+        let n = self.pop()?.round();
+        // the print area is counted in bytes
+        if !s.is_ascii() {
+            return Err(RuntimeError::NonAsciiText(s.to_string()));
+        }
+        valgol::place(&mut self.print_area, n, s);
+        Ok(())
+    }
+
+    fn pnt(&mut self) -> Result<(), RuntimeError> {
+        writeln!(self.out, "{}", self.print_area.trim_end())
+            .map_err(|e| RuntimeError::OutputFailed(e.to_string()))?;
+        self.print_area.truncate(0);
+        Ok(())
+    }
+
+    pub fn execute(&mut self, pgm: &mparse::MProgram<MInstr>) -> Result<(), ExecError> {
+        let memory = procedure_memory(pgm);
+        let mut ic: usize = 0;
+        loop {
+            match self.step(pgm, ic, &memory) {
+                Ok(Some(next)) => ic = next,
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(ExecError::new(e, ic, pgm)),
+            }
+        }
+    }
+
+    /// Executes the instruction at `ic`, returning the next ic or `None`
+    /// when the machine halts.
+    fn step(
+        &mut self,
+        pgm: &mparse::MProgram<MInstr>,
+        ic: usize,
+        memory: &HashMap<usize, Vec<Range<u32>>>,
+    ) -> Result<Option<usize>, RuntimeError> {
+        match pgm.instrs.get(ic).ok_or(RuntimeError::FellOffProgram)? {
+            MInstr::Undef => return Err(RuntimeError::InvalidInstruction),
+            MInstr::LDL(v) => self.push(*v),
+            MInstr::LD(_, loc) => self.push(*loc as f64),
+            MInstr::LOD => self.lod()?,
+            MInstr::AIA => self.aia()?,
+            MInstr::ST => self.st()?,
+            MInstr::STA => self.sta()?,
+            MInstr::POP => {
+                self.pop()?;
+            }
+            MInstr::B(_, jic) => return Ok(Some(*jic)),
+            MInstr::BFP(_, jic) => {
+                if self.pop()? == 0.0 {
+                    return Ok(Some(*jic));
+                }
+            }
+            MInstr::BTP(_, jic) => {
+                if self.pop()? != 0.0 {
+                    return Ok(Some(*jic));
+                }
+            }
+            MInstr::FTS => self.fts()?,
+            MInstr::FIN => self.fin()?,
+            MInstr::PRC(_, pic) => self.prc(*pic),
+            MInstr::CLS => {
+                let pic = self.pending.last().map_or(0, |(pic, _)| *pic);
+                let memory = memory.get(&pic).map_or(&[][..], Vec::as_slice);
+                return self.cls(ic + 1, memory).map(Some);
+            }
+            MInstr::ENT(_, loc) => self.ent(*loc)?,
+            MInstr::RSR => return self.rsr().map(Some),
+            MInstr::ADD => self.binop(|a, b| a + b)?,
+            MInstr::SUB => self.binop(|a, b| a - b)?,
+            MInstr::MLT => self.binop(|a, b| a * b)?,
+            MInstr::DIV => self.binop(|a, b| a / b)?,
+            MInstr::NEG => self.neg()?,
+            MInstr::EQU => self.binop(|a, b| ((a - b).abs() < EPS) as u8 as f64)?,
+            MInstr::LSS => self.binop(|a, b| (a < b) as u8 as f64)?,
+            MInstr::GTR => self.binop(|a, b| (a > b) as u8 as f64)?,
+            MInstr::HLT => return Ok(None),
+            MInstr::EDT(s) => self.edt(s)?,
+            MInstr::PNT => self.pnt()?,
+        }
+        Ok(Some(ic + 1))
+    }
+}
+
+/// The cells each procedure declares, by entry ic: the blocks between
+/// its label and the end of the procedure the `B` before it jumps to.
+fn procedure_memory(pgm: &mparse::MProgram<MInstr>) -> HashMap<usize, Vec<Range<u32>>> {
+    let mut memory = HashMap::new();
+    for instr in pgm.instrs.iter() {
+        let MInstr::PRC(name, pic) = instr else {
+            continue;
+        };
+        let Some(MInstr::B(end, _)) = pic.checked_sub(1).and_then(|i| pgm.instrs.get(i)) else {
+            continue;
+        };
+        let (start, end) = (pgm.labels[name], pgm.labels[end]);
+        let blocks = pgm
+            .blocks
+            .iter()
+            .filter(|b| start <= b.start && b.end <= end)
+            .cloned()
+            .collect();
+        memory.insert(*pic, blocks);
+    }
+    memory
+}
+
+#[derive(Debug)]
+pub enum MInstr {
+    // branch
+    B(String, usize),
+    BFP(String, usize),
+    BTP(String, usize),
+    // procedures
+    PRC(String, usize),
+    CLS,
+    ENT(String, u32),
+    RSR,
+    // constant
+    LDL(f64),
+    // memory, addresses travel on the stack
+    LD(String, u32),
+    LOD,
+    AIA,
+    ST,
+    STA,
+    POP,
+    // for loops, with address and step on the stack
+    FTS,
+    FIN,
+    // operations
+    EQU,
+    LSS,
+    GTR,
+    ADD,
+    SUB,
+    MLT,
+    DIV,
+    NEG,
+    EDT(String),
+    PNT,
+    HLT,
+    Undef,
+}
+
+impl ParseableInstr for MInstr {
+    const UNDEF: Self = MInstr::Undef;
+    const ACCEPT_BLK: bool = true;
+    const ACCEPT_END_LABEL: bool = false;
+
+    fn is_undefined(&self) -> bool {
+        matches!(self, MInstr::Undef)
+    }
+
+    fn with_label(ins: &str, label: String) -> Self {
+        match ins {
+            "B" => MInstr::B(label, 0),
+            "BTP" => MInstr::BTP(label, 0),
+            "BFP" => MInstr::BFP(label, 0),
+            "PRC" => MInstr::PRC(label, 0),
+            "ENT" => MInstr::ENT(label, 0),
+            "LD" => MInstr::LD(label, 0),
+            _ => MInstr::Undef,
+        }
+    }
+
+    fn with_num(ins: &str, n: f64) -> Self {
+        match ins {
+            "LDL" => MInstr::LDL(n),
+            _ => MInstr::Undef,
+        }
+    }
+
+    fn with_string(ins: &str, s: String) -> Self {
+        match ins {
+            "EDT" => MInstr::EDT(s),
+            _ => MInstr::Undef,
+        }
+    }
+
+    fn with_noarg(ins: &str) -> Self {
+        match ins {
+            "CLS" => MInstr::CLS,
+            "RSR" => MInstr::RSR,
+            "LOD" => MInstr::LOD,
+            "AIA" => MInstr::AIA,
+            "ST" => MInstr::ST,
+            "STA" => MInstr::STA,
+            "POP" => MInstr::POP,
+            "FTS" => MInstr::FTS,
+            "FIN" => MInstr::FIN,
+            "EQU" => MInstr::EQU,
+            "LSS" => MInstr::LSS,
+            "GTR" => MInstr::GTR,
+            "ADD" => MInstr::ADD,
+            "SUB" => MInstr::SUB,
+            "MLT" => MInstr::MLT,
+            "DIV" => MInstr::DIV,
+            "NEG" => MInstr::NEG,
+            "PNT" => MInstr::PNT,
+            "HLT" => MInstr::HLT,
+            _ => MInstr::Undef,
+        }
+    }
+
+    fn aaa_of(&self) -> mparse::AAAUse {
+        match self {
+            MInstr::ENT(aaa, _) | MInstr::LD(aaa, _) => mparse::AAAUse::Mem(aaa.to_string()),
+            MInstr::B(aaa, _) | MInstr::BFP(aaa, _) | MInstr::BTP(aaa, _) | MInstr::PRC(aaa, _) => {
+                mparse::AAAUse::IC(aaa.to_string())
+            }
+            _ => mparse::AAAUse::None,
+        }
+    }
+
+    fn reconstruct_with_addr(&mut self, aaa: String, addr: u32) {
+        *self = match self {
+            MInstr::ENT(_, _) => MInstr::ENT(aaa, addr),
+            MInstr::LD(_, _) => MInstr::LD(aaa, addr),
+            _ => panic!("internal error: unknown aaa instruction"),
+        }
+    }
+
+    fn reconstruct_with_ic(&mut self, aaa: String, ic: usize) {
+        *self = match self {
+            MInstr::B(_, _) => MInstr::B(aaa, ic),
+            MInstr::BFP(_, _) => MInstr::BFP(aaa, ic),
+            MInstr::BTP(_, _) => MInstr::BTP(aaa, ic),
+            MInstr::PRC(_, _) => MInstr::PRC(aaa, ic),
+            _ => panic!("internal error: unknown aaa instruction"),
+        };
+    }
+}
+
+pub fn run(opts: Options) -> Result<(), Box<dyn Error>> {
+    let p = mparse::load::<MInstr>(&opts.pgm_path)?;
+    let mut m = M::new(io::stdout());
+    m.execute(&p)?;
+    Ok(())
+}
+
+pub struct Options {
+    pub pgm_path: String,
+}
+
+impl Options {
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Self, &'static str> {
+        args.next();
+        let pgm_path = match args.next() {
+            Some(arg) => arg,
+            None => return Err("missing program path argument"),
+        };
+        Ok(Options { pgm_path })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(mpgm: &str, source: &str) -> String {
+        let p = mparse::parse::<meta::MInstr>(mpgm).expect("meta machine program");
+        let mut m = meta::M::new(source);
+        m.execute(&p);
+        let mut out = m.generated().expect("compilation");
+        out.push('\n');
+        out
+    }
+
+    fn exec(pgm: &str) -> String {
+        let p = mparse::parse::<MInstr>(pgm).expect("program");
+        let mut m = M::new(Vec::new());
+        m.execute(&p).expect("output");
+        String::from_utf8(m.into_output()).expect("utf8 output")
+    }
+
+    #[test]
+    fn m_ld_lod_st() -> Result<(), RuntimeError> {
+        let mut m = M::new(Vec::new());
+        m.push(3.0);
+        m.push(7.0);
+        m.st()?;
+        m.push(3.0);
+        m.lod()?;
+        assert_eq!(m.pop()?, 7.0);
+        m.push(4.0);
+        m.lod()?;
+        assert_eq!(m.pop()?, 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn m_aia_sta() -> Result<(), RuntimeError> {
+        let mut m = M::new(Vec::new());
+        m.push(10.0);
+        m.push(2.0);
+        m.aia()?;
+        m.push(5.0);
+        m.sta()?;
+        assert_eq!(m.stack, vec![12.0]);
+        assert_eq!(m.load(12), 5.0);
+        Ok(())
+    }
+
+    #[test]
+    fn m_sub_div_order() -> Result<(), RuntimeError> {
+        let mut m = M::new(Vec::new());
+        m.push(8.0);
+        m.push(2.0);
+        m.binop(|a, b| a / b)?;
+        m.push(1.0);
+        m.binop(|a, b| a - b)?;
+        assert_eq!(m.pop()?, 3.0);
+        Ok(())
+    }
+
+    #[test]
+    fn m_for_test_and_increment() -> Result<(), RuntimeError> {
+        let mut m = M::new(Vec::new());
+        m.mem.insert(0, 1.0);
+        m.push(0.0);
+        m.push(-1.0);
+        m.push(0.0);
+        m.fts()?;
+        assert_eq!(m.pop()?, 1.0);
+        m.fin()?;
+        m.fin()?;
+        m.push(0.0);
+        m.fts()?;
+        assert_eq!(m.pop()?, 0.0);
+        assert_eq!(m.load(0), -1.0);
+        assert_eq!(m.stack, vec![0.0, -1.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn m_call_frames() -> Result<(), RuntimeError> {
+        let mut m = M::new(Vec::new());
+        m.mem.insert(5, 1.0);
+        m.mem.insert(8, 4.0);
+        m.push(9.0);
+        m.prc(40);
+        m.push(2.0);
+        m.push(3.0);
+        assert_eq!(m.cls(7, &[8..9, 9..10])?, 40);
+        m.ent(5)?;
+        assert_eq!(m.load(5), 2.0);
+        m.ent(6)?;
+        assert_eq!(m.load(6), 3.0);
+        m.mem.insert(8, 0.0);
+        m.push(1.0);
+        m.push(42.0);
+        assert_eq!(m.rsr()?, 7);
+        assert_eq!(m.stack, vec![9.0, 42.0]);
+        assert_eq!(m.load(5), 1.0);
+        assert_eq!(m.load(6), 0.0);
+        assert_eq!(m.load(8), 4.0);
+        Ok(())
+    }
+
+    #[test]
+    fn execute_errors() {
+        let run = |pgm: &str| {
+            let p = mparse::parse::<MInstr>(pgm).expect("program");
+            M::new(Vec::new()).execute(&p).expect_err("error")
+        };
+        let e = run("E\n        LDL 1\n");
+        assert_eq!(e.error, RuntimeError::FellOffProgram);
+        assert_eq!(
+            e.to_string(),
+            "execution past the end of the program at ic 1 (E+1)"
+        );
+        for (pgm, error) in [
+            ("        ADD\n", RuntimeError::StackUnderflow),
+            ("        LDL 1\n        FIN\n", RuntimeError::StackUnderflow),
+            (
+                "        LDL 1\n        LDL 1\n        FTS\n",
+                RuntimeError::StackUnderflow,
+            ),
+            (
+                "        LDL -1\n        LOD\n",
+                RuntimeError::InvalidAddress(-1.0),
+            ),
+            (
+                "        LDL 1.5\n        LDL 0\n        ST\n",
+                RuntimeError::InvalidAddress(1.5),
+            ),
+            (
+                "        LDL 0\n        LDL -2\n        AIA\n",
+                RuntimeError::NegativeIndex(-2.0),
+            ),
+            ("        CLS\n", RuntimeError::CallWithoutProcedure),
+            (
+                "        LDL 0\n        RSR\n",
+                RuntimeError::OutsideProcedure,
+            ),
+            (
+                "X\n        BLK 1\n        ENT X\n",
+                RuntimeError::OutsideProcedure,
+            ),
+            (
+                "        PRC P\n        CLS\nP\n        ENT X\nX\n        BLK 1\n",
+                RuntimeError::MissingArgument,
+            ),
+            (
+                "        LDL 0\n        EDT 'é'\n",
+                RuntimeError::NonAsciiText("é".to_string()),
+            ),
+        ] {
+            assert_eq!(run(pgm).error, error, "{pgm}");
+        }
+        let mut undef = mparse::parse::<MInstr>("        HLT\n").expect("program");
+        undef.instrs[0] = MInstr::Undef;
+        let e = M::new(Vec::new()).execute(&undef).expect_err("error");
+        assert_eq!(e.error, RuntimeError::InvalidInstruction);
+        assert_eq!(e.to_string(), "invalid instruction at ic 0");
+    }
+
+    #[test]
+    fn recursion_with_locals() {
+        let va2m = compile(
+            include_str!("../../meta_mach_pgms/va2.mm"),
+            include_str!("../locals.va2"),
+        );
+        assert_eq!(va2m, include_str!("../locals.va2m"));
+        assert_eq!(exec(&va2m), include_str!("../locals.out"));
+    }
+
+    #[test]
+    fn va2_grammar_compiles() {
+        assert_eq!(
+            compile(
+                include_str!("../../meta_mach_pgms/meta.mm"),
+                include_str!("../../va2.syn")
+            ),
+            include_str!("../../meta_mach_pgms/va2.mm")
+        );
+    }
+
+    #[test]
+    fn primes() {
+        let va2m = compile(
+            include_str!("../../meta_mach_pgms/va2.mm"),
+            include_str!("../primes.va2"),
+        );
+        assert_eq!(va2m, include_str!("../primes.va2m"));
+        assert_eq!(exec(&va2m), include_str!("../primes.out"));
+    }
+
+    #[test]
+    fn fib() {
+        let va2m = compile(
+            include_str!("../../meta_mach_pgms/va2.mm"),
+            include_str!("../fib.va2"),
+        );
+        assert_eq!(va2m, include_str!("../fib.va2m"));
+        assert_eq!(exec(&va2m), include_str!("../fib.out"));
+    }
+}
@@ -0,0 +1,17 @@
+use std::env;
+use std::process;
+
+use valgol2m::Options;
+
+fn handle_err(e: &str) -> ! {
+    eprintln!("error: {e}");
+    process::exit(1)
+}
+
+fn main() {
+    let opts = Options::build(env::args()).unwrap_or_else(|err| handle_err(err));
+
+    if let Err(e) = valgol2m::run(opts) {
+        handle_err(&e.to_string())
+    }
+}